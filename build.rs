@@ -0,0 +1,28 @@
+// Embeds the git commit the binary is built from and the target triple, reported by
+// cmzip --capabilities. Builds outside a git checkout, e.g. from a source tarball, take the commit
+// from CMZIP_GIT_COMMIT or report it as unknown.
+
+use std::{env, fs, path::Path, process::Command};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=CMZIP_GIT_COMMIT");
+    let commit = env::var("CMZIP_GIT_COMMIT").ok().or_else(git_commit).unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=CMZIP_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=CMZIP_TARGET={}", env::var("TARGET").unwrap());
+}
+
+fn git_commit() -> Option<String> {
+    let head = Path::new(".git/HEAD");
+    if !head.exists() {
+        return None;
+    }
+    // Rebuild when HEAD moves to another branch or the branch to another commit
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Some(reference) = fs::read_to_string(head).ok()?.trim().strip_prefix("ref: ") {
+        if Path::new(".git").join(reference).exists() {
+            println!("cargo:rerun-if-changed=.git/{}", reference);
+        }
+    }
+    let output = Command::new("git").args(["rev-parse", "HEAD"]).output().ok().filter(|output| output.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,187 @@
+// Python bindings for CmZ archives, built with maturin as the cmzip_py module.
+//
+//     from cmzip_py import CmzReader, CmzWriter
+//
+//     reader = CmzReader("results.cmz")
+//     print(len(reader), reader[0])
+//     for record in reader:
+//         ...
+//
+//     with CmzWriter("subset.cmz", level=9) as writer:
+//         writer.add(reader[42])
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::PathBuf
+};
+use pyo3::{
+    prelude::*,
+    exceptions::{PyIOError, PyIndexError, PyTypeError, PyValueError},
+    types::{PyBytes, PyString}
+};
+use cmzip::{Archive, Writer};
+
+fn io_error(e: std::io::Error) -> PyErr {
+    PyIOError::new_err(e.to_string())
+}
+
+fn decode(record: Vec<u8>) -> PyResult<String> {
+    String::from_utf8(record).map_err(|_| PyValueError::new_err("Record is not valid UTF-8, use read_bytes() instead"))
+}
+
+// Read access to an archive; supports len(), indexing (negative indices count from the end) and iteration.
+// One reader can be shared by many Python threads: it is immutable, records are read with
+// positional reads and decompressed with the GIL released, so threads extract in parallel.
+#[pyclass(frozen)]
+struct CmzReader {
+    archive: Archive,
+}
+
+impl CmzReader {
+    fn position(&self, i: isize) -> PyResult<usize> {
+        let len = self.archive.len() as isize;
+        let pos = if i < 0 { i + len } else { i };
+        if pos < 0 || pos >= len {
+            return Err(PyIndexError::new_err("record index out of range"));
+        }
+        Ok(pos as usize)
+    }
+}
+
+#[pymethods]
+impl CmzReader {
+    #[new]
+    fn new(path: PathBuf) -> PyResult<Self> {
+        Ok(CmzReader { archive: Archive::open_cached(path).map_err(io_error)? })
+    }
+
+    fn __len__(&self) -> usize {
+        self.archive.len()
+    }
+
+    fn __getitem__(&self, py: Python<'_>, i: isize) -> PyResult<String> {
+        let pos = self.position(i)?;
+        decode(py.detach(|| self.archive.read_record(pos)).map_err(io_error)?)
+    }
+
+    // Returns record i as raw bytes, without UTF-8 decoding
+    fn read_bytes<'py>(&self, py: Python<'py>, i: isize) -> PyResult<Bound<'py, PyBytes>> {
+        let pos = self.position(i)?;
+        let record = py.detach(|| self.archive.read_record(pos)).map_err(io_error)?;
+        Ok(PyBytes::new(py, &record))
+    }
+
+    // Returns all records whose molecule name, read with the rule the archive stores, equals name
+    fn get_by_name(&self, py: Python<'_>, name: &str) -> PyResult<Vec<String>> {
+        let records = py.detach(|| -> std::io::Result<Vec<Vec<u8>>> {
+            self.archive.find(name)?.into_iter().map(|i| self.archive.read_record(i)).collect()
+        }).map_err(io_error)?;
+        records.into_iter().map(decode).collect()
+    }
+
+    fn __iter__(slf: Py<Self>) -> RecordIter {
+        RecordIter { reader: slf, next: 0, as_bytes: false }
+    }
+
+    // Iterates over records as raw bytes
+    fn iter_bytes(slf: Py<Self>) -> RecordIter {
+        RecordIter { reader: slf, next: 0, as_bytes: true }
+    }
+}
+
+#[pyclass]
+struct RecordIter {
+    reader: Py<CmzReader>,
+    next: usize,
+    as_bytes: bool,
+}
+
+#[pymethods]
+impl RecordIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<Py<PyAny>>> {
+        let reader = self.reader.get();
+        if self.next >= reader.archive.len() {
+            return Ok(None);
+        }
+        let next = self.next;
+        let record = py.detach(|| reader.archive.read_record(next)).map_err(io_error)?;
+        self.next += 1;
+        if self.as_bytes {
+            Ok(Some(PyBytes::new(py, &record).into_any().unbind()))
+        } else {
+            Ok(Some(PyString::new(py, &decode(record)?).into_any().unbind()))
+        }
+    }
+}
+
+// Write access to a new archive. Records are complete SD records including the $$$$ line.
+// The footer is written by close(), or when leaving a with block.
+#[pyclass]
+struct CmzWriter {
+    writer: Option<Writer<BufWriter<File>>>,
+}
+
+impl CmzWriter {
+    fn writer(&mut self) -> PyResult<&mut Writer<BufWriter<File>>> {
+        self.writer.as_mut().ok_or_else(|| PyValueError::new_err("Writer is closed"))
+    }
+}
+
+#[pymethods]
+impl CmzWriter {
+    #[new]
+    #[pyo3(signature = (path, level = 6))]
+    fn new(path: PathBuf, level: u32) -> PyResult<Self> {
+        if level > 9 {
+            return Err(PyValueError::new_err("Specified level is invalid!"));
+        }
+        let file = File::create(path).map_err(io_error)?;
+        Ok(CmzWriter { writer: Some(Writer::new(BufWriter::new(file), level)) })
+    }
+
+    // Compresses and appends a record given as str or bytes
+    fn add(&mut self, record: &Bound<'_, PyAny>) -> PyResult<()> {
+        let writer = self.writer()?;
+        if let Ok(text) = record.cast::<PyString>() {
+            writer.add_record(text.to_str()?.as_bytes()).map_err(io_error)
+        } else if let Ok(bytes) = record.cast::<PyBytes>() {
+            writer.add_record(bytes.as_bytes()).map_err(io_error)
+        } else {
+            Err(PyTypeError::new_err("Record must be str or bytes"))
+        }
+    }
+
+    fn __len__(&self) -> usize {
+        self.writer.as_ref().map_or(0, |writer| writer.len())
+    }
+
+    // Writes the file footer; further add() calls fail
+    fn close(&mut self) -> PyResult<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.finish().map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (_exc_type, _exc_value, _traceback))]
+    fn __exit__(&mut self, _exc_type: Option<&Bound<'_, PyAny>>, _exc_value: Option<&Bound<'_, PyAny>>, _traceback: Option<&Bound<'_, PyAny>>) -> PyResult<bool> {
+        self.close()?;
+        Ok(false)
+    }
+}
+
+#[pymodule]
+fn cmzip_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<CmzReader>()?;
+    m.add_class::<CmzWriter>()?;
+    Ok(())
+}
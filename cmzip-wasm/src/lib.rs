@@ -0,0 +1,309 @@
+// In-browser reader for CmZ archives. The whole archive is handed over as a Uint8Array, e.g. from
+// a File picked by the user, so records are listed and extracted without uploading any data.
+//
+// The cmzip library links liblzma and libzstd, which don't build for wasm32, so the footer is
+// parsed here and records are decoded with the pure Rust lzma-rs and ruzstd. Encrypted archives
+// and archives compressed with a delta filter (xz_options) are rejected on opening, neither crate
+// can read them.
+
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    io::Read
+};
+use sha2::{Digest, Sha256};
+use wasm_bindgen::prelude::*;
+
+const METADATA_MAGIC: [u8; 8] = *b"CMZ\0META";
+const ENCRYPTION_MAGIC: [u8; 8] = *b"CMZ\0ENC1";
+const MOL2_DELIMITER: &[u8] = b"@<TRIPOS>MOLECULE";
+const VARINT_INDEX_MAGIC: [u8; 8] = *b"CMZ\0IDX2";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    Xz,
+    Zstd,
+}
+
+#[wasm_bindgen]
+pub struct CmzArchive {
+    data: Vec<u8>,
+    offsets: Vec<usize>, // Start of every record, followed by the end of the last one
+    metadata: BTreeMap<String, Vec<u8>>,
+    codec: Codec,
+    dictionary: Option<Vec<u8>>, // Raw zstd dictionary, embedded or supplied by set_dictionary()
+}
+
+#[wasm_bindgen]
+impl CmzArchive {
+    // Reads the index and metadata from the footer of a complete archive
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: Vec<u8>) -> Result<CmzArchive, JsError> {
+        CmzArchive::parse(data).map_err(|e| JsError::new(&e))
+    }
+
+    // Number of records stored in the archive
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn codec(&self) -> String {
+        match self.codec {
+            Codec::Xz => "xz".to_string(),
+            Codec::Zstd => "zstd".to_string(),
+        }
+    }
+
+    // Metadata value stored under key, if it is valid UTF-8 text
+    pub fn metadata(&self, key: &str) -> Option<String> {
+        self.metadata.get(key).and_then(|value| String::from_utf8(value.clone()).ok())
+    }
+
+    // Keys of all metadata entries
+    #[wasm_bindgen(js_name = metadataKeys)]
+    pub fn metadata_keys(&self) -> Vec<String> {
+        self.metadata.keys().cloned().collect()
+    }
+
+    // Supplies the dictionary the archive was compressed with, verified against the stored digest
+    #[wasm_bindgen(js_name = setDictionary)]
+    pub fn set_dictionary(&mut self, dictionary: Vec<u8>) -> Result<(), JsError> {
+        self.verify_dictionary(dictionary).map_err(|e| JsError::new(&e))
+    }
+
+    // Record contents as text
+    pub fn record(&self, i: usize) -> Result<String, JsError> {
+        self.read_record(i)
+            .map(|record| String::from_utf8_lossy(&record).into_owned())
+            .map_err(|e| JsError::new(&e))
+    }
+
+    // Record contents as raw bytes
+    #[wasm_bindgen(js_name = recordBytes)]
+    pub fn record_bytes(&self, i: usize) -> Result<Vec<u8>, JsError> {
+        self.read_record(i).map_err(|e| JsError::new(&e))
+    }
+
+    // Name of every record, in archive order, read with the rule the archive stores under
+    // "name_from" (the title line by default). Records without the tag get an empty name
+    pub fn names(&self) -> Result<Vec<String>, JsError> {
+        let tag = self.name_tag();
+        (0..self.length())
+            .map(|i| self.read_record(i).map(|record| name(&record, tag.as_deref()).unwrap_or_default()))
+            .collect::<Result<_, _>>()
+            .map_err(|e| JsError::new(&e))
+    }
+
+    // Indexes of all records whose name, read as in names(), is name
+    pub fn find(&self, name: &str) -> Result<Vec<usize>, JsError> {
+        let tag = self.name_tag();
+        let mut found = Vec::new();
+        for i in 0..self.length() {
+            let record = self.read_record(i).map_err(|e| JsError::new(&e))?;
+            if crate::name(&record, tag.as_deref()).as_deref() == Some(name) {
+                found.push(i);
+            }
+        }
+        Ok(found)
+    }
+}
+
+impl CmzArchive {
+    // Data item names are read from, None for the title line
+    fn name_tag(&self) -> Option<String> {
+        self.metadata("name_from")?.strip_prefix("tag:").map(str::to_string)
+    }
+
+    fn parse(data: Vec<u8>) -> Result<CmzArchive, String> {
+        let corrupt = || "Archive is corrupt!".to_string();
+
+        // Compressed index size is stored in the last 8 bytes, the index directly precedes it
+        if data.len() < 8 {
+            return Err(corrupt());
+        }
+        if data.starts_with(&ENCRYPTION_MAGIC) {
+            return Err("Archive is encrypted, which the browser reader doesn't support!".to_string());
+        }
+        let index_size = u64::from_le_bytes(data[data.len() - 8..].try_into().unwrap());
+        if index_size > (data.len() - 8) as u64 {
+            return Err(corrupt());
+        }
+        let index_start = data.len() - 8 - index_size as usize;
+        let mut index_decompressed: Vec<u8> = Vec::new();
+        lzma_rs::xz_decompress(&mut &data[index_start..data.len() - 8], &mut index_decompressed)
+            .map_err(|e| format!("Could not read index: {}!", e))?;
+        let sizes = decode_index(&index_decompressed).ok_or_else(corrupt)?;
+
+        // Index holds the header end followed by compressed record sizes, turn them into offsets
+        let mut offsets: Vec<usize> = Vec::new();
+        let mut offset: u64 = 0;
+        for size in sizes {
+            offset = offset.checked_add(size).ok_or_else(corrupt)?;
+            if offset > index_start as u64 {
+                return Err(corrupt());
+            }
+            offsets.push(offset as usize);
+        }
+
+        // Metadata precedes the index, archives without it are plain xz archives
+        let mut metadata = BTreeMap::new();
+        if index_start >= 16 && data[index_start - 8..index_start] == METADATA_MAGIC {
+            let metadata_size = u64::from_le_bytes(data[index_start - 16..index_start - 8].try_into().unwrap());
+            if metadata_size > (index_start - 16) as u64 {
+                return Err(corrupt());
+            }
+            let metadata_start = index_start - 16 - metadata_size as usize;
+            metadata = decode_metadata(&data[metadata_start..index_start - 16])?;
+        }
+
+        let codec = match metadata.get("codec").map(|name| &name[..]) {
+            None | Some(b"xz") => Codec::Xz,
+            Some(b"zstd") => Codec::Zstd,
+            Some(name) => return Err(format!("Unknown codec {}!", String::from_utf8_lossy(name))),
+        };
+        // lzma-rs only decodes LZMA2, the other xz options only change how it was encoded
+        let xz_options = metadata.get("xz_options").map(|options| String::from_utf8_lossy(options).into_owned());
+        if xz_options.iter().flat_map(|options| options.split(',')).any(|option| option.trim().starts_with("delta=")) {
+            return Err(format!("Archive was compressed with a delta filter (xz options {}), which the browser reader doesn't support!", xz_options.unwrap()));
+        }
+        let dictionary = metadata.get("dictionary").cloned();
+
+        Ok(CmzArchive { data, offsets, metadata, codec, dictionary })
+    }
+
+    fn verify_dictionary(&mut self, dictionary: Vec<u8>) -> Result<(), String> {
+        let expected = match self.metadata.get("dictionary_sha256") {
+            Some(digest) => String::from_utf8_lossy(digest).into_owned(),
+            None => return Err("Archive was not compressed with a dictionary!".to_string()),
+        };
+        let digest: String = Sha256::digest(&dictionary).iter().map(|b| format!("{:02x}", b)).collect();
+        if digest != expected {
+            return Err(format!("Dictionary does not match archive, expected dictionary {} with sha256 {}!",
+                self.metadata("dictionary_id").unwrap_or_else(|| "?".to_string()), expected));
+        }
+        self.dictionary = Some(dictionary);
+        Ok(())
+    }
+
+    fn read_record(&self, i: usize) -> Result<Vec<u8>, String> {
+        if i >= self.length() {
+            return Err(format!("Record {} does not exist!", i));
+        }
+        let input = &self.data[self.offsets[i]..self.offsets[i + 1]];
+        let mut record: Vec<u8> = Vec::new();
+        match self.codec {
+            Codec::Xz => {
+                lzma_rs::xz_decompress(&mut &input[..], &mut record).map_err(|e| format!("Could not read record {}: {}!", i, e))?;
+            },
+            Codec::Zstd => {
+                let mut decoder = ruzstd::decoding::FrameDecoder::new();
+                match (&self.dictionary, self.metadata.get("dictionary_sha256")) {
+                    (Some(dictionary), _) => {
+                        let dictionary = ruzstd::decoding::Dictionary::decode_dict(dictionary)
+                            .map_err(|e| format!("Could not load dictionary: {}!", e))?;
+                        decoder.add_dict(dictionary).map_err(|e| format!("Could not load dictionary: {}!", e))?;
+                    },
+                    (None, Some(digest)) => return Err(format!("Archive requires dictionary {} with sha256 {}!",
+                        self.metadata("dictionary_id").unwrap_or_else(|| "?".to_string()), String::from_utf8_lossy(digest))),
+                    (None, None) => {},
+                }
+                ruzstd::decoding::StreamingDecoder::new_with_decoder(input, decoder)
+                    .map_err(|e| format!("Could not read record {}: {}!", i, e))?
+                    .read_to_end(&mut record)
+                    .map_err(|e| format!("Could not read record {}: {}!", i, e))?;
+            },
+        }
+        Ok(record)
+    }
+}
+
+// Same layouts as cmzip::archive::decode_index(): little endian u64s, or LEB128 varints after VARINT_INDEX_MAGIC
+fn decode_index(data: &[u8]) -> Option<Vec<u64>> {
+    if let Some(data) = data.strip_prefix(&VARINT_INDEX_MAGIC) {
+        let mut index: Vec<u64> = Vec::new();
+        let (mut value, mut shift) = (0u64, 0u32);
+        for &byte in data {
+            // The 10th byte of a varint only holds the highest bit of a u64
+            if shift > 63 || (shift == 63 && byte > 1) {
+                return None;
+            }
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                index.push(value);
+                value = 0;
+                shift = 0;
+            } else {
+                shift += 7;
+            }
+        }
+        return (shift == 0 && !index.is_empty()).then_some(index);
+    }
+    if data.is_empty() || !data.len().is_multiple_of(8) {
+        return None;
+    }
+    Some(data.chunks(8).map(|value| u64::from_le_bytes(value.try_into().unwrap())).collect())
+}
+
+// Same encoding as cmzip::Metadata: u64 key length, key, u64 value length, value
+fn decode_metadata(mut data: &[u8]) -> Result<BTreeMap<String, Vec<u8>>, String> {
+    let mut metadata = BTreeMap::new();
+    while !data.is_empty() {
+        let key = String::from_utf8(take_field(&mut data)?.to_vec()).map_err(|_| "Metadata key is not valid UTF-8!".to_string())?;
+        let value = take_field(&mut data)?.to_vec();
+        metadata.insert(key, value);
+    }
+    Ok(metadata)
+}
+
+fn take_field<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], String> {
+    let corrupt = || "Metadata is corrupt!".to_string();
+    if data.len() < 8 {
+        return Err(corrupt());
+    }
+    let len = u64::from_le_bytes(data[..8].try_into().unwrap());
+    if len > (data.len() - 8) as u64 {
+        return Err(corrupt());
+    }
+    let (field, rest) = data[8..].split_at(len as usize);
+    *data = rest;
+    Ok(field)
+}
+
+// Same as cmzip::record::Record::name(): the title line, or the value of the data item tag, whose
+// lines run from its "> <TAG>" header to the next blank line
+fn name(record: &[u8], tag: Option<&str>) -> Option<String> {
+    let tag = match tag {
+        Some(tag) => tag,
+        None => return Some(title(record)).filter(|title| !title.trim().is_empty()),
+    };
+    let text = String::from_utf8_lossy(record);
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let header = match (line.find('<'), line.rfind('>')) {
+            (Some(start), Some(end)) if line.starts_with('>') && end > start => &line[start + 1..end],
+            _ => continue,
+        };
+        if header == tag {
+            let value: Vec<&str> = lines
+                .take_while(|line| !line.trim().is_empty() && !line.starts_with("$$$$"))
+                .map(str::trim_end)
+                .collect();
+            return Some(value.join("\n"));
+        }
+    }
+    None
+}
+
+// Same as cmzip::archive::title(): the first line of an SD record holds the molecule name, in mol2
+// records it follows the @<TRIPOS>MOLECULE line, which comments and blank lines may precede
+fn title(record: &[u8]) -> String {
+    let first = record.split(|&b| b == b'\n').next().unwrap_or_default();
+    let mut lines = record.split(|&b| b == b'\n').skip_while(|line| line.trim_ascii().is_empty() || line.starts_with(b"#"));
+    let line = match lines.next() {
+        Some(line) if line.trim_ascii() == MOL2_DELIMITER => lines.next().unwrap_or_default(),
+        _ => first,
+    };
+    String::from_utf8_lossy(line).trim_end().to_string()
+}
@@ -0,0 +1,40 @@
+/* C interface for reading CmZ archives. Build with: cargo build --release --features cdylib */
+#ifndef CMZIP_H
+#define CMZIP_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CMZ_OK 0
+#define CMZ_ERR_ARGUMENT -1 /* Null pointer passed */
+#define CMZ_ERR_RANGE -2    /* Record index out of range */
+#define CMZ_ERR_IO -3       /* Reading or decompressing failed */
+
+typedef struct cmz_archive cmz_archive;
+
+/* Opens an archive, returns NULL if the file can't be opened or has no valid footer */
+cmz_archive *cmz_open(const char *path);
+
+/* Number of records in the archive */
+size_t cmz_record_count(const cmz_archive *archive);
+
+/* Decompresses record i into a newly allocated, NUL-terminated buffer.
+ * len is set to the record size excluding the terminator.
+ * Release the buffer with cmz_free_record(). Safe to call from several threads
+ * on the same archive at once. */
+int cmz_read_record(cmz_archive *archive, size_t i, char **data, size_t *len);
+
+/* Releases a buffer returned by cmz_read_record() */
+void cmz_free_record(char *data, size_t len);
+
+/* Closes the archive */
+void cmz_close(cmz_archive *archive);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,934 @@
+// Read access to CmZ archives: footer parsing and extraction of individual records. Records are
+// read with positional reads that leave the file cursor alone, so one Archive can be shared
+// between threads (e.g. in an Arc) and extract different records at the same time.
+
+use std::{
+    fs::File,
+    io::{
+        self,
+        prelude::*
+    },
+    convert::TryInto,
+    ops::Range,
+    path::{Path, PathBuf}
+};
+use xz2::read::XzDecoder;
+use crate::{
+    codec::{self, Codec, Decoder},
+    backup,
+    crypto::{self, Block, Key},
+    dictionary::Dictionary,
+    health::{self, Health},
+    index_cache,
+    intent,
+    sidecar,
+    metadata::{self, Metadata},
+    record::{NameSource, Record},
+    retention::{self, Retention},
+    sdf,
+    transform::{self, RecordTransform}
+};
+
+pub struct Archive {
+    input: File,
+    file_size: u64,
+    index_size: u64,
+    index_start: u64,
+    footer_start: u64, // Start of the backup block or the metadata, or of the index in archives without either
+    index: Vec<u64>, // First element is the header end, followed by compressed record sizes
+    offsets: Vec<u64>, // Start of every record, computed once from the index, followed by the end of the last one
+    metadata: Metadata,
+    codec: Codec,
+    decoder: Option<Decoder>, // None until the dictionary the archive was compressed with is supplied
+    transforms: Vec<Box<dyn RecordTransform>>, // Applied to every decompressed record
+    key: Option<Key>, // Of encrypted archives, see crypto.rs
+    memlimit: Option<u64>, // Bytes of memory a record may take to decompress, see set_memlimit
+    max_record_size: Option<u64>, // Of decompressed records, see set_max_record_size
+}
+
+// Archives are shared between threads without locking, see the note at the top
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Archive>();
+};
+
+// Metadata key of the rule molecule names are read with, "title" or "tag:NAME"
+pub const NAME_SOURCE_KEY: &str = "name_from";
+
+impl Archive {
+    // Opens the archive at path, warning if an in-place write of it is pending (see intent.rs)
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Archive> {
+        intent::check(&path);
+        Archive::new(File::open(path)?)
+    }
+
+    // Same as open(), but takes the decoded index from the archive's index sidecar (see sidecar.rs)
+    // if it matches the footer, and otherwise keeps large decoded indexes in the index cache (see
+    // index_cache.rs), so opening the archive again skips decompressing the index
+    pub fn open_cached<P: AsRef<Path>>(path: P) -> io::Result<Archive> {
+        intent::check(&path);
+        let file = File::open(&path)?;
+        let sidecar = Some(sidecar::path(path.as_ref()));
+        if !index_cache::enabled() {
+            return Archive::read(file, None, sidecar);
+        }
+        let file_size = file.metadata()?.len();
+        let mut index_size_raw: [u8; 8] = [0; 8];
+        if file_size >= 8 {
+            read_exact_at(&file, &mut index_size_raw, file_size - 8)?;
+        }
+        let key = index_cache::key(path.as_ref(), &file, u64::from_le_bytes(index_size_raw));
+        Archive::read(file, key, sidecar)
+    }
+
+    // Reads the index from the file footer of an opened archive
+    pub fn new(input_file: File) -> io::Result<Archive> {
+        Archive::read(input_file, None, None)
+    }
+
+    fn read(input_file: File, cache_key: Option<String>, sidecar: Option<PathBuf>) -> io::Result<Archive> {
+        // The footer is read with positional reads as well, the file cursor is never moved
+        let input = input_file;
+        let file_size = input.metadata()?.len(); // Store file size for calculating offsets
+        // The decoded index of encrypted archives is never stored outside of them
+        let key = crypto::key_of(&input, file_size)?;
+        let (cache_key, sidecar) = match key {
+            Some(_) => (None, None),
+            None => (cache_key, sidecar),
+        };
+
+        // First step: get the compressed index size from last 8 bytes in file footer
+        let index_size = read_index_size(&input, file_size)?;
+        let index_start = file_size.checked_sub(index_size.saturating_add(8)).ok_or_else(not_an_archive)?;
+
+        // Second step: read metadata preceding the index, archives without it are plain xz archives
+        let (metadata, mut footer_start) = read_footer_metadata(&input, index_start, key.as_ref())?;
+
+        // The backup block holds copies of both, so its start follows from their sizes. Archives
+        // without metadata have no backup block either.
+        if footer_start < index_start {
+            let backup_size = backup::size(index_start - 16 - footer_start, index_size);
+            if footer_start >= backup_size {
+                let mut magic: [u8; 8] = [0; 8];
+                read_exact_at(&input, &mut magic, footer_start - backup_size)?;
+                if magic == backup::MAGIC {
+                    footer_start -= backup_size;
+                }
+            }
+        }
+
+        // Third and fourth step: read the compressed index from file footer and decompress it,
+        // unless a matching sidecar holds it or the decoded index is cached
+        // Records end in front of the footer, or elsewhere for index files holding only the footer
+        let index_only = footer_start == 0;
+        let fits = |index: &Vec<u64>| !index.is_empty() && index.iter().try_fold(0u64, |end, &size| end.checked_add(size)).is_some_and(|end| index_only || end <= index_start);
+        let mut index_compressed: Vec<u8> = Vec::new();
+        let mut index = None;
+        if let Some(path) = &sidecar {
+            if path.exists() {
+                index_compressed = read_index(&input, index_start, index_size)?;
+                index = sidecar::load(path, file_size, index_size, &index_compressed).filter(fits);
+            }
+        }
+        if index.is_none() {
+            index = cache_key.as_deref().and_then(index_cache::load).filter(fits);
+        }
+        let index = match index {
+            Some(index) => index,
+            None => {
+                if index_compressed.is_empty() {
+                    index_compressed = read_index(&input, index_start, index_size)?;
+                }
+                let index = decode_index(&unseal(key.as_ref(), index_compressed, Block::Index)?, max_index_entries(&metadata, file_size))?;
+                if !fits(&index) {
+                    return Err(not_an_archive()); // Records would end behind the footer
+                }
+                if let Some(key) = &cache_key {
+                    if index.len() > index_cache::MIN_RECORDS {
+                        let _ = index_cache::store(key, &index); // The cache is only an optimization
+                    }
+                }
+                index
+            },
+        };
+
+        check_record_count(&metadata, &index)?;
+
+        let (codec, decoder) = open_decoder(&metadata)?;
+
+        Ok(Archive {
+            input, file_size, index_size, index_start, footer_start, offsets: offsets(&index), index, metadata, codec, decoder,
+            transforms: Vec::new(), key, memlimit: None, max_record_size: None,
+        })
+    }
+
+    // Opens an archive from the backup block behind its last record instead of the file footer, for
+    // archives whose tail is damaged. See backup.rs for how the block is found.
+    pub fn open_backup<P: AsRef<Path>>(path: P) -> io::Result<Archive> {
+        let input = File::open(path)?;
+        let file_size = input.metadata()?.len();
+        let key = crypto::key_of(&input, file_size)?;
+        let backup = backup::find(&input, file_size, key.as_ref())?;
+        check_record_count(&backup.metadata, &backup.index)?;
+        let (codec, decoder) = open_decoder(&backup.metadata)?;
+        Ok(Archive {
+            input, file_size, index_size: backup.index_size, index_start: backup.index_start, footer_start: backup.start,
+            offsets: offsets(&backup.index), index: backup.index, metadata: backup.metadata, codec, decoder, transforms: Vec::new(), key, memlimit: None, max_record_size: None,
+        })
+    }
+
+    // Stores the decoded index in an index sidecar file at path, see sidecar::path() for where
+    // open_cached() looks for it
+    pub fn write_sidecar<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        if self.key.is_some() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "The index of encrypted archives isn't stored outside of them!"));
+        }
+        let mut index_compressed: Vec<u8> = vec![0u8; self.index_size as usize];
+        read_exact_at(&self.input, &mut index_compressed, self.index_start)?;
+        sidecar::store(path.as_ref(), self.file_size, self.index_size, &index_compressed, &self.index)
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    // Health summary written when the archive was finished, None for archives from before it was
+    // recorded
+    pub fn health(&self) -> Option<Health> {
+        self.metadata.get_str(health::KEY).and_then(Health::decode)
+    }
+
+    // Whether the health summary was written for the index the archive has now, None without one
+    pub fn health_matches(&self) -> Option<bool> {
+        self.health().map(|health| health.records == self.len() as u64 && health.matches(&self.index))
+    }
+
+    // Expiry of the records, see retention.rs. Malformed retention metadata is ignored, so no
+    // record expires by mistake.
+    pub fn retention(&self) -> Retention {
+        self.metadata.get_str(retention::KEY).and_then(Retention::decode).unwrap_or_default()
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    // Key records and footer are encrypted with, None for archives that aren't encrypted
+    pub fn key(&self) -> Option<&Key> {
+        self.key.as_ref()
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    // Reads only the metadata from the file footer, without decompressing the index
+    pub fn read_metadata<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
+        intent::check(&path);
+        let input = File::open(path)?;
+        let file_size = input.metadata()?.len();
+        let key = crypto::key_of(&input, file_size)?;
+        let index_start = file_size.checked_sub(read_index_size(&input, file_size)?.saturating_add(8)).ok_or_else(not_an_archive)?;
+        Ok(read_footer_metadata(&input, index_start, key.as_ref())?.0)
+    }
+
+    // Number of records in the archive at path. Archives store it in the footer, older ones
+    // have their index decompressed.
+    pub fn count<P: AsRef<Path>>(path: P) -> io::Result<usize> {
+        match Archive::read_metadata(&path)?.get_str("records").and_then(|records| records.parse::<usize>().ok()) {
+            Some(records) => Ok(records),
+            None => Ok(Archive::open_cached(path)?.len()),
+        }
+    }
+
+    // Dictionary stored in the archive, if it was embedded at compression
+    pub fn embedded_dictionary(&self) -> Option<Dictionary> {
+        self.metadata.get("dictionary").map(|data| Dictionary::new(data.to_vec()))
+    }
+
+    // Runs transform on every record decompressed from now on, after the transforms attached before
+    pub fn with_transform<T: RecordTransform + 'static>(mut self, transform: T) -> Archive {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    // Supplies the dictionary the archive was compressed with, verified against the stored digest
+    pub fn set_dictionary(&mut self, dictionary: &Dictionary) -> io::Result<()> {
+        let mut decoder = verified_decoder(&self.metadata, self.codec, dictionary)?;
+        decoder.set_memlimit(self.memlimit);
+        self.decoder = Some(decoder);
+        Ok(())
+    }
+
+    // Limits the memory decompressing one record may take to limit bytes, against zip bombs: the
+    // decoder memory (see Decoder::set_memlimit), the compressed record read and the decompressed
+    // record where it is held in memory. Records exceeding it fail with OutOfMemory.
+    pub fn set_memlimit(&mut self, limit: Option<u64>) {
+        self.memlimit = limit;
+        if let Some(decoder) = &mut self.decoder {
+            decoder.set_memlimit(limit);
+        }
+    }
+
+    // Fails records decompressing to more than size bytes with FileTooLarge as soon as they grow
+    // beyond it, so a crafted record can't expand into more output than anyone expects
+    pub fn set_max_record_size(&mut self, size: Option<u64>) {
+        self.max_record_size = size;
+    }
+
+    // Number of records stored in the archive. Last index elt points to the beginning of file footer.
+    pub fn len(&self) -> usize {
+        self.index.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    // Size of the compressed index in the file footer
+    pub fn index_size(&self) -> u64 {
+        self.index_size
+    }
+
+    // Fails if records can't be decompressed, because this build lacks the codec or the dictionary
+    // was not supplied yet
+    pub fn check_readable(&self) -> io::Result<()> {
+        if !self.codec.is_available() {
+            let e = self.codec.unavailable();
+            return Err(io::Error::new(e.kind(), format!("Archive uses {}: {}", self.codec, e)));
+        }
+        match self.decoder {
+            Some(_) => Ok(()),
+            None => Err(missing_dictionary(&self.metadata)),
+        }
+    }
+
+    // Byte range of compressed record i within the archive file
+    pub fn record_range(&self, i: usize) -> Option<std::ops::Range<u64>> {
+        if i >= self.len() {
+            return None;
+        }
+        Some(self.offsets[i]..self.offsets[i + 1])
+    }
+
+    // End of the last record, where the file footer starts
+    pub fn records_end(&self) -> u64 {
+        *self.offsets.last().unwrap()
+    }
+
+    // True for index files (.cmzi) holding only the footer of an archive stored elsewhere
+    pub fn is_index_only(&self) -> bool {
+        self.footer_start == 0 && self.records_end() > 0
+    }
+
+    pub(crate) fn index(&self) -> &[u64] {
+        &self.index
+    }
+
+    pub(crate) fn file(&self) -> &File {
+        &self.input
+    }
+
+    // Reads compressed record i without decompressing it, decrypted in encrypted archives, as
+    // Writer::add_compressed() takes it
+    pub fn read_raw(&self, i: usize) -> io::Result<Vec<u8>> {
+        let range = self.record_range(i)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Record {} does not exist!", i)))?;
+        let compressed = self.read_compressed(i, range)?;
+        match &self.key {
+            Some(key) => key.open(&compressed, Block::Record(i)),
+            None => Ok(compressed),
+        }
+    }
+
+    // Compressed record i, failing with UnexpectedEof if the record data is cut short by the footer
+    fn read_compressed(&self, i: usize, range: Range<u64>) -> io::Result<Vec<u8>> {
+        self.check_present(i, &range)?;
+        self.check_memlimit(&range)?;
+        let mut buf: Vec<u8> = vec![0u8; (range.end - range.start) as usize];
+        read_exact_at(&self.input, &mut buf, range.start)?;
+        Ok(buf)
+    }
+
+    // Fails for compressed records larger than the memory limit, before they are read into memory
+    fn check_memlimit(&self, range: &Range<u64>) -> io::Result<()> {
+        match self.memlimit {
+            Some(limit) if range.end - range.start > limit => Err(codec::memlimit_exceeded(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_present(&self, i: usize, range: &Range<u64>) -> io::Result<()> {
+        if range.end > self.footer_start {
+            let present = self.footer_start.saturating_sub(range.start);
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                format!("Record {} at byte {} is cut short, only {} of its {} bytes are present!", i, range.start, present, range.end - range.start)));
+        }
+        Ok(())
+    }
+
+    // Decompresses a record compressed with the codec and dictionary of this archive, as read_raw()
+    // returns it
+    pub fn decompress<W: Write>(&self, compressed: &[u8], output: &mut W) -> io::Result<u64> {
+        self.check_readable()?;
+        self.decoder.as_ref().unwrap().decompress(compressed, output)
+    }
+
+    // Decompresses record i directly into output, returns the number of bytes written
+    pub fn extract<W: Write>(&self, i: usize, output: &mut W) -> io::Result<u64> {
+        if i >= self.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Record {} does not exist!", i)));
+        }
+        self.check_readable()?;
+        let buf = self.read_compressed(i, self.offsets[i]..self.offsets[i + 1])?;
+        self.decode_record(i, &buf, output)
+    }
+
+    // Decrypts and decompresses record i as stored in the file, and runs the transforms on it
+    fn decode_record<W: Write>(&self, i: usize, stored: &[u8], output: &mut W) -> io::Result<u64> {
+        let opened;
+        let compressed = match &self.key {
+            Some(key) => {
+                opened = key.open(stored, Block::Record(i))?;
+                &opened[..]
+            },
+            None => stored,
+        };
+        let mut output = Bounded { inner: output, written: 0, limit: self.max_record_size.unwrap_or(u64::MAX), exceeded: record_too_large };
+        if self.transforms.is_empty() {
+            return self.decompress(compressed, &mut output);
+        }
+        let mut record: Vec<u8> = Vec::new();
+        self.decompress(compressed, &mut self.buffer(&mut record))?;
+        let record = transform::post_decompress(&self.transforms, record)?;
+        output.write_all(&record)?;
+        Ok(record.len() as u64)
+    }
+
+    // Decompresses record i into memory
+    pub fn read_record(&self, i: usize) -> io::Result<Vec<u8>> {
+        let mut record: Vec<u8> = Vec::new();
+        self.extract(i, &mut self.buffer(&mut record))?;
+        Ok(record)
+    }
+
+    // Writer filling buf with a decompressed record, up to the memory limit
+    fn buffer<'a>(&self, buf: &'a mut Vec<u8>) -> Bounded<&'a mut Vec<u8>> {
+        Bounded { inner: buf, written: 0, limit: self.memlimit.unwrap_or(u64::MAX), exceeded: codec::memlimit_exceeded }
+    }
+
+    // Iterates over all records in archive order, decompressing one at a time
+    pub fn records(&self) -> Records<'_> {
+        Records { archive: self, next: 0 }
+    }
+
+    // Same as records(), but reads the file front to back in large blocks instead of once per
+    // record, which is much faster for extracting a whole archive from a network filesystem
+    pub fn records_sequential(&self) -> SequentialRecords<'_> {
+        SequentialRecords { archive: self, next: 0, buf: Vec::new(), buf_start: 0 }
+    }
+
+    // Where the records keep their molecule name, as zip --name-from stored it in the metadata,
+    // the title line for archives without it
+    pub fn name_source(&self) -> NameSource {
+        self.metadata.get_str(NAME_SOURCE_KEY).and_then(|source| source.parse().ok()).unwrap_or(NameSource::Title)
+    }
+
+    // Returns indices of all records whose molecule name, read as name_source() gives, equals name
+    pub fn find(&self, name: &str) -> io::Result<Vec<usize>> {
+        let source = self.name_source();
+        let mut found: Vec<usize> = Vec::new();
+        for (i, record) in self.records().enumerate() {
+            if record?.name(&source).as_deref() == Some(name) {
+                found.push(i);
+            }
+        }
+        Ok(found)
+    }
+}
+
+pub struct Records<'a> {
+    archive: &'a Archive,
+    next: usize,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        if self.next >= self.archive.len() {
+            return None;
+        }
+        let record = self.archive.read_record(self.next).map(Record::new);
+        self.next += 1;
+        Some(record)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.archive.len() - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for Records<'a> {}
+
+// Fails if the metadata records a different number of records than the index holds
+fn check_record_count(metadata: &Metadata, index: &[u64]) -> io::Result<()> {
+    if let Some(records) = metadata.get_str("records") {
+        if records.parse::<usize>().ok() != Some(index.len() - 1) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Archive index holds {} records, but the footer records {}!", index.len() - 1, records)));
+        }
+    }
+    Ok(())
+}
+
+// Writer passing a decompressed record on to inner, failing with the error of exceeded once the
+// record grows beyond limit bytes
+struct Bounded<W> {
+    inner: W,
+    written: u64,
+    limit: u64,
+    exceeded: fn(u64) -> io::Error,
+}
+
+impl<W: Write> Write for Bounded<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.written + data.len() as u64 > self.limit {
+            return Err((self.exceeded)(self.limit));
+        }
+        let written = self.inner.write(data)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Error for records decompressing to more than the limit of set_max_record_size
+fn record_too_large(limit: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::FileTooLarge, format!("Record decompresses to more than the limit of {} bytes!", limit))
+}
+
+pub struct SequentialRecords<'a> {
+    archive: &'a Archive,
+    next: usize,
+    buf: Vec<u8>, // Block of the file holding the next records
+    buf_start: u64, // File offset of the block
+}
+
+impl<'a> SequentialRecords<'a> {
+    const BLOCK_SIZE: u64 = 4 << 20;
+
+    fn read(&mut self, i: usize) -> io::Result<Record> {
+        let archive = self.archive;
+        archive.check_readable()?;
+        let range = archive.offsets[i]..archive.offsets[i + 1];
+        archive.check_present(i, &range)?;
+        archive.check_memlimit(&range)?;
+        if range.start < self.buf_start || range.end > self.buf_start + self.buf.len() as u64 {
+            // Records following this one are read along with it, up to where the records end
+            let end = (range.start + SequentialRecords::BLOCK_SIZE).min(archive.footer_start).max(range.end);
+            self.buf.resize((end - range.start) as usize, 0);
+            read_exact_at(&archive.input, &mut self.buf, range.start)?;
+            self.buf_start = range.start;
+        }
+        let start = (range.start - self.buf_start) as usize;
+        let mut record: Vec<u8> = Vec::new();
+        archive.decode_record(i, &self.buf[start..start + (range.end - range.start) as usize], &mut archive.buffer(&mut record))?;
+        Ok(Record::new(record))
+    }
+}
+
+impl<'a> Iterator for SequentialRecords<'a> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        if self.next >= self.archive.len() {
+            return None;
+        }
+        let record = self.read(self.next);
+        self.next += 1;
+        Some(record)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.archive.len() - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for SequentialRecords<'a> {}
+
+// Reads the metadata in front of the index starting at index_start, returns it together with the
+// offset where the footer starts
+fn read_footer_metadata(input: &File, index_start: u64, key: Option<&Key>) -> io::Result<(Metadata, u64)> {
+    if index_start >= 16 {
+        let mut trailer: [u8; 16] = [0; 16];
+        read_exact_at(input, &mut trailer, index_start - 16)?;
+        if trailer[8..] == metadata::MAGIC {
+            let metadata_size = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+            let footer_start = (index_start - 16).checked_sub(metadata_size)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Metadata is corrupt!"))?;
+            let mut data: Vec<u8> = vec![0u8; metadata_size as usize];
+            read_exact_at(input, &mut data, footer_start)?;
+            return Ok((Metadata::decode(&unseal(key, data, Block::Metadata)?)?, footer_start));
+        }
+    }
+    Ok((Metadata::new(), index_start))
+}
+
+// Fills buf from the given file offset without moving the file cursor
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+pub(crate) fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, buf, offset) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            },
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Unpacks the compressed index from the file footer
+fn read_index(input: &File, index_start: u64, index_size: u64) -> io::Result<Vec<u8>> {
+    let mut index_compressed: Vec<u8> = vec![0u8; index_size as usize];
+    read_exact_at(input, &mut index_compressed, index_start)?;
+    Ok(index_compressed)
+}
+
+// Decrypts a block of the footer of an encrypted archive, others are returned as they are
+pub(crate) fn unseal(key: Option<&Key>, data: Vec<u8>, block: Block) -> io::Result<Vec<u8>> {
+    match key {
+        Some(key) => key.open(&data, block),
+        None => Ok(data),
+    }
+}
+
+// Stream header magic of xz streams, which the compressed index is
+const XZ_MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+
+// Error for files whose footer doesn't hold the index of a CmZ archive, e.g. SD files given as an
+// archive, or archives whose footer is lost
+pub(crate) fn not_an_archive() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Not a CmZ archive, or its file footer is damaged!")
+}
+
+// Compressed index size from the last 8 bytes of the file
+fn read_index_size(input: &File, file_size: u64) -> io::Result<u64> {
+    if file_size < 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a CmZ archive, the file is too short to hold a file footer!"));
+    }
+    let mut index_size_raw: [u8; 8] = [0; 8];
+    read_exact_at(input, &mut index_size_raw, file_size - 8)?;
+    Ok(u64::from_le_bytes(index_size_raw))
+}
+
+// Record offsets from the index: the start of every record followed by the end of the last one, so
+// looking up a record doesn't add up the sizes of all records in front of it
+pub(crate) fn offsets(index: &[u64]) -> Vec<u64> {
+    let mut offsets: Vec<u64> = Vec::with_capacity(index.len());
+    let mut offset: u64 = 0;
+    for &size in index {
+        offset = offset.saturating_add(size);
+        offsets.push(offset);
+    }
+    offsets
+}
+
+// Layout of the uncompressed archive index. Fixed is the original one, a little endian u64 per
+// entry. Varint payloads start with VARINT_INDEX_MAGIC followed by every entry as a LEB128 varint,
+// so the typical record size takes two bytes instead of eight. The magic read as the first fixed
+// entry would put the first record past the end of any file, so both layouts are told apart safely.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexFormat {
+    Fixed,
+    Varint,
+}
+
+pub const VARINT_INDEX_MAGIC: [u8; 8] = *b"CMZ\0IDX2";
+
+impl IndexFormat {
+    // Largest uncompressed size of an index with the given number of entries
+    pub fn max_size(self, entries: usize) -> usize {
+        match self {
+            IndexFormat::Fixed => entries * 8,
+            IndexFormat::Varint => VARINT_INDEX_MAGIC.len() + entries * 10,
+        }
+    }
+}
+
+// Uncompressed index payload in the given layout
+pub fn encode_index(index: &[u64], format: IndexFormat) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::with_capacity(format.max_size(index.len()));
+    match format {
+        IndexFormat::Fixed => {
+            for value in index {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        },
+        IndexFormat::Varint => {
+            data.extend_from_slice(&VARINT_INDEX_MAGIC);
+            for &value in index {
+                let mut value = value;
+                while value >= 0x80 {
+                    data.push((value as u8 & 0x7F) | 0x80);
+                    value >>= 7;
+                }
+                data.push(value as u8);
+            }
+        },
+    }
+    data
+}
+
+// Entries the index of an archive of file_size bytes with the given footer metadata can hold:
+// the header end and one per record the metadata records, or without metadata one per byte of
+// the file, as every record takes at least one
+pub fn max_index_entries(metadata: &Metadata, file_size: u64) -> u64 {
+    metadata.get_str("records").and_then(|records| records.parse::<u64>().ok()).unwrap_or(file_size).saturating_add(1)
+}
+
+// Decompresses and decodes an index of at most max_entries entries, decompression stops once the
+// largest index of that many entries is exceeded, so a small crafted index can't expand without bound
+pub fn decode_index(compressed: &[u8], max_entries: u64) -> io::Result<Vec<u64>> {
+    // Any file has an index size in its last 8 bytes, those of other files point at data that
+    // isn't an xz stream or doesn't decompress to an index
+    if !compressed.starts_with(&XZ_MAGIC) {
+        return Err(not_an_archive());
+    }
+    let limit = (VARINT_INDEX_MAGIC.len() as u64).saturating_add(max_entries.saturating_mul(10));
+    let mut index_decompressed: Vec<u8> = Vec::new();
+    XzDecoder::new(compressed).take(limit.saturating_add(1)).read_to_end(&mut index_decompressed)?;
+    let too_large = || io::Error::new(io::ErrorKind::InvalidData, format!("Archive index holds more than {} entries!", max_entries));
+    if index_decompressed.len() as u64 > limit {
+        return Err(too_large());
+    }
+    // Every index starts with the header end, archives without records hold only that
+    let index = match index_decompressed.strip_prefix(&VARINT_INDEX_MAGIC) {
+        Some(data) => decode_varints(data)?,
+        None => {
+            if !index_decompressed.len().is_multiple_of(8) {
+                return Err(not_an_archive());
+            }
+            // Numbers in index are raw little endian bytes, convert them to u64
+            index_decompressed.chunks(8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap())).collect()
+        },
+    };
+    if index.is_empty() {
+        return Err(not_an_archive());
+    }
+    if index.len() as u64 > max_entries {
+        return Err(too_large());
+    }
+    Ok(index)
+}
+
+fn decode_varints(data: &[u8]) -> io::Result<Vec<u64>> {
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "Archive index is corrupt!");
+    let mut index: Vec<u64> = Vec::with_capacity(data.len() / 2);
+    let (mut value, mut shift) = (0u64, 0u32);
+    for &byte in data {
+        if shift > 63 || (shift == 63 && byte > 1) {
+            return Err(corrupt());
+        }
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            index.push(value);
+            value = 0;
+            shift = 0;
+        } else {
+            shift += 7;
+        }
+    }
+    if shift != 0 {
+        return Err(corrupt()); // Last varint cut off
+    }
+    Ok(index)
+}
+
+// Codec named in the metadata and its decoder, None while a referenced dictionary is not supplied
+pub(crate) fn open_decoder(metadata: &Metadata) -> io::Result<(Codec, Option<Decoder>)> {
+    let codec = match metadata.get_str("codec") {
+        Some(name) => name.parse::<Codec>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        None => Codec::Xz,
+    };
+    let decoder = match (metadata.get("dictionary"), metadata.get("dictionary_sha256")) {
+        (Some(embedded), _) => Some(Decoder::new(codec, Some(&Dictionary::new(embedded.to_vec())))),
+        (None, Some(_)) => None,
+        (None, None) => Some(Decoder::new(codec, None)),
+    };
+    Ok((codec, decoder))
+}
+
+// Decoder using dictionary, after checking it against the digest stored in the metadata
+pub(crate) fn verified_decoder(metadata: &Metadata, codec: Codec, dictionary: &Dictionary) -> io::Result<Decoder> {
+    match metadata.get_str("dictionary_sha256") {
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Archive was not compressed with a dictionary!")),
+        Some(digest) if digest != dictionary.digest() => Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("Dictionary does not match archive, expected dictionary {} with sha256 {}!", metadata.get_str("dictionary_id").unwrap_or("?"), digest))),
+        Some(_) => Ok(Decoder::new(codec, Some(dictionary))),
+    }
+}
+
+pub(crate) fn missing_dictionary(metadata: &Metadata) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("Archive requires dictionary {} with sha256 {}!",
+        metadata.get_str("dictionary_id").unwrap_or("?"), metadata.get_str("dictionary_sha256").unwrap_or("?")))
+}
+
+// Returns the title line of an SD record, which holds the molecule name. In mol2 records the name
+// follows the @<TRIPOS>MOLECULE line, which comments and blank lines may precede.
+pub fn title(record: &[u8]) -> String {
+    let first = record.split(|&b| b == b'\n').next().unwrap_or_default();
+    let mut lines = record.split(|&b| b == b'\n').skip_while(|line| line.trim_ascii().is_empty() || line.starts_with(b"#"));
+    let line = match lines.next() {
+        Some(line) if line.trim_ascii() == sdf::MOL2_DELIMITER => lines.next().unwrap_or_default(),
+        _ => first,
+    };
+    String::from_utf8_lossy(line).trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Writer, testing::{self, Corruption}, writer::compress};
+
+    // Index entries around the varint byte boundaries, the header end first
+    const INDEX: [u64; 8] = [68, 0, 1, 127, 128, 16383, 16384, u64::MAX];
+    const ENTRIES: u64 = INDEX.len() as u64;
+
+    fn xz(data: &[u8]) -> Vec<u8> {
+        let mut compressed: Vec<u8> = Vec::new();
+        compress(data, &mut compressed, 6).unwrap();
+        compressed
+    }
+
+    fn varint_payload(bytes: &[u8]) -> Vec<u8> {
+        [&VARINT_INDEX_MAGIC[..], bytes].concat()
+    }
+
+    #[test]
+    fn fixed_index_round_trips() {
+        let data = encode_index(&INDEX, IndexFormat::Fixed);
+        assert_eq!(data.len(), IndexFormat::Fixed.max_size(INDEX.len()));
+        assert_eq!(decode_index(&xz(&data), ENTRIES).unwrap(), INDEX);
+    }
+
+    #[test]
+    fn varint_index_round_trips() {
+        let data = encode_index(&INDEX, IndexFormat::Varint);
+        assert!(data.starts_with(&VARINT_INDEX_MAGIC));
+        assert!(data.len() <= IndexFormat::Varint.max_size(INDEX.len()));
+        assert_eq!(decode_index(&xz(&data), ENTRIES).unwrap(), INDEX);
+    }
+
+    #[test]
+    fn index_of_archive_without_records_round_trips() {
+        for format in [IndexFormat::Fixed, IndexFormat::Varint] {
+            assert_eq!(decode_index(&xz(&encode_index(&[0], format)), ENTRIES).unwrap(), [0]);
+        }
+    }
+
+    #[test]
+    fn truncated_varint_is_rejected() {
+        let mut data = encode_index(&INDEX, IndexFormat::Varint);
+        data.pop(); // Last byte of u64::MAX, the one before it has the continuation bit set
+        assert_eq!(decode_index(&xz(&data), ENTRIES).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decode_index(&xz(&varint_payload(&[0x80])), ENTRIES).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn overlong_varint_is_rejected() {
+        // Ten bytes hold 64 bits with only the lowest bit of the last one, more overflow a u64
+        let varint = |continued: u8, length: usize, last: u8| varint_payload(&[vec![continued; length - 1], vec![last]].concat());
+        assert_eq!(decode_index(&xz(&varint(0xFF, 10, 0x01)), ENTRIES).unwrap(), [u64::MAX]);
+        assert_eq!(decode_index(&xz(&varint(0xFF, 10, 0x02)), ENTRIES).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decode_index(&xz(&varint(0x80, 11, 0x00)), ENTRIES).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn index_larger_than_its_entries_is_rejected() {
+        // A fixed index of 1000 entries and varints taking the 10 bytes allowed for each entry
+        let fixed = xz(&encode_index(&[1; 1000], IndexFormat::Fixed));
+        assert_eq!(decode_index(&fixed, 1000).unwrap().len(), 1000);
+        assert_eq!(decode_index(&fixed, 999).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let varints = xz(&encode_index(&[u64::MAX; 100], IndexFormat::Varint));
+        assert_eq!(decode_index(&varints, 100).unwrap().len(), 100);
+        assert_eq!(decode_index(&varints, 99).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // Archives without metadata are bounded by their size, with it by its record count
+        let mut metadata = Metadata::new();
+        assert_eq!(max_index_entries(&metadata, 5000), 5001);
+        metadata.set("records", "25");
+        assert_eq!(max_index_entries(&metadata, 5000), 26);
+    }
+
+    #[test]
+    fn footer_opens_as_index_file() {
+        let bytes = testing::archive_bytes(&testing::sd_records(5)).unwrap();
+        let archive = testing::open(&bytes).unwrap();
+        let records_end = archive.record_range(4).unwrap().end as usize;
+        assert!(!archive.is_index_only());
+        let index_file = testing::open(&bytes[records_end..]).unwrap();
+        assert!(index_file.is_index_only());
+        assert_eq!(index_file.len(), 5);
+        assert_eq!(index_file.record_range(4), archive.record_range(4));
+    }
+
+    #[test]
+    fn damaged_record_fails_alone() {
+        let records = testing::sd_records(10);
+        let mut bytes = testing::archive_bytes(&records).unwrap();
+        testing::corrupt(&mut bytes, Corruption::FlipRecordByte(4)).unwrap();
+        let archive = testing::open(&bytes).unwrap();
+        for (i, record) in records.iter().enumerate() {
+            match i {
+                4 => assert!(archive.read_record(i).is_err()),
+                _ => assert_eq!(&archive.read_record(i).unwrap(), record),
+            }
+        }
+    }
+
+    #[test]
+    fn empty_or_misaligned_index_is_rejected() {
+        assert!(decode_index(&xz(&[]), ENTRIES).is_err());
+        assert!(decode_index(&xz(&VARINT_INDEX_MAGIC), ENTRIES).is_err());
+        assert!(decode_index(&xz(&encode_index(&INDEX, IndexFormat::Fixed)[..20]), ENTRIES).is_err());
+        assert!(decode_index(&encode_index(&INDEX, IndexFormat::Fixed), ENTRIES).is_err()); // Not xz compressed
+    }
+
+    #[test]
+    fn find_reads_names_with_the_stored_rule() {
+        let records = [
+            testing::sd_record("MOL0", 1, &[("ID", "LIG-A")]),
+            testing::sd_record("LIG-A", 2, &[]),
+            testing::sd_record("MOL2", 3, &[("ID", "LIG-A")]),
+        ];
+        let archive = testing::open(&testing::archive_bytes(&records).unwrap()).unwrap();
+        assert_eq!(archive.name_source(), NameSource::Title);
+        assert_eq!(archive.find("LIG-A").unwrap(), [1]);
+
+        let mut writer = Writer::with_codec(Vec::new(), Codec::Xz, 0, None).unwrap();
+        writer.metadata_mut().set(NAME_SOURCE_KEY, "tag:ID");
+        for record in &records {
+            writer.add_record(record).unwrap();
+        }
+        let archive = testing::open(&writer.finish().unwrap()).unwrap();
+        assert_eq!(archive.name_source(), NameSource::Tag("ID".to_string()));
+        assert_eq!(archive.find("LIG-A").unwrap(), [0, 2]);
+    }
+}
@@ -0,0 +1,143 @@
+// Non-blocking read access to CmZ archives over tokio's AsyncRead + AsyncSeek, for embedding
+// archives in async services. Footer reads and record fetches await the input, decompression of
+// a single record happens in memory on the calling task.
+
+use std::{
+    convert::TryInto,
+    io::{self, SeekFrom}
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+use crate::{
+    archive,
+    codec::{Codec, Decoder},
+    crypto,
+    dictionary::Dictionary,
+    metadata::{self, Metadata},
+    record::Record,
+    transform::{self, RecordTransform}
+};
+
+pub struct AsyncArchive<R> {
+    input: R,
+    file_size: u64,
+    index_size: u64,
+    index: Vec<u64>, // First element is the header end, followed by compressed record sizes
+    offsets: Vec<u64>, // Start of every record, followed by the end of the last one
+    metadata: Metadata,
+    codec: Codec,
+    decoder: Option<Decoder>, // None until the dictionary the archive was compressed with is supplied
+    transforms: Vec<Box<dyn RecordTransform>>, // Applied to every decompressed record
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncArchive<R> {
+    // Reads the index and metadata from the file footer
+    pub async fn new(mut input: R) -> io::Result<AsyncArchive<R>> {
+        let file_size = input.seek(SeekFrom::End(0)).await?;
+        if file_size < 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a CmZ archive, the file is too short to hold a file footer!"));
+        }
+        if file_size >= crypto::HEADER_SIZE {
+            let mut magic: [u8; 8] = [0; 8];
+            input.seek(SeekFrom::Start(0)).await?;
+            input.read_exact(&mut magic).await?;
+            if magic == crypto::MAGIC {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "Encrypted archives can only be read with Archive!"));
+            }
+        }
+
+        // Compressed index size is stored in the last 8 bytes, the index directly precedes it
+        input.seek(SeekFrom::Start(file_size - 8)).await?;
+        let index_size = input.read_u64_le().await?;
+        let index_start = file_size.checked_sub(index_size.saturating_add(8)).ok_or_else(archive::not_an_archive)?;
+
+        // Metadata precedes the index, archives without it are plain xz archives
+        let mut metadata = Metadata::new();
+        if index_start >= 16 {
+            let mut trailer: [u8; 16] = [0; 16];
+            input.seek(SeekFrom::Start(index_start - 16)).await?;
+            input.read_exact(&mut trailer).await?;
+            if trailer[8..] == metadata::MAGIC {
+                let metadata_size = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+                let metadata_start = (index_start - 16).checked_sub(metadata_size)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Metadata is corrupt!"))?;
+                let mut data: Vec<u8> = vec![0u8; metadata_size as usize];
+                input.seek(SeekFrom::Start(metadata_start)).await?;
+                input.read_exact(&mut data).await?;
+                metadata = Metadata::decode(&data)?;
+            }
+        }
+
+        // Its record count bounds the index
+        let mut index_compressed: Vec<u8> = vec![0u8; index_size as usize];
+        input.seek(SeekFrom::Start(index_start)).await?;
+        input.read_exact(&mut index_compressed).await?;
+        let index = archive::decode_index(&index_compressed, archive::max_index_entries(&metadata, file_size))?;
+        if index.iter().try_fold(0u64, |end, &size| end.checked_add(size)).is_none_or(|end| end > index_start) {
+            return Err(archive::not_an_archive()); // Records would end behind the footer
+        }
+
+        let (codec, decoder) = archive::open_decoder(&metadata)?;
+        let offsets = archive::offsets(&index);
+        Ok(AsyncArchive { input, file_size, index_size, index, offsets, metadata, codec, decoder, transforms: Vec::new() })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    // Runs transform on every record decompressed from now on, after the transforms attached before
+    pub fn with_transform<T: RecordTransform + 'static>(mut self, transform: T) -> AsyncArchive<R> {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    // Supplies the dictionary the archive was compressed with, verified against the stored digest
+    pub fn set_dictionary(&mut self, dictionary: &Dictionary) -> io::Result<()> {
+        self.decoder = Some(archive::verified_decoder(&self.metadata, self.codec, dictionary)?);
+        Ok(())
+    }
+
+    // Number of records stored in the archive
+    pub fn len(&self) -> usize {
+        self.index.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    // Size of the compressed index in the file footer
+    pub fn index_size(&self) -> u64 {
+        self.index_size
+    }
+
+    // Decompresses record i into memory
+    pub async fn read_record(&mut self, i: usize) -> io::Result<Vec<u8>> {
+        if i >= self.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Record {} does not exist!", i)));
+        }
+        let decoder = self.decoder.as_ref().ok_or_else(|| archive::missing_dictionary(&self.metadata))?;
+        self.input.seek(SeekFrom::Start(self.offsets[i])).await?;
+        let mut buf: Vec<u8> = vec![0u8; self.index[i + 1] as usize];
+        self.input.read_exact(&mut buf).await?;
+        let mut record: Vec<u8> = Vec::new();
+        decoder.decompress(&buf, &mut record)?;
+        transform::post_decompress(&self.transforms, record)
+    }
+
+    pub async fn record(&mut self, i: usize) -> io::Result<Record> {
+        self.read_record(i).await.map(Record::new)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+}
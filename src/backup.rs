@@ -0,0 +1,97 @@
+// Backup footer block, written directly after the last record in front of the regular footer, so
+// archives whose tail was cut off or overwritten can still be opened with Archive::open_backup().
+//
+// Layout: MAGIC, the metadata size, the metadata, the compressed index size and the compressed
+// index, sizes as little endian u64s. It holds the same metadata and index as the regular footer,
+// so readers of the regular footer find its start from their sizes. Without the regular footer it
+// is found by searching the file backwards for MAGIC, a candidate is only accepted if its index
+// decompresses and the records it lists end exactly where the block starts.
+
+use std::{
+    convert::TryInto,
+    fs::File,
+    io
+};
+use crate::{
+    archive::{decode_index, max_index_entries, read_exact_at, unseal},
+    crypto::{Block, Key},
+    metadata::Metadata
+};
+
+pub const MAGIC: [u8; 8] = *b"CMZ\0BKUP";
+
+// Size of the backup block for metadata and compressed index of the given sizes
+pub fn size(metadata_size: u64, index_size: u64) -> u64 {
+    8 + 8 + metadata_size + 8 + index_size
+}
+
+pub fn encode(metadata: &[u8], index_compressed: &[u8]) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::with_capacity(size(metadata.len() as u64, index_compressed.len() as u64) as usize);
+    data.extend_from_slice(&MAGIC);
+    data.extend_from_slice(&(metadata.len() as u64).to_le_bytes());
+    data.extend_from_slice(metadata);
+    data.extend_from_slice(&(index_compressed.len() as u64).to_le_bytes());
+    data.extend_from_slice(index_compressed);
+    data
+}
+
+pub(crate) struct Backup {
+    pub start: u64,
+    pub index_start: u64,
+    pub index_size: u64,
+    pub index: Vec<u64>,
+    pub metadata: Metadata,
+}
+
+// Last valid backup block in the file, searched from its end
+pub(crate) fn find(input: &File, file_size: u64, key: Option<&Key>) -> io::Result<Backup> {
+    const CHUNK: u64 = 1 << 20;
+    let mut end = file_size;
+    let mut buf: Vec<u8> = Vec::new();
+    while end >= MAGIC.len() as u64 {
+        // Chunks overlap by the magic length, so a magic crossing a chunk border is not missed
+        let start = end.saturating_sub(CHUNK);
+        buf.resize((end - start) as usize, 0);
+        read_exact_at(input, &mut buf, start)?;
+        for at in (0..=buf.len() - MAGIC.len()).rev() {
+            if buf[at..at + MAGIC.len()] == MAGIC {
+                if let Some(backup) = decode(input, start + at as u64, file_size, key) {
+                    return Ok(backup);
+                }
+            }
+        }
+        if start == 0 {
+            break;
+        }
+        end = start + MAGIC.len() as u64 - 1;
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "Archive has no readable backup index!"))
+}
+
+// Backup block starting at offset start, None if it is damaged or the magic is part of a record
+fn decode(input: &File, start: u64, file_size: u64, key: Option<&Key>) -> Option<Backup> {
+    let number = |offset: u64| -> Option<u64> {
+        let mut raw: [u8; 8] = [0; 8];
+        read_exact_at(input, &mut raw, offset).ok()?;
+        Some(u64::from_le_bytes(raw))
+    };
+    let metadata_size = number(start + 8)?;
+    let metadata_start = start + 16;
+    let index_size = number(metadata_start.checked_add(metadata_size)?)?;
+    let index_start = metadata_start + metadata_size + 8;
+    if index_start.checked_add(index_size)? > file_size {
+        return None;
+    }
+    let read = |offset: u64, size: u64| -> Option<Vec<u8>> {
+        let mut data: Vec<u8> = vec![0u8; size.try_into().ok()?];
+        read_exact_at(input, &mut data, offset).ok()?;
+        Some(data)
+    };
+    let metadata = Metadata::decode(&unseal(key, read(metadata_start, metadata_size)?, Block::Metadata).ok()?).ok()?;
+    let index = decode_index(&unseal(key, read(index_start, index_size)?, Block::Index).ok()?, max_index_entries(&metadata, file_size)).ok()?;
+    let records_end = index.iter().try_fold(0u64, |end, &size| end.checked_add(size))?;
+    if index.is_empty() || records_end != start {
+        return None;
+    }
+    Some(Backup { start, index_start, index_size, index, metadata })
+}
@@ -0,0 +1,101 @@
+// Batch directory mode of cmzip zip and cmzip watch: every SD file of a directory (tree) is
+// compressed into an archive of the same relative path under the output directory, e.g.
+// results/run1/a.sdf into archives/run1/a.cmz.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::Mutex
+};
+use cmzip::{Archive, units};
+use crate::{OrExit, invalid_argument};
+
+const EXTENSIONS: [&str; 2] = ["sd", "sdf"];
+
+// Input and output files of the SD files in dir, sorted by path. Subdirectories are only searched
+// with recursive set.
+pub fn jobs(dir: &Path, out_dir: &Path, recursive: bool) -> Vec<(PathBuf, PathBuf)> {
+    let mut inputs: Vec<PathBuf> = Vec::new();
+    find(dir, recursive, &mut inputs);
+    inputs.sort();
+    let mut outputs: HashMap<PathBuf, &Path> = HashMap::new();
+    let mut jobs: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for input in &inputs {
+        let output = out_dir.join(input.strip_prefix(dir).unwrap()).with_extension("cmz");
+        if let Some(other) = outputs.insert(output.clone(), input) {
+            invalid_argument(&format!("Both {} and {} would be compressed into {}!", other.display(), input.display(), output.display()));
+        }
+        jobs.push((input.clone(), output));
+    }
+    jobs
+}
+
+fn find(dir: &Path, recursive: bool, found: &mut Vec<PathBuf>) {
+    let entries = fs::read_dir(dir).or_exit(&format!("Unable to read {}!", dir.display()));
+    for entry in entries {
+        let entry = entry.or_exit(&format!("Unable to read {}!", dir.display()));
+        let path = entry.path();
+        let kind = entry.file_type().or_exit(&format!("Unable to read {}!", path.display()));
+        if kind.is_dir() {
+            if recursive {
+                find(&path, recursive, found);
+            }
+        } else if path.extension().and_then(|extension| extension.to_str()).is_some_and(|extension| EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())) {
+            found.push(path);
+        }
+    }
+}
+
+// Progress of the files compressed by all workers, one line on stderr per finished file
+pub struct Progress {
+    total: usize,
+    done: Mutex<(usize, u64, u64)>, // Files, input bytes and archive bytes so far
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Progress {
+        Progress { total, done: Mutex::new((0, 0, 0)) }
+    }
+
+    // Reports input compressed into output, which was written to staged until all are done
+    pub fn finished(&self, input: &Path, output: &Path, staged: &Path) {
+        let size = |path: &Path| fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+        let mut done = self.done.lock().unwrap();
+        done.0 += 1;
+        done.1 += size(input);
+        done.2 += size(staged);
+        log::info!("[{}/{}] {} -> {} ({} to {} so far)", done.0, self.total, input.display(), output.display(), units::format_size(done.1), units::format_size(done.2));
+    }
+}
+
+// Checks that the records of archive decompress to exactly the bytes of inputs, read one after
+// another, and that its index matches the health checksum written with it, so inputs can be
+// deleted. Fails for inputs whose trailing whitespace after the last record was dropped as well.
+pub fn verify(inputs: &[&Path], archive: &Path) -> Result<(), String> {
+    let unreadable = |path: &dyn std::fmt::Display, e: io::Error| format!("Unable to read {}: {}", path, e);
+    let archive_data = Archive::open(archive).map_err(|e| unreadable(&archive.display(), e))?;
+    if archive_data.health_matches() == Some(false) {
+        return Err(format!("Index of {} doesn't match its health checksum!", archive.display()));
+    }
+    let names = inputs.iter().map(|input| input.display().to_string()).collect::<Vec<String>>().join(", ");
+    let mut input_data: Box<dyn Read> = Box::new(io::empty());
+    for input in inputs {
+        let file = File::open(input).map_err(|e| unreadable(&input.display(), e))?;
+        input_data = Box::new(input_data.chain(BufReader::new(file)));
+    }
+    let mut expected: Vec<u8> = Vec::new();
+    for (i, record) in archive_data.records_sequential().enumerate() {
+        let record = record.map_err(|e| format!("Error extracting record {} of {}: {}", i, archive.display(), e))?;
+        expected.resize(record.as_bytes().len(), 0);
+        input_data.read_exact(&mut expected).map_err(|e| unreadable(&names, e))?;
+        if expected != record.as_bytes() {
+            return Err(format!("Record {} of {} differs from {}!", i, archive.display(), names));
+        }
+    }
+    if input_data.read(&mut [0u8; 1]).map_err(|e| unreadable(&names, e))? > 0 {
+        return Err(format!("{} holds data after the last record of {}!", names, archive.display()));
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+// Report of what this build of cmzip handles, written by cmzip --capabilities as one JSON object,
+// so orchestration layers can check that the binary on a node reads a given archive before
+// scheduling work there: the version and git commit, the archive formats read, the codecs compiled
+// in with their highest level and library, and the optional cargo features enabled.
+//
+//   {"version":"0.1.0","git_commit":"ec077ae...","target":"x86_64-unknown-linux-gnu",
+//    "formats":{"index":["fixed","varint"],...},"codecs":[{"name":"xz","max_level":9,...}],
+//    "features":["zstd"]}
+
+use cmzip::{codec::Codec, crypto};
+use crate::json;
+
+// Optional cargo features and whether this build has them
+const FEATURES: [(&str, bool); 3] = [
+    ("zstd", cfg!(feature = "zstd")),
+    ("async", cfg!(feature = "async")),
+    ("cdylib", cfg!(feature = "cdylib")),
+];
+
+pub fn report() -> String {
+    let list = |items: &[&str]| format!("[{}]", items.iter().map(|item| json::string(item)).collect::<Vec<String>>().join(","));
+    let formats = json::object(&[
+        ("index", list(&["fixed", "varint"])),
+        ("footer", list(&["metadata", "backup", "health"])),
+        ("encryption", list(&[crypto::ALGORITHM])),
+        ("records", list(&["sdf", "mol2"])),
+        ("files", list(&["cmz", "cmzi", "idx", "intent"])),
+    ]);
+    let codecs: Vec<String> = Codec::ALL.iter().filter(|codec| codec.is_available()).map(|codec| json::object(&[
+        ("name", json::string(codec.name())),
+        ("max_level", codec.max_level().to_string()),
+        ("library", json::string(&codec.library_version())),
+    ])).collect();
+    let features: Vec<&str> = FEATURES.iter().filter(|(_, enabled)| *enabled).map(|(feature, _)| *feature).collect();
+    json::object(&[
+        ("version", json::string(env!("CARGO_PKG_VERSION"))),
+        ("git_commit", json::string(env!("CMZIP_GIT_COMMIT"))),
+        ("target", json::string(env!("CMZIP_TARGET"))),
+        ("formats", formats),
+        ("codecs", format!("[{}]", codecs.join(","))),
+        ("features", list(&features)),
+    ])
+}
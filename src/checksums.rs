@@ -0,0 +1,73 @@
+// SHA-256 manifests of archives, written by cmzip hash and checked by verify --manifest. The first
+// line holds the digest of the archive file in the format of sha256sum, so `sha256sum -c` checks
+// the archive as well. With --per-record a line "# record INDEX DIGEST NAME" follows for every
+// record, with the digest of the decompressed record, which sha256sum skips as a comment. Record
+// digests stay valid across changes that keep the records, e.g. recompress, while the digest of
+// the archive doesn't.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read}
+};
+use sha2::{Digest, Sha256};
+
+pub const SUFFIX: &str = ".sha256";
+
+pub struct Manifest {
+    pub archive: String, // Hex digest of the archive file
+    pub records: Option<Vec<String>>, // Hex digests of the decompressed records, by index
+}
+
+pub fn manifest_path(archive: &str) -> String {
+    format!("{}{}", archive, SUFFIX)
+}
+
+// Hex encoded SHA-256 of the file in path, read in blocks
+pub fn file_digest(path: &str) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub fn archive_line(digest: &str, path: &str) -> String {
+    format!("{}  {}\n", digest, path)
+}
+
+pub fn record_line(i: usize, digest: &str, name: &str) -> String {
+    format!("# record {} {} {}\n", i, digest, name)
+}
+
+pub fn read(path: &str) -> Result<Manifest, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Unable to read manifest {}: {}", path, e))?;
+    let invalid = |n: usize| format!("Line {} of manifest {} is invalid!", n + 1, path);
+    let mut lines = text.lines().enumerate();
+    let archive = match lines.next() {
+        Some((_, line)) if is_digest(line.split(' ').next().unwrap()) => line[..64].to_ascii_lowercase(),
+        _ => return Err(invalid(0)),
+    };
+    let mut records: Option<Vec<String>> = None;
+    for (n, line) in lines {
+        let mut fields = line.splitn(5, ' ');
+        let (hash, record, i, digest) = (fields.next(), fields.next(), fields.next(), fields.next());
+        if hash != Some("#") || record != Some("record") {
+            continue;
+        }
+        let records = records.get_or_insert_with(Vec::new);
+        match (i.and_then(|i| i.parse::<usize>().ok()), digest) {
+            (Some(i), Some(digest)) if i == records.len() && is_digest(digest) => records.push(digest.to_ascii_lowercase()),
+            _ => return Err(invalid(n)),
+        }
+    }
+    Ok(Manifest { archive, records })
+}
+
+fn is_digest(text: &str) -> bool {
+    text.len() == 64 && text.bytes().all(|b| b.is_ascii_hexdigit())
+}
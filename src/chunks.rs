@@ -0,0 +1,73 @@
+// Content-defined chunking of archive files for cmzip chunks, so deduplicating storage can plan
+// how an archive will be stored before ingesting it. Boundaries are found with a gear rolling hash
+// over the bytes themselves rather than at fixed offsets, so the same bytes give the same chunks
+// wherever they are in the file: records appended to an archive or copied into another one by
+// merge leave the chunks in front of them unchanged.
+
+use std::io::{self, Read};
+use sha2::{Digest, Sha256};
+
+pub struct Sizes {
+    pub min: u64,
+    pub avg: u64,
+    pub max: u64,
+}
+
+pub struct Chunk {
+    pub offset: u64,
+    pub length: u64,
+    pub digest: [u8; 32], // SHA-256 of the chunk
+}
+
+// Random value for every byte, from splitmix64 so the boundaries never change between versions
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x636d7a6970; // "cmzip"
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+// Splits input into chunks, handing each to found in file order. Between sizes.min and sizes.max
+// bytes after its start, a chunk ends where the hash of the bytes before it falls below a threshold
+// that makes chunks sizes.avg bytes long on average.
+pub fn split<R: Read, F: FnMut(Chunk) -> io::Result<()>>(mut input: R, sizes: &Sizes, mut found: F) -> io::Result<()> {
+    // Past the minimum size a boundary follows after avg - min bytes on average
+    let threshold = u64::MAX / (sizes.avg - sizes.min).max(1);
+    let mut buffer: Vec<u8> = vec![0u8; 8 << 20];
+    let (mut offset, mut length, mut hash) = (0u64, 0u64, 0u64);
+    let mut digest = Sha256::new();
+    loop {
+        let n = match input.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let mut start = 0;
+        for (i, &byte) in buffer[..n].iter().enumerate() {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            length += 1;
+            if (length >= sizes.min && hash <= threshold) || length >= sizes.max {
+                digest.update(&buffer[start..=i]);
+                found(Chunk { offset, length, digest: digest.finalize_reset().into() })?;
+                start = i + 1;
+                offset += length;
+                length = 0;
+                hash = 0;
+            }
+        }
+        digest.update(&buffer[start..n]);
+    }
+    if length > 0 {
+        found(Chunk { offset, length, digest: digest.finalize().into() })?;
+    }
+    Ok(())
+}
@@ -0,0 +1,298 @@
+// Record codecs. Every record is an independent xz or zstd stream; zstd records may share a
+// dictionary trained on similar records, which pays off for small SD records. zstd support is
+// behind the default zstd feature, xz is always built in since the file footer index uses it.
+
+use std::{
+    fmt,
+    io::{
+        self,
+        prelude::*
+    },
+    str::FromStr
+};
+use xz2::{read::{XzEncoder, XzDecoder}, stream::Stream};
+use crate::{dictionary::Dictionary, xz};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Xz,
+    Zstd,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 2] = [Codec::Xz, Codec::Zstd];
+
+    // True if this build can compress and decompress records with the codec
+    pub fn is_available(self) -> bool {
+        match self {
+            Codec::Xz => true,
+            Codec::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    // Error for archives and records using a codec this build was compiled without
+    pub fn unavailable(self) -> io::Error {
+        let available: Vec<&str> = Codec::ALL.iter().filter(|codec| codec.is_available()).map(|codec| codec.name()).collect();
+        io::Error::new(io::ErrorKind::Unsupported, format!("{} support is not built in (this build supports {}), rebuild cmzip with --features {}!",
+            self, available.join(", "), self))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Xz => "xz",
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    // Highest accepted compression level
+    pub fn max_level(self) -> u32 {
+        match self {
+            Codec::Xz => 9,
+            Codec::Zstd => 22,
+        }
+    }
+
+    // Name and version of the compression library linked into this build
+    pub fn library_version(self) -> String {
+        match self {
+            Codec::Xz => {
+                let version = unsafe { std::ffi::CStr::from_ptr(lzma_sys::lzma_version_string()) };
+                format!("liblzma {}", version.to_string_lossy())
+            },
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => format!("zstd {}", zstd::zstd_safe::version_string()),
+            #[cfg(not(feature = "zstd"))]
+            Codec::Zstd => "none".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Codec, String> {
+        match name {
+            "xz" => Ok(Codec::Xz),
+            "zstd" => Ok(Codec::Zstd),
+            _ => Err(format!("Unknown codec {}!", name)),
+        }
+    }
+}
+
+// Compresses records with a fixed codec, level and optional dictionary
+pub struct Encoder {
+    codec: Codec,
+    level: u32,
+    extreme: bool, // xz only, see set_extreme()
+    xz_options: Option<xz::Options>, // Custom filter chain, see set_xz_options()
+    dictionary_id: Option<u32>,
+    #[cfg(feature = "zstd")]
+    zstd: Option<zstd::bulk::Compressor<'static>>, // Keeps the dictionary loaded between records
+}
+
+impl Encoder {
+    pub fn new(codec: Codec, level: u32, dictionary: Option<&Dictionary>) -> io::Result<Encoder> {
+        if !codec.is_available() {
+            return Err(codec.unavailable());
+        }
+        if codec == Codec::Xz && dictionary.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Dictionaries are only supported by the zstd codec!"));
+        }
+        #[cfg(feature = "zstd")]
+        let zstd = match (codec, dictionary) {
+            (Codec::Xz, _) => None,
+            (Codec::Zstd, None) => Some(zstd::bulk::Compressor::new(level as i32)?),
+            (Codec::Zstd, Some(dictionary)) => Some(zstd::bulk::Compressor::with_dictionary(level as i32, dictionary.as_bytes())?),
+        };
+        Ok(Encoder {
+            codec,
+            level,
+            extreme: false,
+            xz_options: None,
+            dictionary_id: dictionary.map(Dictionary::id),
+            #[cfg(feature = "zstd")]
+            zstd,
+        })
+    }
+
+    pub fn xz(level: u32) -> Encoder {
+        Encoder {
+            codec: Codec::Xz,
+            level,
+            extreme: false,
+            xz_options: None,
+            dictionary_id: None,
+            #[cfg(feature = "zstd")]
+            zstd: None,
+        }
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    // Compresses with the extreme variant of the xz preset (xz -9e), a few percent smaller at
+    // several times the compression time. Decompression is unaffected.
+    pub fn set_extreme(&mut self, extreme: bool) -> io::Result<()> {
+        if extreme && self.codec != Codec::Xz {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The extreme preset is only supported by the xz codec!"));
+        }
+        self.extreme = extreme;
+        Ok(())
+    }
+
+    // Compresses with the filter chain of the xz preset changed by options, see xz.rs
+    pub fn set_xz_options(&mut self, options: Option<xz::Options>) -> io::Result<()> {
+        if options.is_some() && self.codec != Codec::Xz {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "xz options are only supported by the xz codec!"));
+        }
+        self.xz_options = options;
+        Ok(())
+    }
+
+    pub fn xz_options(&self) -> Option<&xz::Options> {
+        self.xz_options.as_ref()
+    }
+
+    // Effective xz preset in the notation of the xz tool, e.g. "9e", None for other codecs
+    pub fn preset(&self) -> Option<String> {
+        match (self.codec, self.extreme) {
+            (Codec::Xz, true) => Some(format!("{}e", self.level)),
+            (Codec::Xz, false) => Some(self.level.to_string()),
+            (Codec::Zstd, _) => None,
+        }
+    }
+
+    fn xz_preset(&self) -> u32 {
+        match self.extreme {
+            true => self.level | lzma_sys::LZMA_PRESET_EXTREME,
+            false => self.level,
+        }
+    }
+
+    // Every setting that affects the compressed output, e.g. "codec=xz,level=6,check=crc64"
+    pub fn parameters(&self) -> String {
+        match (self.codec, self.dictionary_id) {
+            (Codec::Xz, _) => {
+                let extreme = if self.extreme { ",extreme" } else { "" };
+                let options = self.xz_options.as_ref().map_or_else(String::new, |options| format!(",{}", options));
+                format!("codec=xz,level={}{}{},check=crc64", self.level, extreme, options)
+            },
+            (Codec::Zstd, None) => format!("codec=zstd,level={}", self.level),
+            (Codec::Zstd, Some(id)) => format!("codec=zstd,level={},dictionary={}", self.level, id),
+        }
+    }
+
+    // Bytes of memory the encoder uses to compress a record: for xz what liblzma allocates for the
+    // preset, for zstd the size of the compression context, which grows to the largest record so far
+    pub fn memory_usage(&mut self) -> u64 {
+        #[cfg(feature = "zstd")]
+        if let Some(compressor) = &mut self.zstd {
+            return compressor.context_mut().sizeof() as u64;
+        }
+        match &self.xz_options {
+            Some(options) => options.memory_usage(self.xz_preset()),
+            None => unsafe { lzma_sys::lzma_easy_encoder_memusage(self.xz_preset()) },
+        }
+    }
+
+    // Compresses input, appending to output
+    pub fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        #[cfg(feature = "zstd")]
+        if let Some(compressor) = &mut self.zstd {
+            output.extend_from_slice(&compressor.compress(input)?);
+            return Ok(());
+        }
+        if let Some(options) = &self.xz_options {
+            return options.compress(self.xz_preset(), input, output);
+        }
+        XzEncoder::new(input, self.xz_preset()).read_to_end(output)?;
+        Ok(())
+    }
+}
+
+// Decompresses records of one archive
+pub struct Decoder {
+    codec: Codec,
+    memlimit: Option<u64>, // Bytes of memory the decoder may use for a record
+    #[cfg(feature = "zstd")]
+    dictionary: Option<zstd::dict::DecoderDictionary<'static>>,
+}
+
+impl Decoder {
+    // Decoders for unavailable codecs can be created, every record they decompress fails with
+    // Codec::unavailable()
+    #[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+    pub fn new(codec: Codec, dictionary: Option<&Dictionary>) -> Decoder {
+        Decoder {
+            codec,
+            memlimit: None,
+            #[cfg(feature = "zstd")]
+            dictionary: dictionary.map(|dictionary| zstd::dict::DecoderDictionary::copy(dictionary.as_bytes())),
+        }
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    // Fails records whose decompression needs more than limit bytes of memory with OutOfMemory
+    // instead of allocating it, so a malicious or damaged record can't exhaust memory: the xz
+    // dictionary is checked against the limit, zstd windows are capped at the largest power of two
+    // within it
+    pub fn set_memlimit(&mut self, limit: Option<u64>) {
+        self.memlimit = limit;
+    }
+
+    pub fn memlimit(&self) -> Option<u64> {
+        self.memlimit
+    }
+
+    // Decompresses input directly into output, returns the number of bytes written
+    pub fn decompress<W: Write + ?Sized>(&self, input: &[u8], output: &mut W) -> io::Result<u64> {
+        let result = match self.codec {
+            Codec::Xz => match self.memlimit {
+                Some(limit) => io::copy(&mut XzDecoder::new_stream(input, Stream::new_stream_decoder(limit, 0)?), output),
+                None => io::copy(&mut XzDecoder::new(input), output),
+            },
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => {
+                let mut decoder = match &self.dictionary {
+                    Some(dictionary) => zstd::stream::read::Decoder::with_prepared_dictionary(input, dictionary)?,
+                    None => zstd::stream::read::Decoder::with_buffer(input)?,
+                };
+                if let Some(limit) = self.memlimit {
+                    decoder.window_log_max((63 - limit.max(1).leading_zeros()).clamp(10, 31))?;
+                }
+                io::copy(&mut decoder, output)
+            },
+            #[cfg(not(feature = "zstd"))]
+            Codec::Zstd => Err(self.codec.unavailable()),
+        };
+        match (result, self.memlimit) {
+            (Err(e), Some(limit)) if is_memlimit_error(&e) => Err(memlimit_exceeded(limit)),
+            (result, _) => result,
+        }
+    }
+}
+
+// Error for data that needs more than limit bytes of memory to be held or decompressed
+pub fn memlimit_exceeded(limit: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::OutOfMemory, format!("Record needs more than the memory limit of {} bytes!", limit))
+}
+
+// True for the errors xz and zstd report for data exceeding their memory limit
+fn is_memlimit_error(e: &io::Error) -> bool {
+    let message = e.to_string();
+    message.contains("memory limit") || message.contains("too much memory")
+}
@@ -0,0 +1,240 @@
+// Encryption of archives with AES-256-GCM, keyed by a passphrase. An encrypted archive starts with
+// a header holding the Argon2id salt and parameters the key is derived with, which the first index
+// entry (the header end) skips. Every compressed record, the metadata and the compressed index are
+// sealed on their own, each with a random nonce stored in front of it and the GCM tag behind it,
+// so records can still be read one at a time; the framing of the footer stays readable. Record
+// sizes and the number of records are not hidden. Every block is authenticated together with what
+// it is, records with their index, so blocks can't be swapped or records reordered unnoticed.
+//
+// Header layout: MAGIC, the salt, the Argon2id memory cost (KiB), time cost and parallelism as
+// little endian u32s, and a SHA-256 digest of the key telling a wrong passphrase from damage.
+// Headers asking for costs above MAX_MEMORY_COST, MAX_TIME_COST or MAX_PARALLELISM are rejected
+// instead of spending that much memory or time deriving the key.
+
+use std::{
+    convert::TryInto,
+    fs::File,
+    io,
+    sync::{Mutex, OnceLock}
+};
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, Payload}
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use sha2::{Digest, Sha256};
+use crate::archive::read_exact_at;
+
+pub const MAGIC: [u8; 8] = *b"CMZ\0ENC1";
+pub const HEADER_SIZE: u64 = 8 + SALT_SIZE as u64 + 12 + 32;
+pub const ALGORITHM: &str = "aes-256-gcm";
+// Nonce and tag added to every sealed block
+pub const OVERHEAD: u64 = (NONCE_SIZE + 16) as u64;
+// Passphrase environment variable read unless another source is set
+pub const PASSWORD_VAR: &str = "CMZIP_PASSWORD";
+
+const SALT_SIZE: usize = 16;
+pub const NONCE_SIZE: usize = 12;
+// Argon2id parameters of new archives, the minimum recommended by RFC 9106 for memory constrained use
+const MEMORY_COST: u32 = 19 * 1024;
+const TIME_COST: u32 = 2;
+const PARALLELISM: u32 = 1;
+// Highest Argon2id parameters accepted from headers, 1 GiB of memory
+pub const MAX_MEMORY_COST: u32 = 1024 * 1024;
+pub const MAX_TIME_COST: u32 = 16;
+pub const MAX_PARALLELISM: u32 = 16;
+
+type HeaderBytes = [u8; HEADER_SIZE as usize];
+
+// Block of an archive that is sealed on its own, bound to it as associated data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Block {
+    Record(usize),
+    Metadata,
+    Index,
+}
+
+impl Block {
+    fn associated_data(self) -> Vec<u8> {
+        match self {
+            Block::Record(i) => [&b"record"[..], &(i as u64).to_le_bytes()].concat(),
+            Block::Metadata => b"metadata".to_vec(),
+            Block::Index => b"index".to_vec(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Key {
+    cipher: Aes256Gcm,
+    header: HeaderBytes,
+}
+
+impl Key {
+    // Derives the key of a new archive from passphrase with a random salt
+    pub fn generate(passphrase: &str) -> io::Result<Key> {
+        let mut salt = [0u8; SALT_SIZE];
+        getrandom::getrandom(&mut salt).map_err(io::Error::other)?;
+        let key = derive(passphrase, &salt, MEMORY_COST, TIME_COST, PARALLELISM)?;
+        let mut header: HeaderBytes = [0; HEADER_SIZE as usize];
+        header[..8].copy_from_slice(&MAGIC);
+        header[8..24].copy_from_slice(&salt);
+        header[24..28].copy_from_slice(&MEMORY_COST.to_le_bytes());
+        header[28..32].copy_from_slice(&TIME_COST.to_le_bytes());
+        header[32..36].copy_from_slice(&PARALLELISM.to_le_bytes());
+        header[36..].copy_from_slice(&check(&key));
+        Ok(Key { cipher: Aes256Gcm::new(&key.into()), header })
+    }
+
+    // Key of the archive starting with header, fails if passphrase is wrong
+    pub fn from_header(header: &[u8], passphrase: &str) -> io::Result<Key> {
+        let header: HeaderBytes = header.try_into().ok().filter(|header: &HeaderBytes| header[..8] == MAGIC)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Encryption header is corrupt!"))?;
+        let number = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+        let (memory_cost, time_cost, parallelism) = (number(24), number(28), number(32));
+        if memory_cost > MAX_MEMORY_COST || time_cost > MAX_TIME_COST || parallelism > MAX_PARALLELISM {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "Encryption header asks for Argon2id memory cost {} KiB, time cost {} and parallelism {}, more than the {} KiB, {} and {} allowed!",
+                memory_cost, time_cost, parallelism, MAX_MEMORY_COST, MAX_TIME_COST, MAX_PARALLELISM)));
+        }
+        let key = derive(passphrase, &header[8..24], memory_cost, time_cost, parallelism)?;
+        if check(&key) != header[36..] {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Wrong passphrase for encrypted archive!"));
+        }
+        Ok(Key { cipher: Aes256Gcm::new(&key.into()), header })
+    }
+
+    // Header written in front of the records of archives encrypted with this key
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    // Encrypts data stored as block, returning the nonce, the ciphertext and the tag
+    pub fn seal(&self, data: &[u8], block: Block) -> io::Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        getrandom::getrandom(&mut nonce).map_err(io::Error::other)?;
+        let sealed = self.cipher.encrypt(Nonce::from_slice(&nonce), Payload { msg: data, aad: &block.associated_data() })
+            .map_err(|_| io::Error::other("Encryption failed!"))?;
+        let mut output: Vec<u8> = Vec::with_capacity(NONCE_SIZE + sealed.len());
+        output.extend_from_slice(&nonce);
+        output.extend_from_slice(&sealed);
+        Ok(output)
+    }
+
+    // Decrypts data sealed by seal() as block, failing if it was changed or sealed as another block
+    pub fn open(&self, data: &[u8], block: Block) -> io::Result<Vec<u8>> {
+        let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "Encrypted data is corrupt!");
+        if data.len() < OVERHEAD as usize {
+            return Err(corrupt());
+        }
+        let (nonce, sealed) = data.split_at(NONCE_SIZE);
+        self.cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad: &block.associated_data() }).map_err(|_| corrupt())
+    }
+}
+
+// Key of the archive in input if it is encrypted, with the passphrase from passphrase()
+pub(crate) fn key_of(input: &File, file_size: u64) -> io::Result<Option<Key>> {
+    if file_size < HEADER_SIZE {
+        return Ok(None);
+    }
+    let mut header: HeaderBytes = [0; HEADER_SIZE as usize];
+    read_exact_at(input, &mut header, 0)?;
+    if header[..8] != MAGIC {
+        return Ok(None);
+    }
+    // Keys are cached, deriving one takes long on purpose and commands open archives repeatedly
+    static KEYS: Mutex<Vec<Key>> = Mutex::new(Vec::new());
+    if let Some(key) = KEYS.lock().unwrap().iter().find(|key| key.header == header) {
+        return Ok(Some(key.clone()));
+    }
+    let passphrase = passphrase().ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied,
+        format!("Archive is encrypted, set {} to its passphrase!", PASSWORD_VAR)))?;
+    let key = Key::from_header(&header, &passphrase)?;
+    KEYS.lock().unwrap().push(key.clone());
+    Ok(Some(key))
+}
+
+static SOURCE: OnceLock<Box<dyn Fn() -> Option<String> + Send + Sync>> = OnceLock::new();
+
+// Sets where the passphrase of encrypted archives comes from, e.g. a prompt, instead of
+// PASSWORD_VAR. Only the first call has an effect.
+pub fn set_passphrase_source<F: Fn() -> Option<String> + Send + Sync + 'static>(source: F) {
+    let _ = SOURCE.set(Box::new(source));
+}
+
+pub fn passphrase() -> Option<String> {
+    match SOURCE.get() {
+        Some(source) => source(),
+        None => std::env::var(PASSWORD_VAR).ok(),
+    }
+}
+
+fn derive(passphrase: &str, salt: &[u8], memory_cost: u32, time_cost: u32, parallelism: u32) -> io::Result<[u8; 32]> {
+    let invalid = |e: argon2::Error| io::Error::new(io::ErrorKind::InvalidData, format!("Encryption header is corrupt: {}", e));
+    let params = Params::new(memory_cost, time_cost, parallelism, Some(32)).map_err(invalid)?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params).hash_password_into(passphrase.as_bytes(), salt, &mut key).map_err(invalid)?;
+    Ok(key)
+}
+
+fn check(key: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"cmzip key check\n");
+    hasher.update(key);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    #[test]
+    fn sealed_blocks_round_trip() {
+        let key = Key::generate(PASSPHRASE).unwrap();
+        for (data, block) in [(&b"record"[..], Block::Record(7)), (b"", Block::Record(0)), (b"metadata", Block::Metadata), (b"index", Block::Index)] {
+            let sealed = key.seal(data, block).unwrap();
+            assert_eq!(sealed.len(), data.len() + OVERHEAD as usize);
+            assert_eq!(key.open(&sealed, block).unwrap(), data);
+        }
+        // The same key again from the header, as readers derive it
+        let sealed = key.seal(b"record", Block::Record(1)).unwrap();
+        let reopened = Key::from_header(key.header(), PASSPHRASE).unwrap();
+        assert_eq!(reopened.open(&sealed, Block::Record(1)).unwrap(), b"record");
+    }
+
+    #[test]
+    fn moved_or_changed_block_is_rejected() {
+        let key = Key::generate(PASSPHRASE).unwrap();
+        let sealed = key.seal(b"record", Block::Record(3)).unwrap();
+        for block in [Block::Record(4), Block::Record(usize::MAX), Block::Metadata, Block::Index] {
+            assert_eq!(key.open(&sealed, block).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+        let mut changed = sealed.clone();
+        *changed.last_mut().unwrap() ^= 1;
+        assert_eq!(key.open(&changed, Block::Record(3)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(key.open(&sealed[..OVERHEAD as usize - 1], Block::Record(3)).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let key = Key::generate(PASSPHRASE).unwrap();
+        assert_eq!(Key::from_header(key.header(), "wrong").err().unwrap().kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn oversized_argon2_costs_are_rejected() {
+        let key = Key::generate(PASSPHRASE).unwrap();
+        // Rejected before deriving the key, which would take the memory or time asked for
+        for (at, cost) in [(24, MAX_MEMORY_COST + 1), (28, MAX_TIME_COST + 1), (32, MAX_PARALLELISM + 1), (24, u32::MAX)] {
+            let mut header = key.header().to_vec();
+            header[at..at + 4].copy_from_slice(&cost.to_le_bytes());
+            assert_eq!(Key::from_header(&header, PASSPHRASE).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        }
+        let mut header = key.header().to_vec();
+        header[0] ^= 1;
+        assert_eq!(Key::from_header(&header, PASSPHRASE).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(Key::from_header(&key.header()[1..], PASSPHRASE).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,242 @@
+// Error reports pointing at the record that caused them: its index, molecule name, byte offset
+// and the lines around the problem, so the record can be found in the source data.
+//
+//   × Error extracting record!
+//   │ corrupt xz stream
+//    ╭─[record 12 "LIG0012" at byte 10342]
+//  1 │ LIG0012
+//  2 │   RDKit          3D
+//    ·   ╰── decompression stopped here
+//    ╰────
+//   help: the archive is damaged, re-create it from the source SD file
+//
+// With --json the same report is written as a single line JSON object instead, its code tells
+// failure classes apart: corrupt_record, corrupt_data, suspicious_record, memory_limit,
+// record_too_large, checksum_mismatch, partial_extraction, output_limit, disk_full, not_found,
+// permission_denied, invalid_argument, io_error, error and panic. The exit status tells the broader
+// classes apart without parsing stderr, see exit_status.
+
+use std::{
+    env,
+    io::{self, IsTerminal},
+    process,
+    sync::atomic::{AtomicBool, Ordering}
+};
+use crate::json;
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+// Switches every report to JSON, panics included
+pub fn set_json() {
+    JSON.store(true, Ordering::Relaxed);
+    std::panic::set_hook(Box::new(|info| {
+        let message = match (info.payload().downcast_ref::<&str>(), info.payload().downcast_ref::<String>()) {
+            (Some(message), _) => message.to_string(),
+            (None, Some(message)) => message.clone(),
+            (None, None) => "Unknown error!".to_string(),
+        };
+        Diagnostic::error(&message).code("panic").emit();
+    }));
+}
+
+const SNIPPET_LINES: usize = 6;
+
+// Exit statuses of failed modes
+pub const EXIT_FAILURE: i32 = 1; // Any other failure, e.g. a full disk or an unreadable file
+pub const EXIT_USAGE: i32 = 2; // Invalid arguments, nothing was done
+pub const EXIT_NOT_FOUND: i32 = 3; // An input file doesn't exist
+pub const EXIT_CORRUPT: i32 = 4; // The archive or records in it are damaged or suspicious
+pub const EXIT_MISMATCH: i32 = 5; // A checksum, manifest digest or signature doesn't match
+pub const EXIT_PARTIAL: i32 = 6; // Some records were skipped or left out of the output
+
+// Exit status for a report with code
+pub fn exit_status(code: &str) -> i32 {
+    match code {
+        "invalid_argument" => EXIT_USAGE,
+        "not_found" => EXIT_NOT_FOUND,
+        "corrupt_record" | "corrupt_data" | "truncated_record" | "suspicious_record" | "memory_limit" | "record_too_large" => EXIT_CORRUPT,
+        "checksum_mismatch" => EXIT_MISMATCH,
+        "partial_extraction" | "output_limit" => EXIT_PARTIAL,
+        _ => EXIT_FAILURE,
+    }
+}
+
+pub struct Diagnostic {
+    code: &'static str,
+    message: String,
+    cause: Option<String>,
+    record: Option<usize>,
+    name: Option<String>,
+    offset: Option<u64>,
+    snippet: Vec<String>,
+    first_line: usize, // Line number of the first snippet line within the record, starting at 1
+    marker: Option<(usize, String)>, // Snippet line the label points at, with the label
+    help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: &str) -> Diagnostic {
+        Diagnostic {
+            code: "error", message: message.to_string(), cause: None, record: None, name: None, offset: None,
+            snippet: Vec::new(), first_line: 1, marker: None, help: None,
+        }
+    }
+
+    // Report for a failed I/O operation, classified by the kind of error
+    pub fn io(message: &str, error: &io::Error) -> Diagnostic {
+        let code = match error.kind() {
+            _ if error.raw_os_error() == Some(28) => "disk_full", // ENOSPC
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => "disk_full",
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => "corrupt_data",
+            io::ErrorKind::NotFound => "not_found",
+            io::ErrorKind::PermissionDenied => "permission_denied",
+            io::ErrorKind::InvalidInput => "invalid_argument",
+            io::ErrorKind::Unsupported => "unsupported",
+            io::ErrorKind::OutOfMemory => "memory_limit",
+            io::ErrorKind::FileTooLarge => "record_too_large",
+            _ => "io_error",
+        };
+        Diagnostic::error(message).code(code).cause(error)
+    }
+
+    pub fn code(mut self, code: &'static str) -> Diagnostic {
+        self.code = code;
+        self
+    }
+
+    pub fn get_code(&self) -> &'static str {
+        self.code
+    }
+
+    pub fn cause<E: ToString>(mut self, cause: E) -> Diagnostic {
+        self.cause = Some(cause.to_string());
+        self
+    }
+
+    pub fn record(mut self, i: usize) -> Diagnostic {
+        self.record = Some(i);
+        self
+    }
+
+    pub fn name(mut self, name: String) -> Diagnostic {
+        if !name.is_empty() {
+            self.name = Some(name);
+        }
+        self
+    }
+
+    pub fn offset(mut self, offset: u64) -> Diagnostic {
+        self.offset = Some(offset);
+        self
+    }
+
+    // Shows the first lines of data, the start of the record
+    pub fn head(mut self, data: &[u8]) -> Diagnostic {
+        self.snippet = String::from_utf8_lossy(data).lines().take(SNIPPET_LINES).map(str::to_string).collect();
+        self.first_line = 1;
+        self
+    }
+
+    // Shows the last lines of data and points the label at the final one
+    pub fn tail(mut self, data: &[u8], label: &str) -> Diagnostic {
+        let lines: Vec<String> = String::from_utf8_lossy(data).lines().map(str::to_string).collect();
+        let skip = lines.len().saturating_sub(SNIPPET_LINES);
+        self.snippet = lines[skip..].to_vec();
+        self.first_line = skip + 1;
+        self.marker = self.snippet.len().checked_sub(1).map(|last| (last, label.to_string()));
+        self
+    }
+
+    pub fn help(mut self, help: &str) -> Diagnostic {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    // Prints the report to stderr, colored when stderr is a terminal and NO_COLOR is not set
+    pub fn emit(&self) {
+        if JSON.load(Ordering::Relaxed) {
+            eprintln!("{}", self.to_json());
+        } else {
+            let color = io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+            eprint!("{}", self.render(color));
+        }
+    }
+
+    // Prints the report of a problem that doesn't stop the mode, unless warnings are turned off by
+    // --quiet or CMZIP_LOG
+    pub fn warn(&self) {
+        if log::log_enabled!(log::Level::Warn) {
+            self.emit();
+        }
+    }
+
+    // Prints the report and exits with the status of its code
+    pub fn exit(&self) -> ! {
+        self.emit();
+        process::exit(exit_status(self.code));
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![
+            format!("\"code\":{}", json::string(self.code)),
+            format!("\"message\":{}", json::string(&self.message)),
+        ];
+        if let Some(cause) = &self.cause {
+            fields.push(format!("\"cause\":{}", json::string(cause)));
+        }
+        if let Some(i) = self.record {
+            fields.push(format!("\"record\":{}", i));
+        }
+        if let Some(name) = &self.name {
+            fields.push(format!("\"name\":{}", json::string(name)));
+        }
+        if let Some(offset) = self.offset {
+            fields.push(format!("\"offset\":{}", offset));
+        }
+        format!("{{\"error\":{{{}}}}}", fields.join(","))
+    }
+
+    pub fn render(&self, color: bool) -> String {
+        let paint = |code: &str, text: &str| if color { format!("\x1b[{}m{}\x1b[0m", code, text) } else { text.to_string() };
+        let mut out = format!("  {} {}\n", paint("31;1", "×"), paint("1", &self.message));
+        if let Some(cause) = &self.cause {
+            out += &format!("  {} {}\n", paint("31", "│"), cause);
+        }
+
+        let width = (self.first_line + self.snippet.len()).to_string().len();
+        let gutter = " ".repeat(width + 1);
+        let mut location: Vec<String> = Vec::new();
+        if let Some(i) = self.record {
+            location.push(format!("record {}", i));
+        }
+        if let Some(name) = &self.name {
+            location.push(format!("{:?}", name));
+        }
+        if let Some(offset) = self.offset {
+            location.push(format!("at byte {}", offset));
+        }
+        if self.snippet.is_empty() {
+            if !location.is_empty() {
+                out += &format!("{}{}{}\n", gutter, paint("2", "──"), paint("36", &format!("[{}]", location.join(" "))));
+            }
+        } else {
+            out += &format!("{}{}{}\n", gutter, paint("2", "╭─"), paint("36", &format!("[{}]", location.join(" "))));
+            for (n, line) in self.snippet.iter().enumerate() {
+                out += &format!("{} {} {}\n", paint("2", &format!("{:>width$}", self.first_line + n, width = width)), paint("2", "│"), line);
+                if let Some((marker, label)) = &self.marker {
+                    if *marker == n {
+                        let indent = line.len() - line.trim_start().len();
+                        let label = format!("{}╰── {}", " ".repeat(indent), label);
+                        out += &format!("{}{} {}\n", gutter, paint("2", "·"), paint("35;1", &label));
+                    }
+                }
+            }
+            out += &format!("{}{}\n", gutter, paint("2", "╰────"));
+        }
+
+        if let Some(help) = &self.help {
+            out += &format!("  {} {}\n", paint("36", "help:"), help);
+        }
+        out
+    }
+}
@@ -0,0 +1,69 @@
+// zstd dictionaries shared by the records of one or more archives. Archives reference their
+// dictionary by id and SHA-256 digest, so readers can verify they were given the right one.
+
+use std::{
+    fs,
+    io,
+    path::Path
+};
+use sha2::{Digest, Sha256};
+
+const ZSTD_DICT_MAGIC: [u8; 4] = [0x37, 0xA4, 0x30, 0xEC];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dictionary {
+    data: Vec<u8>,
+}
+
+impl Dictionary {
+    pub fn new(data: Vec<u8>) -> Dictionary {
+        Dictionary { data }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Dictionary> {
+        Ok(Dictionary::new(fs::read(path)?))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, &self.data)
+    }
+
+    // Trains a dictionary of at most max_size bytes on sample records
+    #[cfg(feature = "zstd")]
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> io::Result<Dictionary> {
+        Ok(Dictionary::new(zstd::dict::from_samples(samples, max_size)?))
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    pub fn train<S: AsRef<[u8]>>(_samples: &[S], _max_size: usize) -> io::Result<Dictionary> {
+        Err(crate::codec::Codec::Zstd.unavailable())
+    }
+
+    // Id from the zstd dictionary header, 0 for raw content dictionaries
+    pub fn id(&self) -> u32 {
+        if self.data.len() >= 8 && self.data[..4] == ZSTD_DICT_MAGIC {
+            let mut id = [0u8; 4];
+            id.copy_from_slice(&self.data[4..8]);
+            u32::from_le_bytes(id)
+        } else {
+            0
+        }
+    }
+
+    // Hex encoded SHA-256 of the dictionary contents
+    pub fn digest(&self) -> String {
+        Sha256::digest(&self.data).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
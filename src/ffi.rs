@@ -0,0 +1,95 @@
+// C interface for reading archives, enabled with the cdylib feature so CmDock can read
+// ligands straight from .cmz files. Declarations are in include/cmzip.h.
+
+use std::{
+    ffi::CStr,
+    os::raw::{c_char, c_int},
+    ptr
+};
+use crate::archive::Archive;
+
+pub const CMZ_OK: c_int = 0;
+pub const CMZ_ERR_ARGUMENT: c_int = -1; // Null pointer passed
+pub const CMZ_ERR_RANGE: c_int = -2; // Record index out of range
+pub const CMZ_ERR_IO: c_int = -3; // Reading or decompressing failed
+
+/// Opens an archive, returns null if the file can't be opened or has no valid footer.
+///
+/// # Safety
+/// path must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn cmz_open(path: *const c_char) -> *mut Archive {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => path,
+        Err(_) => return ptr::null_mut(),
+    };
+    match Archive::open(path) {
+        Ok(archive) => Box::into_raw(Box::new(archive)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Number of records in the archive, 0 for a null archive.
+///
+/// # Safety
+/// archive must be null or a pointer returned by cmz_open() that was not closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn cmz_record_count(archive: *const Archive) -> usize {
+    match archive.as_ref() {
+        Some(archive) => archive.len(),
+        None => 0,
+    }
+}
+
+/// Decompresses record i into a newly allocated, NUL-terminated buffer. On success *data and *len
+/// (excluding the terminator) are set and the buffer must be released with cmz_free_record().
+///
+/// # Safety
+/// archive must be a pointer returned by cmz_open() that was not closed yet, data and len must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn cmz_read_record(archive: *mut Archive, i: usize, data: *mut *mut c_char, len: *mut usize) -> c_int {
+    let archive = match archive.as_ref() {
+        Some(archive) => archive,
+        None => return CMZ_ERR_ARGUMENT,
+    };
+    if data.is_null() || len.is_null() {
+        return CMZ_ERR_ARGUMENT;
+    }
+    if i >= archive.len() {
+        return CMZ_ERR_RANGE;
+    }
+    let mut record = match archive.read_record(i) {
+        Ok(record) => record,
+        Err(_) => return CMZ_ERR_IO,
+    };
+    *len = record.len();
+    record.push(0);
+    *data = Box::into_raw(record.into_boxed_slice()) as *mut c_char;
+    CMZ_OK
+}
+
+/// Releases a buffer returned by cmz_read_record().
+///
+/// # Safety
+/// data and len must be exactly as returned by cmz_read_record(), each buffer may only be freed once.
+#[no_mangle]
+pub unsafe extern "C" fn cmz_free_record(data: *mut c_char, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data as *mut u8, len + 1)));
+    }
+}
+
+/// Closes the archive and releases its resources.
+///
+/// # Safety
+/// archive must be null or a pointer returned by cmz_open() that was not closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn cmz_close(archive: *mut Archive) {
+    if !archive.is_null() {
+        drop(Box::from_raw(archive));
+    }
+}
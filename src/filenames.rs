@@ -0,0 +1,120 @@
+// Output file names of unzip --template, which writes every record to a file of its own named
+// after its index and molecule name, e.g. `ligands/{name}.sdf`. Molecule names come from vendor
+// libraries and may hold anything, so they are made safe to use as one path component first: path
+// separators and characters the filesystem rejects become `_`, and overlong names are cut. Under
+// Windows rules, also used on other systems with --windows-names for files read by Windows later,
+// that covers `<>:"/\|?*`, control characters, trailing dots and spaces and the reserved device
+// names (CON, PRN, AUX, NUL, COM0-9, LPT0-9 with any extension), and names differing only in case
+// are the same file. Records whose file name was already given get their index appended, so
+// duplicate names don't overwrite each other. On Windows, paths of MAX_PATH (260) characters or
+// more get the `\\?\` prefix that lifts the limit, for every output file.
+
+use std::{
+    collections::HashSet,
+    path::{Component, Path}
+};
+
+pub const PLACEHOLDERS: [&str; 2] = ["{index}", "{name}"];
+
+// Longest molecule name kept, in bytes, leaving room for the rest of the template within the
+// 255 byte limit of most filesystems
+const MAX_NAME: usize = 200;
+const RESERVED: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+
+pub struct Template {
+    template: String,
+    windows: bool,
+    used: HashSet<String>, // File names given so far, lowercase under Windows rules
+}
+
+impl Template {
+    pub fn new(template: &str, windows: bool) -> Result<Template, String> {
+        if !PLACEHOLDERS.iter().any(|placeholder| template.contains(placeholder)) {
+            return Err(format!("Template {} holds neither {{index}} nor {{name}}, every record would be written to the same file!", template));
+        }
+        Ok(Template { template: template.to_string(), windows, used: HashSet::new() })
+    }
+
+    // File name of record i named name
+    pub fn filename(&mut self, i: usize, name: &str) -> String {
+        let mut name = component(name, self.windows);
+        let mut filename = self.expand(i, &name);
+        while !self.used.insert(self.key(&filename)) {
+            name = format!("{}_{}", name, i);
+            filename = self.expand(i, &name);
+        }
+        long_path(filename)
+    }
+
+    fn expand(&self, i: usize, name: &str) -> String {
+        self.template.replace("{index}", &i.to_string()).replace("{name}", name)
+    }
+
+    fn key(&self, filename: &str) -> String {
+        match self.windows {
+            true => filename.to_lowercase(),
+            false => filename.to_string(),
+        }
+    }
+}
+
+// Molecule name made safe to use as one path component
+pub fn component(name: &str, windows: bool) -> String {
+    let illegal = |c: char| match windows {
+        true => c < ' ' || "<>:\"/\\|?*".contains(c),
+        false => c == '/' || c == '\0',
+    };
+    let mut safe: String = name.trim().chars().map(|c| if illegal(c) { '_' } else { c }).collect();
+    if safe.len() > MAX_NAME {
+        let mut end = MAX_NAME;
+        while !safe.is_char_boundary(end) {
+            end -= 1;
+        }
+        safe.truncate(end);
+    }
+    if windows {
+        safe.truncate(safe.trim_end_matches(['.', ' ']).len());
+        if is_reserved(&safe) {
+            safe.push('_');
+        }
+    }
+    match safe.as_str() {
+        "" | "." | ".." => "unnamed".to_string(),
+        _ => safe,
+    }
+}
+
+// Device names Windows reserves whatever their extension, e.g. NUL.sdf
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap().trim_end().to_ascii_uppercase();
+    let numbered = |prefix: &str| stem.len() == 4 && stem.starts_with(prefix) && stem.as_bytes()[3].is_ascii_digit();
+    RESERVED.contains(&stem.as_str()) || numbered("COM") || numbered("LPT")
+}
+
+// Directory that has to exist before path is created, None for files in the current directory
+// and right under a root: `/`, `C:\`, `C:` (the current directory of drive C) or a share
+// `\\server\share\`, which can't be created. Windows paths mixing `/` and `\` are split at both.
+pub fn parent_dir(path: &Path) -> Option<&Path> {
+    path.parent().filter(|dir| dir.components().any(|component| !matches!(component, Component::Prefix(_) | Component::RootDir)))
+}
+
+#[cfg(windows)]
+pub fn long_path(filename: String) -> String {
+    const MAX_PATH: usize = 260;
+    if filename.len() < MAX_PATH || filename.starts_with(r"\\?\") {
+        return filename;
+    }
+    let absolute = match std::path::absolute(&filename) {
+        Ok(absolute) => absolute.to_string_lossy().replace('/', "\\"),
+        Err(_) => return filename,
+    };
+    match absolute.strip_prefix(r"\\") {
+        Some(unc) => format!(r"\\?\UNC\{}", unc),
+        None => format!(r"\\?\{}", absolute),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn long_path(filename: String) -> String {
+    filename
+}
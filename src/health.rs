@@ -0,0 +1,74 @@
+// Health summary of an archive, stored in its metadata when the archive is finished, so inventory
+// and monitoring tools get the record count, the spread of compressed record sizes and a digest of
+// the index from one fetch of the footer, without reading any record. Written as one line of
+// key=value pairs, e.g. "records=25 min_size=220 max_size=276 total_size=6208 checksum=sha256
+// index_digest=...".
+
+use sha2::{Digest, Sha256};
+
+pub const KEY: &str = "health";
+pub const CHECKSUM: &str = "sha256";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Health {
+    pub records: u64,
+    pub min_size: u64, // Compressed record sizes, 0 without records
+    pub max_size: u64,
+    pub total_size: u64,
+    pub checksum: String, // Algorithm of index_digest
+    pub index_digest: String,
+}
+
+impl Health {
+    // Summary of an archive index, whose first entry is the 0 in front of the first record
+    pub fn of(index: &[u64]) -> Health {
+        let sizes = index.get(1..).unwrap_or(&[]);
+        Health {
+            records: sizes.len() as u64,
+            min_size: sizes.iter().copied().min().unwrap_or(0),
+            max_size: sizes.iter().copied().max().unwrap_or(0),
+            total_size: sizes.iter().sum(),
+            checksum: CHECKSUM.to_string(),
+            index_digest: Health::index_digest(index),
+        }
+    }
+
+    // Digest of the index entries as little endian u64s, the same for every index layout
+    pub fn index_digest(index: &[u64]) -> String {
+        let mut hasher = Sha256::new();
+        for size in index {
+            hasher.update(size.to_le_bytes());
+        }
+        hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    // Whether index is the index the summary was written for
+    pub fn matches(&self, index: &[u64]) -> bool {
+        self.checksum == CHECKSUM && self.index_digest == Health::index_digest(index)
+    }
+
+    pub fn encode(&self) -> String {
+        format!("records={} min_size={} max_size={} total_size={} checksum={} index_digest={}",
+            self.records, self.min_size, self.max_size, self.total_size, self.checksum, self.index_digest)
+    }
+
+    // None if a field is missing or malformed, unknown fields are skipped
+    pub fn decode(text: &str) -> Option<Health> {
+        let field = |key: &str| text.split_whitespace().find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='));
+        let number = |key: &str| field(key)?.parse::<u64>().ok();
+        Some(Health {
+            records: number("records")?,
+            min_size: number("min_size")?,
+            max_size: number("max_size")?,
+            total_size: number("total_size")?,
+            checksum: field("checksum")?.to_string(),
+            index_digest: field("index_digest")?.to_string(),
+        })
+    }
+
+    // Upper bound of encode(), for the footer size bound of the writer
+    pub(crate) fn encoded_bound() -> usize {
+        let max = Health { records: u64::MAX, min_size: u64::MAX, max_size: u64::MAX, total_size: u64::MAX, checksum: CHECKSUM.to_string(), index_digest: "0".repeat(64) };
+        max.encode().len()
+    }
+}
@@ -0,0 +1,42 @@
+// Audit log of the cmzip commands that wrote an archive, kept in its metadata so the provenance of a
+// long-lived archive can be reconstructed with cmzip history. One line per command, oldest first:
+// the UTC time, the cmzip version and the command line, e.g.
+// "2026-10-14T09:30:00Z cmzip 0.1.0 delete -i ligands.cmz -r 5 --in-place". Commands copying
+// records into a new archive carry the log of their input over.
+
+use std::io::Write;
+use cmzip::{Metadata, Writer};
+use crate::{shell_quote, unix_time};
+
+pub const KEY: &str = "history";
+
+// Adds the running command to the log of the archive being written
+pub fn append<W: Write>(writer: &mut Writer<W>) {
+    let command: Vec<String> = std::env::args().skip(1).map(|arg| shell_quote(&arg)).collect();
+    let entry = format!("{} cmzip {} {}", utc(unix_time()), env!("CARGO_PKG_VERSION"), command.join(" "));
+    let log = match writer.metadata_mut().get_str(KEY) {
+        Some(log) if !log.is_empty() => format!("{}\n{}", log, entry),
+        _ => entry,
+    };
+    writer.metadata_mut().set(KEY, log);
+}
+
+pub fn entries(metadata: &Metadata) -> Vec<&str> {
+    metadata.get_str(KEY).unwrap_or("").lines().filter(|line| !line.trim().is_empty()).collect()
+}
+
+// Formats seconds since the Unix epoch as an ISO 8601 UTC time like 2026-10-14T09:30:00Z
+fn utc(time: u64) -> String {
+    let (days, seconds) = (time / 86400, time % 86400);
+    // Civil date of a day count, counted in 400 year eras starting on March 1st
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, seconds / 3600, seconds % 3600 / 60, seconds % 60)
+}
@@ -0,0 +1,69 @@
+// On-disk cache of decoded archive indexes, used by Archive::open_cached(). Decompressing the
+// index of an archive with tens of millions of records takes seconds, which scripts that open the
+// same archive over and over would otherwise pay every time.
+//
+// Entries live in $XDG_CACHE_HOME/cmzip (~/.cache/cmzip, %LOCALAPPDATA%\cmzip on Windows), named
+// after a digest of the archive's canonical path, size, modification time and compressed index
+// size, so rewriting an archive never hits a stale entry. Setting CMZIP_NO_CACHE disables it.
+
+use std::{
+    convert::TryInto,
+    env,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH
+};
+use sha2::{Digest, Sha256};
+
+// Smaller indexes decompress faster than the cache can be read
+pub const MIN_RECORDS: usize = 100_000;
+
+pub fn enabled() -> bool {
+    env::var_os("CMZIP_NO_CACHE").is_none()
+}
+
+pub fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("cmzip"));
+    }
+    if cfg!(windows) {
+        if let Some(dir) = env::var_os("LOCALAPPDATA") {
+            return Some(PathBuf::from(dir).join("cmzip"));
+        }
+    }
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache").join("cmzip"))
+}
+
+// Cache key of the archive at path, None if its modification time is not available
+pub fn key(path: &Path, file: &File, index_size: u64) -> Option<String> {
+    let metadata = file.metadata().ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    let mut hasher = Sha256::new();
+    hasher.update(fs::canonicalize(path).ok()?.to_string_lossy().as_bytes());
+    hasher.update(metadata.len().to_le_bytes());
+    hasher.update(modified.as_nanos().to_le_bytes());
+    hasher.update(index_size.to_le_bytes());
+    Some(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub fn load(key: &str) -> Option<Vec<u64>> {
+    let data = fs::read(cache_dir()?.join(format!("{}.idx", key))).ok()?;
+    if data.is_empty() || !data.len().is_multiple_of(8) {
+        return None;
+    }
+    Some(data.chunks(8).map(|value| u64::from_le_bytes(value.try_into().unwrap())).collect())
+}
+
+// Stores the index under key, written to a temporary file first so readers never see half of it
+pub fn store(key: &str, index: &[u64]) -> io::Result<()> {
+    let dir = cache_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No cache directory!"))?;
+    fs::create_dir_all(&dir)?;
+    let mut data: Vec<u8> = Vec::with_capacity(index.len() * 8);
+    for value in index {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    let tmp = dir.join(format!("{}.idx.{}.tmp", key, std::process::id()));
+    fs::write(&tmp, &data)?;
+    fs::rename(&tmp, dir.join(format!("{}.idx", key)))
+}
@@ -0,0 +1,139 @@
+// Input files of cmzip zip, given as several -i values or as globs like 'batch_*.sdf'. The files are
+// split into records one after another, so a file lacking its last delimiter doesn't run into the
+// next one, and the archive records taken from each file are kept as provenance in the metadata.
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, Seek, SeekFrom}
+};
+use cmzip::SdfRecords;
+use crate::{OrExit, Split, diagnostic::Diagnostic, invalid_argument, next_record, sdf_records};
+
+pub const KEY: &str = "sources";
+
+pub struct Inputs<'a> {
+    paths: Vec<String>,
+    split: Split<'a>,
+    records: Option<SdfRecords<BufReader<File>>>, // Of the file being read
+    file: usize,
+    offset: u64, // Of the next record in its file
+    admitted: Vec<usize>, // Records compressed of every file
+}
+
+impl<'a> Inputs<'a> {
+    // Exits if a file can't be opened, before any output is written
+    pub fn new(paths: Vec<String>, split: Split<'a>) -> Inputs<'a> {
+        for path in &paths {
+            File::open(path).or_exit(&format!("Unable to read {}!", path));
+        }
+        let admitted = vec![0; paths.len()];
+        Inputs { paths, split, records: None, file: 0, offset: 0, admitted }
+    }
+
+    // Next record of the inputs and its offset in its file, None after the last file
+    pub fn next_record(&mut self) -> Option<(u64, Vec<u8>)> {
+        while self.file < self.paths.len() {
+            if self.records.is_none() {
+                let path = &self.paths[self.file];
+                let file = File::open(path).or_exit(&format!("Unable to read {}!", path));
+                self.records = Some(sdf_records(BufReader::new(file), self.split));
+            }
+            if let Some(record) = next_record(self.records.as_mut().unwrap(), self.split) {
+                let offset = self.offset;
+                self.offset += record.len() as u64;
+                return Some((offset, record));
+            }
+            self.records = None;
+            self.file += 1;
+            self.offset = 0;
+        }
+        None
+    }
+
+    // Counts the record returned last as compressed
+    pub fn admitted(&mut self) {
+        self.admitted[self.file] += 1;
+    }
+
+    // File and offset the next record is read from, and the records compressed of every file
+    pub fn position(&self) -> (usize, u64, &[usize]) {
+        (self.file, self.offset, &self.admitted)
+    }
+
+    // Continues reading at a position returned by position(), for zip --resume
+    pub fn resume(&mut self, file: usize, offset: u64, admitted: &[usize]) -> Result<(), String> {
+        if file > self.paths.len() || admitted.len() != self.paths.len() {
+            return Err("Journal doesn't match the input files!".to_string());
+        }
+        self.admitted = admitted.to_vec();
+        self.file = file;
+        self.offset = offset;
+        self.records = None;
+        if let Some(path) = self.paths.get(file) {
+            let mut opened = File::open(path).or_exit(&format!("Unable to read {}!", path));
+            opened.seek(SeekFrom::Start(offset)).or_exit(&format!("Unable to read {}!", path));
+            self.records = Some(sdf_records(BufReader::new(opened), self.split));
+        }
+        Ok(())
+    }
+
+    // One line per file of the archive records taken from it, e.g. "0-24 batch_1.sdf", or "none"
+    // for files without any. Only the first records of the archive are counted, compression may
+    // have stopped at the size limit after records were handed to the workers.
+    pub fn provenance(&self, records: usize) -> String {
+        let mut start = 0;
+        let mut lines: Vec<String> = Vec::new();
+        for (path, &admitted) in self.paths.iter().zip(&self.admitted) {
+            let end = (start + admitted).min(records);
+            match end > start {
+                true => lines.push(format!("{}-{} {}", start, end - 1, path)),
+                false => lines.push(format!("none {}", path)),
+            }
+            start = end;
+        }
+        lines.join("\n")
+    }
+}
+
+// Files of -i values in the given order, the files matching a glob sorted by name. Wildcards (* and
+// ?) are only supported in the file name, not in the directories leading to it.
+pub fn expand<'v, I: Iterator<Item = &'v str>>(values: I) -> Vec<String> {
+    let mut paths: Vec<String> = Vec::new();
+    for value in values {
+        let (dir, pattern) = match value.rfind('/') {
+            Some(i) => (&value[..=i], &value[i + 1..]),
+            None => ("", value),
+        };
+        if !pattern.contains(['*', '?']) {
+            if dir.contains(['*', '?']) {
+                invalid_argument(&format!("Wildcards are only supported in file names, not in {}!", value));
+            }
+            paths.push(value.to_string());
+            continue;
+        }
+        let entries = fs::read_dir(if dir.is_empty() { "." } else { dir }).or_exit(&format!("Unable to read {}!", dir));
+        let mut matched: Vec<String> = entries.filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|kind| !kind.is_dir()))
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| matches(pattern.as_bytes(), name.as_bytes()))
+            .map(|name| format!("{}{}", dir, name))
+            .collect();
+        if matched.is_empty() {
+            Diagnostic::error(&format!("No file matches {}!", value)).code("not_found").exit();
+        }
+        matched.sort();
+        paths.append(&mut matched);
+    }
+    paths
+}
+
+// Whether name matches pattern, * standing for any run of characters and ? for one
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => matches(&pattern[1..], name) || (!name.is_empty() && matches(pattern, &name[1..])),
+        (Some(b'?'), Some(_)) => matches(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => matches(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
@@ -0,0 +1,254 @@
+// Intent records of in-place writes (a.cmz.intent for a.cmz): before an operation overwrites the
+// end of an archive, e.g. to append records, delete or replace some or rewrite the footer, the
+// bytes it overwrites are saved next to it. An interrupted operation leaves the intent record
+// behind, and the next in-place write opening the archive rolls it back to how it was before the
+// operation, so an archive is never left without a usable index. The record holds what is
+// overwritten, the footer when appending, also the records after the first one changed otherwise.
+//
+// Writers hold an exclusive advisory lock of the archive from before they recover it until they
+// close it, so a second writer fails instead of rolling back a write still going on. Reading the
+// archive never repairs it, only warns about the intent record, so archives with a stale one stay
+// readable from read-only media.
+//
+// Layout: MAGIC, the archive size before the operation, the offset the saved bytes start at, the
+// saved bytes up to the end of the archive and the SHA-256 of everything before it. A record
+// failing the digest was cut short before the operation started and is discarded.
+
+use std::{
+    convert::TryInto,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, prelude::*, SeekFrom},
+    path::{Path, PathBuf}
+};
+use sha2::{Digest, Sha256};
+use crate::archive::read_exact_at;
+
+pub const MAGIC: [u8; 8] = *b"CMZ\0INTN";
+
+pub fn path(archive: &Path) -> PathBuf {
+    let mut name = archive.as_os_str().to_owned();
+    name.push(".intent");
+    PathBuf::from(name)
+}
+
+// Opens archive for an in-place write, locked until the returned file is closed, and rolls back
+// an interrupted write of it first
+pub fn open<P: AsRef<Path>>(archive: P) -> io::Result<File> {
+    let archive = archive.as_ref();
+    let file = OpenOptions::new().read(true).write(true).open(archive)?;
+    match file.try_lock() {
+        Ok(()) => (),
+        Err(TryLockError::WouldBlock) => {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, format!("{} is being written by another process!", archive.display())));
+        },
+        Err(TryLockError::Error(e)) => return Err(e),
+    }
+    recover(archive, &file)?;
+    Ok(file)
+}
+
+// Warns if archive has an intent record, for opens that only read it
+pub fn check<P: AsRef<Path>>(archive: P) {
+    let archive = archive.as_ref();
+    if !path(archive).exists() {
+        return;
+    }
+    // A writer holds the lock while its intent record is pending, a stale one is left by a crash
+    match File::open(archive).map(|file| file.try_lock_shared()) {
+        Ok(Err(TryLockError::WouldBlock)) => log::warn!("{} is being written by another process, reading it as it is", archive.display()),
+        _ => log::warn!("{} has the intent record of an interrupted write, reading it as it is until it is changed in place again", archive.display()),
+    }
+}
+
+// Pending in-place write of an archive, finish() once the archive is complete again
+#[derive(Debug)]
+pub struct Intent {
+    archive: PathBuf,
+    record: File, // The intent record, read back by saved()
+    offset: u64, // Of the archive, where the saved bytes start
+    size: u64, // Of the archive before the operation
+}
+
+impl Intent {
+    // Saves the bytes of archive from offset on and syncs them to disk, before they are overwritten.
+    // file is the archive as returned by open(), which keeps it locked. The bytes are copied in
+    // chunks, so saving a large tail doesn't hold it in memory.
+    pub fn begin<P: AsRef<Path>>(archive: P, file: &File, offset: u64) -> io::Result<Intent> {
+        let archive = archive.as_ref();
+        let size = file.metadata()?.len();
+        let mut header: Vec<u8> = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&offset.to_le_bytes());
+
+        let mut record = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path(archive))?;
+        let mut hasher = Sha256::new();
+        hasher.update(&header);
+        record.write_all(&header)?;
+        let mut chunk: Vec<u8> = Vec::new();
+        let mut position = offset.min(size);
+        while position < size {
+            chunk.resize((size - position).min(CHUNK) as usize, 0);
+            read_exact_at(file, &mut chunk, position)?;
+            hasher.update(&chunk);
+            record.write_all(&chunk)?;
+            position += chunk.len() as u64;
+        }
+        record.write_all(&hasher.finalize())?;
+        record.sync_all()?;
+        Ok(Intent { archive: archive.to_path_buf(), record, offset, size })
+    }
+
+    // The intent record and the position in it of the byte at offset of the archive as it was
+    // before the operation, for reading back bytes already overwritten
+    pub(crate) fn saved(&self, offset: u64) -> io::Result<(&File, u64)> {
+        if offset < self.offset || offset > self.size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Offset {} was not saved in the intent record!", offset)));
+        }
+        Ok((&self.record, HEADER_SIZE + offset - self.offset))
+    }
+
+    // Syncs the rewritten archive to disk and removes the intent record
+    pub fn finish(self) -> io::Result<()> {
+        let Intent { archive, record, .. } = self;
+        drop(record);
+        File::open(&archive)?.sync_all()?;
+        fs::remove_file(path(&archive))
+    }
+}
+
+const HEADER_SIZE: u64 = 24;
+const CHUNK: u64 = 8 << 20; // Of the saved bytes copied at a time
+
+// Rolls archive back if an in-place write of it was interrupted, file has to hold its lock.
+// Returns whether it was.
+fn recover(archive: &Path, mut file: &File) -> io::Result<bool> {
+    let record_path = path(archive);
+    let record = match File::open(&record_path) {
+        Ok(record) => record,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let length = record.metadata()?.len();
+    if !(length >= HEADER_SIZE + 32 && verify(&record, length)?) {
+        // Cut short while it was written, the archive wasn't touched yet
+        fs::remove_file(&record_path)?;
+        return Ok(false);
+    }
+    let mut header = [0u8; HEADER_SIZE as usize];
+    read_exact_at(&record, &mut header, 0)?;
+    let size = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let offset = u64::from_le_bytes(header[16..24].try_into().unwrap());
+    let saved = length - HEADER_SIZE - 32;
+    if offset.checked_add(saved) != Some(size) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Intent record {} is corrupt!", record_path.display())));
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut chunk: Vec<u8> = Vec::new();
+    let mut copied = 0;
+    while copied < saved {
+        chunk.resize((saved - copied).min(CHUNK) as usize, 0);
+        read_exact_at(&record, &mut chunk, HEADER_SIZE + copied)?;
+        file.write_all(&chunk)?;
+        copied += chunk.len() as u64;
+    }
+    file.set_len(size)?;
+    file.sync_all()?;
+    fs::remove_file(&record_path)?;
+    Ok(true)
+}
+
+// Whether the intent record of length bytes starts with MAGIC and ends with the SHA-256 of the
+// rest, read in chunks
+fn verify(record: &File, length: u64) -> io::Result<bool> {
+    let mut magic = [0u8; 8];
+    read_exact_at(record, &mut magic, 0)?;
+    if magic != MAGIC {
+        return Ok(false);
+    }
+    let mut hasher = Sha256::new();
+    let mut chunk: Vec<u8> = Vec::new();
+    let mut position = 0;
+    while position < length - 32 {
+        chunk.resize((length - 32 - position).min(CHUNK) as usize, 0);
+        read_exact_at(record, &mut chunk, position)?;
+        hasher.update(&chunk);
+        position += chunk.len() as u64;
+    }
+    let mut digest = [0u8; 32];
+    read_exact_at(record, &mut digest, length - 32)?;
+    Ok(hasher.finalize()[..] == digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing, Archive, Writer};
+
+    // Archive of records in the temporary directory, removed again by the test
+    fn archive_file(name: &str, records: &[Vec<u8>]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("cmzip-intent-{}-{}.cmz", std::process::id(), name));
+        fs::write(&path, testing::archive_bytes(records).unwrap()).unwrap();
+        path
+    }
+
+    fn assert_holds(archive: &Path, records: &[Vec<u8>]) {
+        let archive = Archive::open(archive).unwrap();
+        assert_eq!(archive.len(), records.len());
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(&archive.read_record(i).unwrap(), record);
+        }
+    }
+
+    #[test]
+    fn interrupted_truncate_is_rolled_back() {
+        let records = testing::sd_records(10);
+        let archive = archive_file("truncate", &records);
+        let original = fs::read(&archive).unwrap();
+
+        // Cut off after record 3 and dropped halfway through rewriting the tail, without a footer
+        let mut writer = Writer::truncate(&archive, 4, None).unwrap();
+        writer.add_record(&testing::sd_record("NEW", 3, &[])).unwrap();
+        writer.copy_original(5..7).unwrap();
+        drop(writer);
+        assert!(path(&archive).exists());
+        assert_ne!(fs::read(&archive).unwrap(), original);
+
+        drop(open(&archive).unwrap());
+        assert!(!path(&archive).exists());
+        assert_eq!(fs::read(&archive).unwrap(), original);
+        assert_holds(&archive, &records);
+        fs::remove_file(&archive).unwrap();
+    }
+
+    #[test]
+    fn intent_record_cut_short_is_discarded() {
+        let records = testing::sd_records(5);
+        let archive = archive_file("cut", &records);
+        let original = fs::read(&archive).unwrap();
+
+        // Interrupted while saving the tail, before the archive was touched
+        let file = open(&archive).unwrap();
+        let size = file.metadata().unwrap().len();
+        drop(Intent::begin(&archive, &file, size / 2).unwrap());
+        drop(file);
+        let record = OpenOptions::new().write(true).open(path(&archive)).unwrap();
+        record.set_len(record.metadata().unwrap().len() - 1).unwrap();
+
+        drop(open(&archive).unwrap());
+        assert!(!path(&archive).exists());
+        assert_eq!(fs::read(&archive).unwrap(), original);
+        assert_holds(&archive, &records);
+        fs::remove_file(&archive).unwrap();
+    }
+
+    #[test]
+    fn second_writer_is_refused() {
+        let archive = archive_file("locked", &testing::sd_records(2));
+        let file = open(&archive).unwrap();
+        assert_eq!(open(&archive).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        drop(file);
+        drop(open(&archive).unwrap());
+        fs::remove_file(&archive).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+// Journal of zip --resume, written next to the archive as OUTPUT.cmz.journal while it is being
+// compressed, so a killed run continues where it stopped instead of starting over. The first line
+// describes the settings and input files, each following line is a checkpoint written once the
+// records before it were flushed to the archive:
+//
+//   <records> <archive bytes> <input file> <input offset> <records per input> <molfile versions> <sizes>
+//
+// with the records compressed of every input file, the molfile versions seen and the compressed
+// sizes of the records since the checkpoint before separated by commas, or "-" if there are none.
+// A checkpoint cut short by the kill doesn't add up and is ignored, along with everything after it.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf}
+};
+
+const MAGIC: &str = "cmzip-journal 1";
+
+// Checkpoints are written every INTERVAL records
+pub const INTERVAL: usize = 1000;
+
+#[derive(Default)]
+pub struct Checkpoint {
+    pub sizes: Vec<u64>, // Of every record flushed to the archive
+    pub file: usize, // Input file and offset the next record is read from
+    pub offset: u64,
+    pub admitted: Vec<usize>, // Records compressed of every input file
+    pub versions: Vec<String>,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    written: usize, // Records covered by the checkpoints written
+}
+
+impl Journal {
+    pub fn path(archive: &str) -> PathBuf {
+        PathBuf::from(format!("{}.journal", archive))
+    }
+
+    // Starts the journal at path, replacing an existing one
+    pub fn create(path: &Path, settings: &str) -> io::Result<Journal> {
+        let mut file = File::create(path)?;
+        writeln!(file, "{} {}", MAGIC, settings)?;
+        Ok(Journal { path: path.to_path_buf(), file, written: 0 })
+    }
+
+    // Last complete checkpoint of the journal at path, None if there is no journal
+    pub fn load(path: &Path, settings: &str) -> Result<Option<Checkpoint>, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Unable to read {}: {}", path.display(), e)),
+        };
+        let mut lines = text.lines();
+        if lines.next() != Some(&format!("{} {}", MAGIC, settings)) {
+            return Err(format!("{} was written for other settings or input files, remove it to start over!", path.display()));
+        }
+        let mut last = Checkpoint::default();
+        for line in lines {
+            match parse(line, &last.sizes) {
+                Some(checkpoint) => last = checkpoint,
+                None => break,
+            }
+        }
+        Ok(Some(last))
+    }
+
+    // Notes that the records of the given sizes are flushed and the input continues at file and
+    // offset
+    pub fn checkpoint(&mut self, sizes: &[u64], file: usize, offset: u64, admitted: &[usize], versions: &[&str]) -> io::Result<()> {
+        let list = |values: Vec<String>| if values.is_empty() { "-".to_string() } else { values.join(",") };
+        writeln!(self.file, "{} {} {} {} {} {} {}", sizes.len(), sizes.iter().sum::<u64>(), file, offset,
+            list(admitted.iter().map(usize::to_string).collect()),
+            list(versions.iter().map(|version| version.to_string()).collect()),
+            list(sizes[self.written..].iter().map(u64::to_string).collect()))?;
+        self.written = sizes.len();
+        Ok(())
+    }
+
+    // Removes the journal once the archive is complete
+    pub fn remove(self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// Checkpoint of line following the sizes of the checkpoints before, None unless it adds up
+fn parse(line: &str, before: &[u64]) -> Option<Checkpoint> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    if fields.len() != 7 {
+        return None;
+    }
+    let list = |field: &str| -> Vec<String> {
+        if field == "-" { Vec::new() } else { field.split(',').map(str::to_string).collect() }
+    };
+    let mut sizes = before.to_vec();
+    for size in list(fields[6]) {
+        sizes.push(size.parse().ok()?);
+    }
+    let admitted = list(fields[4]).iter().map(|n| n.parse::<usize>().ok()).collect::<Option<Vec<usize>>>()?;
+    let (records, bytes) = (fields[0].parse::<usize>().ok()?, fields[1].parse::<u64>().ok()?);
+    if records != sizes.len() || bytes != sizes.iter().sum::<u64>() || admitted.iter().sum::<usize>() != records {
+        return None;
+    }
+    Some(Checkpoint { sizes, file: fields[2].parse().ok()?, offset: fields[3].parse().ok()?, admitted, versions: list(fields[5]) })
+}
@@ -0,0 +1,29 @@
+// Minimal JSON encoding helpers for the hand written JSON output of serve and --json, values are
+// encoded by the caller so numbers, arrays and nested objects pass through as they are
+
+// Quotes and escapes value as a JSON string
+pub fn string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// JSON object of fields, whose values are already encoded
+pub fn object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields.iter().map(|(key, value)| format!("{}:{}", string(key), value)).collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+// JSON value of an optional string, null if it is None
+pub fn optional(value: Option<&str>) -> String {
+    value.map_or_else(|| "null".to_string(), string)
+}
@@ -0,0 +1,39 @@
+// CmZIP library: reading and writing CmZ archives.
+// The cmzip binary and the language bindings are built on top of it.
+
+pub mod archive;
+pub mod backup;
+pub mod codec;
+pub mod crypto;
+pub mod dictionary;
+pub mod health;
+mod index_cache;
+pub mod intent;
+pub mod metadata;
+pub mod record;
+pub mod retention;
+pub mod sdf;
+pub mod seekable;
+pub mod sidecar;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod transform;
+pub mod units;
+pub mod writer;
+pub mod xz;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
+#[cfg(feature = "async")]
+pub mod async_archive;
+
+pub use archive::Archive;
+#[cfg(feature = "async")]
+pub use async_archive::AsyncArchive;
+pub use codec::Codec;
+pub use dictionary::Dictionary;
+pub use metadata::Metadata;
+pub use record::Record;
+pub use sdf::SdfRecords;
+pub use seekable::CmzSeekableReader;
+pub use transform::RecordTransform;
+pub use writer::Writer;
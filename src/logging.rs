@@ -0,0 +1,42 @@
+// Messages besides the output of a mode and its error reports: what the mode did, warnings about
+// single records, e.g. malformed ones kept by zip --validate, and with -v or -vv details of the
+// work, all written to stderr through the log crate so stdout only carries output. The level is
+// error with -q --quiet, info by default, debug with -v and trace with -vv, unless CMZIP_LOG sets
+// env_logger filters instead, e.g. CMZIP_LOG=debug. Messages are written as they are, warnings
+// prefixed by "warning: ", or with --json as single line JSON objects like
+// {"level":"warn","message":"..."} next to the error reports.
+
+use std::io::Write;
+use env_logger::Builder;
+use log::{Level, LevelFilter};
+use crate::json;
+
+pub const ENV_VAR: &str = "CMZIP_LOG";
+
+pub fn init(verbosity: u64, quiet: bool, json: bool) {
+    let level = match (quiet, verbosity) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    let mut builder = Builder::new();
+    builder.filter_level(level);
+    if let Ok(filters) = std::env::var(ENV_VAR) {
+        builder.parse_filters(&filters);
+    }
+    builder.format(move |buf, record| {
+        let level = record.level();
+        let message = record.args().to_string();
+        match (json, level) {
+            (true, _) => writeln!(buf, "{}", json::object(&[
+                ("level", json::string(&level.as_str().to_lowercase())),
+                ("message", json::string(&message)),
+            ])),
+            (false, Level::Error) => writeln!(buf, "error: {}", message),
+            (false, Level::Warn) => writeln!(buf, "warning: {}", message),
+            (false, _) => writeln!(buf, "{}", message),
+        }
+    });
+    builder.init();
+}
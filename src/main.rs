@@ -1,16 +1,60 @@
 extern crate clap;
 
+mod batch;
+mod capabilities;
+mod checksums;
+mod chunks;
+mod diagnostic;
+mod filenames;
+mod history;
+mod hll;
+mod inputs;
+mod journal;
+mod json;
+mod logging;
+mod names;
+mod numbers;
+mod numa;
+mod password;
+mod rate;
+mod preflight;
+mod progress;
+mod remote;
+mod repair;
+mod sample;
+mod selection;
+mod server;
+mod shards;
+mod signing;
+mod staging;
+mod tuning;
+
 use std::{
     process, 
     io::{
-        Seek, SeekFrom,
         BufReader, BufWriter,
         prelude::*
     },
-    convert::TryInto
+    collections::{BTreeMap, HashMap},
+    sync::{mpsc, Arc, Mutex},
+    thread
 };
 use clap::{Arg, ArgMatches, App, SubCommand};
-use xz2::read::{XzEncoder, XzDecoder};
+use sha2::{Digest, Sha256};
+use cmzip::{
+    Archive, Codec, Dictionary, SdfRecords, Writer, archive, codec::Encoder, intent::{self, Intent}, record::NameSource,
+    retention::{self, Retention},
+    transform::{CrlfNewlines, NormalizeNewlines}, units
+};
+use diagnostic::Diagnostic;
+use inputs::Inputs;
+use names::{NameFilter, NameMatch};
+use selection::Entry;
+
+// Set by --force, see create_file()
+static FORCE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+// Files the mode reads, which no output may overwrite even with --force, see check_overwrite()
+static INPUTS: Mutex<Vec<std::path::PathBuf>> = Mutex::new(Vec::new());
 
 // Main function only sets up clap then calls run()
 fn main() {
@@ -18,14 +62,71 @@ fn main() {
         .version("1.0")
         .author("Gašper Tomšič <gasper.tomsic@covid.si>")
         .about("CmDock archive utility.\nMDL SD file records are encoded individually and concatenated into a file.\nCmZ archives also contain a file footer which allows for individual decompression and easier processing.")
+        .arg(Arg::with_name("capabilities")
+            .long("capabilities")
+            .alias("version-json")
+            .help("Prints the version, git commit, archive formats, codecs and features of this build as JSON")
+        )
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Reports failures as JSON objects on stderr, and writes the results of info, count, list, verify and bench as JSON")
+            .global(true)
+        )
+        .arg(Arg::with_name("force")
+            .long("force")
+            .help("Overwrites existing output files instead of refusing to")
+            .global(true)
+        )
+        .arg(Arg::with_name("no-atomic")
+            .long("no-atomic")
+            .help("Writes archives in place instead of to OUTPUT.tmp renamed once complete, for filesystems where renaming is costly")
+            .global(true)
+        )
+        .arg(Arg::with_name("keyfile")
+            .long("keyfile")
+            .value_name("FILE")
+            .help("Reads the passphrase of encrypted archives from the first line of FILE instead of CMZIP_PASSWORD or a prompt")
+            .takes_value(true)
+            .global(true)
+        )
+        .arg(Arg::with_name("verbose")
+            .short("v")
+            .multiple(true)
+            .help("Also reports details of the work on stderr, -vv even more")
+            .global(true)
+        )
+        .arg(Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .help("Only reports errors on stderr, no progress or warnings")
+            .conflicts_with("verbose")
+            .global(true)
+        )
+        .arg(Arg::with_name("jobs")
+            .short("j")
+            .long("jobs")
+            .value_name("JOBS")
+            .help("Sets the number of threads of every mode working in parallel, unless its --threads is given")
+            .takes_value(true)
+            .global(true)
+        )
+        .arg(Arg::with_name("memlimit")
+            .long("memlimit")
+            .value_name("SIZE")
+            .help("Fails records needing more than SIZE of memory to decompress, e.g. 256MiB, instead of exhausting memory on corrupt or malicious archives")
+            .takes_value(true)
+            .global(true)
+        )
         .subcommand(SubCommand::with_name("zip")
             .about("Compresses MDL SD file into CmZ archive using LZMA")
             .arg(Arg::with_name("input")
                 .short("i")
                 .long("input")
                 .value_name("INPUT")
-                .help("Sets the input SDF file to use")
-                .required(true)
+                .help("Sets the input SDF file to use; may be given several times or as a quoted glob like 'batch_*.sdf', the records of all files go into one archive")
+                .required_unless("dir")
+                .multiple(true)
+                .number_of_values(1)
                 .takes_value(true)
             )
             .arg(Arg::with_name("output")
@@ -33,17 +134,290 @@ fn main() {
                 .long("output")
                 .value_name("OUTPUT")
                 .help("Sets the archive filename and path to write")
+                .required_unless("dir")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dir")
+                .long("dir")
+                .value_name("DIR")
+                .help("Compresses every .sd and .sdf file in DIR into its own archive under --out-dir, -t files at a time")
+                .conflicts_with_all(&["input", "output"])
+                .requires("out-dir")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("out-dir")
+                .long("out-dir")
+                .value_name("OUT_DIR")
+                .help("Sets the directory --dir archives are written to, with the same relative paths as their SD files")
+                .requires("dir")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("recursive")
+                .long("recursive")
+                .help("Also compresses the SD files in the subdirectories of --dir")
+                .requires("dir")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("level")
+                .short("l")
+                .long("level")
+                .value_name("LEVEL")
+                .help("Sets the compression level (0 - 9 for xz, 1 - 22 for zstd), or auto for the best ratio meeting --target-mbps")
+                .default_value("6")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("target-mbps")
+                .long("target-mbps")
+                .value_name("MBPS")
+                .help("Sets the throughput in MB of records per second on all threads --level auto picks a level for")
+                .default_value("50")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("extreme")
+                .long("extreme")
+                .help("Compresses with the extreme variant of the xz preset (like xz -9e), a few percent smaller but several times slower")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("xz-opts")
+                .long("xz-opts")
+                .value_name("OPTIONS")
+                .help("Sets options of the xz filter chain on top of the preset, e.g. dict=64MiB,lc=4 or delta=4")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("codec")
+                .short("c")
+                .long("codec")
+                .value_name("CODEC")
+                .help("Sets the record codec")
+                .possible_values(&["xz", "zstd"])
+                .default_value("xz")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Compresses records with a zstd dictionary created by dict train")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("embed-dict")
+                .long("embed-dict")
+                .help("Stores the dictionary in the archive instead of only referencing it")
+                .requires("dict")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("threads")
+                .short("t")
+                .long("threads")
+                .value_name("THREADS")
+                .help("Sets the number of compression worker threads")
+                .default_value("1")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("max-output-bytes")
+                .long("max-output-bytes")
+                .value_name("SIZE")
+                .help("Stops before the archive would grow past SIZE, e.g. 10GiB, leaving a valid archive of the records compressed so far")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("index-format")
+                .long("index-format")
+                .value_name("FORMAT")
+                .help("Sets the index layout, compact varints (varint) or u64s (fixed) readable by cmzip versions before the varint index")
+                .possible_values(&["varint", "fixed"])
+                .default_value("varint")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("inspect")
+                .long("inspect")
+                .value_name("N")
+                .help("Prints the title line, offset and size of every Nth record while compressing, to check record detection early")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("progress-json")
+                .long("progress-json")
+                .help("Writes progress events (phase, records done, bytes in and out) to stderr as JSON lines, for web UIs and workflow managers")
+            )
+            .arg(Arg::with_name("lenient")
+                .long("lenient")
+                .help("Ends records at any line containing $$$$, instead of only at lines that are exactly $$$$")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("strict")
+                .long("strict")
+                .help("Fails if the input ends with a record lacking the $$$$ delimiter, instead of compressing it as the last record")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Sets the input format, MDL SD files (sdf) or Tripos mol2 files (mol2), whose records start with @<TRIPOS>MOLECULE")
+                .possible_values(&["sdf", "mol2"])
+                .conflicts_with("delimiter")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("delimiter")
+                .long("delimiter")
+                .value_name("LINE")
+                .help("Splits records at LINE instead of $$$$, for other record-oriented files, e.g. ENDMDL for multi-model PDB files")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("delimiter-at")
+                .long("delimiter-at")
+                .value_name("POSITION")
+                .help("Sets whether the --delimiter line ends records (end) or starts them (start), lines starting with LINE then start records, e.g. > for FASTA files")
+                .possible_values(&["end", "start"])
+                .requires("delimiter")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("validate")
+                .long("validate")
+                .help("Checks that every record is a well-formed molfile with SD data items and reports the malformed ones")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("on-invalid")
+                .long("on-invalid")
+                .value_name("ACTION")
+                .help("Sets what happens to malformed records found by --validate, compress them anyway (keep), leave them out (skip) or stop (fail)")
+                .possible_values(&["keep", "skip", "fail"])
+                .default_value("keep")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("write-buffer")
+                .long("write-buffer")
+                .value_name("SIZE")
+                .help("Buffers up to SIZE of output, e.g. 64MiB, instead of writing every record as it is compressed, for network filesystems")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("sync-every")
+                .long("sync-every")
+                .value_name("N")
+                .help("Flushes the output and syncs it to disk every N records")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("normalize-newlines")
+                .long("normalize-newlines")
+                .help("Converts CRLF line endings to LF before compressing, so archives of Windows files hold the same bytes as those of their Unix copies")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("name-from")
+                .long("name-from")
+                .value_name("SOURCE")
+                .help("Stores where molecule names are read from in the archive, the title line (title) or an SD data tag (tag:NAME), used by name lookups that don't set their own")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("rm-input")
+                .long("rm-input")
+                .help("Deletes the input files once the written archive is verified to decompress to exactly their contents")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("keep")
+                .long("keep")
+                .help("Keeps the input files, the default")
+                .conflicts_with("rm-input")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("retain")
+                .long("retain")
+                .value_name("DURATION")
+                .help("Keeps the records for DURATION, e.g. 365d, after which prune --expired drops them")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("resume")
+                .long("resume")
+                .help("Keeps a journal of the records written, so a run that was interrupted continues where it stopped when started again with --resume")
+                .conflicts_with("dir")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("encrypt")
+                .long("encrypt")
+                .help("Encrypts the records and the footer with AES-256-GCM, keyed by the passphrase of --keyfile, CMZIP_PASSWORD or a prompt")
+                .conflicts_with("resume")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("no-preflight")
+                .long("no-preflight")
+                .help("Starts even if the estimated archive size exceeds the free disk space")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("numa")
+                .long("numa")
+                .help("Pins compression workers to NUMA nodes and keeps a separate work queue per node (Linux only)")
+                .required(false)
+                .takes_value(false)
+            )
+        )
+        .subcommand(SubCommand::with_name("watch")
+            .about("Compresses the SD files appearing in a directory as they are finished")
+            .arg(Arg::with_name("dir")
+                .long("dir")
+                .value_name("DIR")
+                .help("Sets the directory to watch")
                 .required(true)
                 .takes_value(true)
             )
+            .arg(Arg::with_name("out-dir")
+                .long("out-dir")
+                .value_name("OUT_DIR")
+                .help("Sets the directory archives are written to, with the same relative paths as their SD files (defaults to DIR)")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("recursive")
+                .long("recursive")
+                .help("Also watches the subdirectories of DIR")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("interval")
+                .long("interval")
+                .value_name("DURATION")
+                .help("Sets how often DIR is scanned; a file is compressed once its size and modification time stayed the same for one interval")
+                .default_value("5s")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("delete-originals")
+                .long("delete-originals")
+                .help("Deletes every SD file once its archive is verified to decompress to exactly its contents")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("once")
+                .long("once")
+                .help("Compresses the SD files found in one scan, without waiting for them to stay unchanged, and exits")
+                .takes_value(false)
+            )
             .arg(Arg::with_name("level")
                 .short("l")
                 .long("level")
                 .value_name("LEVEL")
-                .help("Sets the compression level (0 - 9)")
+                .help("Sets the compression level (0 - 9 for xz, 1 - 22 for zstd), or auto for the best ratio meeting --target-mbps")
                 .default_value("6")
                 .takes_value(true)
             )
+            .arg(Arg::with_name("target-mbps")
+                .long("target-mbps")
+                .value_name("MBPS")
+                .help("Sets the throughput in MB of records per second on all threads --level auto picks a level for")
+                .default_value("50")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("extreme")
+                .long("extreme")
+                .help("Compresses with the extreme variant of the xz preset (like xz -9e), a few percent smaller but several times slower")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("xz-opts")
+                .long("xz-opts")
+                .value_name("OPTIONS")
+                .help("Sets options of the xz filter chain on top of the preset, e.g. dict=64MiB,lc=4 or delta=4")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("codec")
+                .short("c")
+                .long("codec")
+                .value_name("CODEC")
+                .help("Sets the record codec")
+                .possible_values(&["xz", "zstd"])
+                .default_value("xz")
+                .takes_value(true)
+            )
         )
         .subcommand(SubCommand::with_name("unzip")
             .about("DeCompresses CmZ archive into MDL SD file")
@@ -60,181 +434,4015 @@ fn main() {
                 .long("output")
                 .value_name("OUTPUT")
                 .help("Sets the MDL SD filename and path to write")
+                .required_unless("template")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("template")
+                .long("template")
+                .value_name("TEMPLATE")
+                .help("Writes every record to a file of its own named by TEMPLATE instead, with {index} and {name} replaced by its index and molecule name, e.g. ligands/{name}.sdf")
+                .conflicts_with_all(&["output", "split"])
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("windows-names")
+                .long("windows-names")
+                .help("Makes the file names of --template safe for Windows on any system, as on Windows itself")
+                .requires("template")
+            )
+            .arg(Arg::with_name("records")
+                .short("r")
+                .long("record")
+                .value_name("RECORD")
+                .help("Only extract specified records in specified order, single indices or inclusive ranges like 100-200. Indexing starts at 0.")
+                .use_delimiter(true)
+                .required(false)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("ignore-missing")
+                .long("ignore-missing")
+                .help("Skips record indices past the end of the archive and names matching no record with a warning, instead of failing")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("records-from")
+                .long("records-from")
+                .value_name("FILE")
+                .help("Also extract the records listed in FILE (- for stdin), indices, ranges or molecule names one per line or comma separated")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("names")
+                .long("name")
+                .value_name("NAME")
+                .help("Only extract the records named NAME, in archive order; may be given several times")
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with_all(&["records", "records-from"])
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("name-from")
+                .long("name-from")
+                .value_name("SOURCE")
+                .help("Sets where molecule names are read from, the title line (title) or an SD data tag (tag:NAME); defaults to the rule stored in the archive, or title")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("name-match")
+                .long("name-match")
+                .value_name("MODE")
+                .help("Sets how names are compared: exactly (exact), ignoring case (ci) or also ignoring padding and -, _ or space separators (normalized)")
+                .possible_values(&["exact", "ci", "normalized"])
+                .default_value("exact")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("no-preflight")
+                .long("no-preflight")
+                .help("Starts even if the extracted records exceed the free disk space")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("sample")
+                .long("sample")
+                .value_name("N")
+                .help("Only extract a random sample of N of the selected records, in their order, or a percentage like 10% of the archive")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("stratify-by")
+                .long("stratify-by")
+                .value_name("TAG")
+                .help("Shares the sample between the values of SD data tag TAG, as set by --allocation")
+                .requires("sample")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("allocation")
+                .long("allocation")
+                .value_name("MODE")
+                .help("Sets how the sample is shared between tag values: by their number of records (proportional) or equally (equal)")
+                .possible_values(&["proportional", "equal"])
+                .default_value("proportional")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Sets the seed picking the sampled records, the same seed picks the same records")
+                .default_value("0")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("pubkey")
+                .long("pubkey")
+                .value_name("KEY")
+                .help("Checks the minisign signature of the archive against public key KEY before extracting")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("signature")
+                .long("signature")
+                .value_name("SIG")
+                .help("Sets the signature file to check, defaults to INPUT.minisig")
+                .requires("pubkey")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("use-backup-index")
+                .long("use-backup-index")
+                .help("Reads the index from the backup copy behind the last record, for archives with a damaged footer")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("best-effort")
+                .long("best-effort")
+                .help("Reports damaged or cut short records and goes on with the next one instead of stopping")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("max-record-size")
+                .long("max-record-size")
+                .value_name("SIZE")
+                .help("Fails records decompressing to more than SIZE, against crafted archives expanding a tiny record")
+                .default_value("1GiB")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("threads")
+                .short("t")
+                .long("threads")
+                .value_name("THREADS")
+                .help("Sets the number of threads decompressing records")
+                .default_value("1")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("crlf")
+                .long("crlf")
+                .help("Writes records with CRLF line endings, e.g. for tools on Windows")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("split")
+                .long("split-output")
+                .value_name("N")
+                .help("Splits output into files of N records each, named OUTPUT_0001.sdf, OUTPUT_0002.sdf, ...")
+                .required(false)
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("cat")
+            .about("Writes decompressed records of a CmZ archive to stdout")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
                 .required(true)
                 .takes_value(true)
             )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the MDL SD filename and path to write, defaults to stdout")
+                .takes_value(true)
+            )
             .arg(Arg::with_name("records")
                 .short("r")
                 .long("record")
                 .value_name("RECORD")
-                .help("Only extract specified records in specified order. Indexing starts at 0.")
+                .help("Only write specified records in specified order, single indices or inclusive ranges like 100-200. Indexing starts at 0.")
                 .use_delimiter(true)
                 .required(false)
                 .takes_value(true)
             )
+            .arg(Arg::with_name("ignore-missing")
+                .long("ignore-missing")
+                .help("Skips record indices past the end of the archive and names matching no record with a warning, instead of failing")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("records-from")
+                .long("records-from")
+                .value_name("FILE")
+                .help("Also write the records listed in FILE (- for stdin), indices, ranges or molecule names one per line or comma separated")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("names")
+                .long("name")
+                .value_name("NAME")
+                .help("Only write the records named NAME, in archive order; may be given several times")
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with_all(&["records", "records-from"])
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("name-from")
+                .long("name-from")
+                .value_name("SOURCE")
+                .help("Sets where molecule names are read from, the title line (title) or an SD data tag (tag:NAME); defaults to the rule stored in the archive, or title")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("name-match")
+                .long("name-match")
+                .value_name("MODE")
+                .help("Sets how names are compared: exactly (exact), ignoring case (ci) or also ignoring padding and -, _ or space separators (normalized)")
+                .possible_values(&["exact", "ci", "normalized"])
+                .default_value("exact")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("sample")
+                .long("sample")
+                .value_name("N")
+                .help("Only write a random sample of N of the selected records, in their order, or a percentage like 10% of the archive")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("stratify-by")
+                .long("stratify-by")
+                .value_name("TAG")
+                .help("Shares the sample between the values of SD data tag TAG, as set by --allocation")
+                .requires("sample")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("allocation")
+                .long("allocation")
+                .value_name("MODE")
+                .help("Sets how the sample is shared between tag values: by their number of records (proportional) or equally (equal)")
+                .possible_values(&["proportional", "equal"])
+                .default_value("proportional")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Sets the seed picking the sampled records, the same seed picks the same records")
+                .default_value("0")
+                .takes_value(true)
+            )
         )
-        .get_matches();
-    
-    if let Err(e) = run(matches) {
-        println!("Application error: {}", e);
-        process::exit(1);
-    }
-}
-
-// run() starts appropriate subcommand
-fn run(matches: ArgMatches) -> Result<(), String> {
-    match matches.subcommand() {
-        ("zip", Some(m)) => zip(m),
-        ("unzip", Some(m)) => unzip(m),
-        _ => {
-            eprintln!("Operating mode not selected!");
-            eprintln!("Use cmzip -h for reference on how to use the utility.");
-            process::exit(0);
-        },
-    }
-}
-
-// Entrypoint for zip subcommand
-fn zip(matches: &ArgMatches) -> Result<(), String> {
-    // Setup variables from command line input
-    let input_filename = matches.value_of("input").unwrap();
-    let mut output_filename = matches.value_of("output").unwrap().to_string();
-    let level = matches.value_of("level").unwrap().parse::<u32>().expect("Specified level is invalid!");
-    if level > 9 {
-        eprintln!("Specified level is invalid!");
-        process::exit(1);
-    }
-    // Always end archive file names with .cmz
-    if !output_filename.ends_with(".cmz") {
-        output_filename = output_filename.to_string() + ".cmz";
-    }
-
-    // Initialize the input and output buffers
-    let mut input = BufReader::new(std::fs::File::open(input_filename).expect("No such file!"));
-    let mut output = BufWriter::new(create_file(&output_filename));
-    
-    // Create vectors used in compression sequence
-    let mut vec_record: Vec<u8> = Vec::new(); // Holds data of each record, delimited with $$$$
-    let mut vec_index: Vec<u64> = vec![0]; // Holds the sizes of each created record, used for calculating offsets at decompression
-    let mut compressed_data: Vec<u8> = Vec::new(); // Cleared each loop, used for compression
-    let mut buf: Vec<u8> = Vec::new(); // Cleared each loop, holds one line from file as bytes
-    loop {  // Iterate over lines in file
-        match input.read_until(b'\n', &mut buf) {
-            Ok(0) => break,
-            Ok(_) => {
-                let line = String::from_utf8_lossy(&buf).to_string();
-                vec_record.append(&mut buf);
-        
-                if !line.contains("$$$$") {
-                    continue; // Only finish loop when $$$$ is reached, this way we compress each record by itself
-                }
-            },
-            Err(_) => eprintln!("Error reading input file!")
-        };
-
-        // Compress record with specified compression level
-        compress(&vec_record, &mut compressed_data, level).expect("Error compressing data!");
-        vec_index.push(compressed_data.len() as u64); // Update index
-        output.write_all(&compressed_data).expect("Error writing to file!");
-        output.flush().unwrap(); // Write to output file
-
-        // Clear vectors, this data is not needed anymore
-        compressed_data.clear();
-        buf.clear();
-        vec_record.clear();
-    }
-    let mut data: Vec<u8> = Vec::new();
-    // Convert index vector into binary vector for compression
-    for index in vec_index {
-        data.append(&mut index.to_le_bytes().to_vec())
-    }
-    compress(&data, &mut compressed_data, 9).expect("Error compressing data!");
-    
-    // Calculate size of compressed index for easier extraction
-    let size = (compressed_data.len() as u64).to_le_bytes();
-
-    // Write file footer
-    output.write_all(&compressed_data).expect("Error writing to file!");
-    output.write_all(&size).expect("Error writing to file!");
-    output.flush().unwrap();
-
-    return Ok(());
-}
-
-// Entrypoint for unzip subcommand
-fn unzip(matches: &ArgMatches) -> Result<(), String> {
-    // Setup variables from command line input
-    let input_filename = matches.value_of("input").unwrap();
-    let output_filename = matches.value_of("output").unwrap().to_string();
-
-    // Initialize the input and output buffers
-    let input_file = std::fs::File::open(input_filename).expect("No such file!");
-    let file_size = input_file.metadata().unwrap().len() as u64; // Store file size for calculating offsets
-    let mut input = BufReader::new(input_file);
-    let mut output = BufWriter::new(create_file(&output_filename));
-
-    // Get index from file footer
-    // First step: get the compressed index size from last 8 bytes in file footer
-    let mut index_size_raw: [u8; 8] = [0; 8];
-    input.seek(SeekFrom::Start(file_size-8)).expect("Unable to seek in file!");
-    input.read_exact(&mut index_size_raw).expect("Unexpected EOF!");
-    let index_size = u64::from_le_bytes(index_size_raw); // Convert raw bytes to u64
-
-    // Second step: read the compressed data from file footer
-    let mut index_compressed: Vec<u8> = vec![0u8; index_size as usize];
-    input.seek(SeekFrom::Start(file_size-index_size-8)).expect("Unable to seek in file!");
-    input.read_exact(&mut index_compressed).expect("Unexpected EOF!");
-    
-    // Third step: decompress index and store in Vec[u64]
-    let mut index_decompressed: Vec<u8> = Vec::new();
-    decompress(&index_compressed, &mut index_decompressed).expect("Decompression failed");
-    let mut index: Vec<u64> = Vec::new();
-    for byte in index_decompressed.chunks(8) {
-        index.push(u64::from_le_bytes(byte.try_into().unwrap())); // Numbers in index are raw little endian bytes, convert them to u64
-    }
-
-    let t_records: Vec<usize>; // This vector stores record indices of records to be extracted, should --record be specified
-    if matches.is_present("records") {
-        t_records = matches.values_of("records").unwrap().map(|x| x.parse::<usize>().expect("Invalid record index!")).collect();
-    } else { // Else just decompress everything. Last elt is ignored as it points to the beginning of file footer.
-        t_records = (0..(index.len() - 1)).collect();
-    }
-
-    // Decompression loop
-    for i in t_records {
-        let offset: u64 = (&index[0..=i]).iter().sum(); // Calculate offset
-        input.seek(SeekFrom::Start(offset)).expect("Unable to seek in file!");
-        let mut buf: Vec<u8> = vec![0u8; (index[i + 1]) as usize]; // Stores compressed record. Must be exactly the size of compressed data!
-        input.read_exact(&mut buf).expect("Unexpected EOF!");
-        let mut decompressor = XzDecoder::new(&buf[..]); // Create decompress stream
-        std::io::copy(&mut decompressor, &mut output).expect("Error writing to file!"); // Decompress directly to file
-    }
-    
-    return Ok(());
-}
-
-fn compress(input_buffer: &Vec<u8>, output_buffer: &mut Vec<u8>, level: u32) -> Result<usize, std::io::Error> {
-    let mut compressor = XzEncoder::new(&input_buffer[..], level);
-    return compressor.read_to_end(output_buffer)
-}
-
-fn decompress(input_buffer: &Vec<u8>, output_buffer: &mut Vec<u8>) -> Result<usize, std::io::Error> {
-    let mut decompressor = XzDecoder::new(&input_buffer[..]);
-    return decompressor.read_to_end(output_buffer);
-}
-
-fn create_file(filename: &str) -> std::fs::File {
-    let path = std::path::Path::new(&filename);
-    if filename.contains("/") {
-        let prefix = path.parent().unwrap();
-        std::fs::create_dir_all(prefix).unwrap();
-    }
-    let display = path.display();
-
-    let file = match std::fs::File::create(&path) {
-        Err(why) => panic!("Couldn't create {}: {}", display, why),
-        Ok(file) => file,
-    };
+        .subcommand(SubCommand::with_name("head")
+            .about("Writes the first records of a CmZ archive")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("lines")
+                .short("n")
+                .long("records")
+                .value_name("N")
+                .help("Sets the number of records to write")
+                .default_value("10")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the MDL SD filename and path to write, defaults to stdout")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("tail")
+            .about("Writes the last records of a CmZ archive")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("lines")
+                .short("n")
+                .long("records")
+                .value_name("N")
+                .help("Sets the number of records to write")
+                .default_value("10")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the MDL SD filename and path to write, defaults to stdout")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("grep")
+            .about("Prints records of a CmZ archive matching a regular expression")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("pattern")
+                .short("e")
+                .long("regexp")
+                .value_name("PATTERN")
+                .help("Sets the regular expression to search for")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("tag")
+                .long("tag")
+                .value_name("NAME")
+                .help("Only matches against the value of SD data tag NAME instead of the whole record")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("list-only")
+                .short("l")
+                .long("list-only")
+                .help("Prints indices of matching records instead of the records")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("threads")
+                .short("t")
+                .long("threads")
+                .value_name("THREADS")
+                .help("Sets the number of threads decompressing records, defaults to the number of CPUs")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("unzip-many")
+            .about("Extracts matching records of many shard archives into one SD file")
+            .arg(Arg::with_name("manifest")
+                .long("manifest")
+                .value_name("MANIFEST")
+                .help("Sets the manifest (.cmzd) listing shard archives, one per line")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("where")
+                .long("where")
+                .value_name("CONDITION")
+                .help("Only extracts records whose SD data tags satisfy CONDITION, e.g. SCORE<-9 or TARGET=A; may be given several times")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+            )
+            .arg(Arg::with_name("numbers")
+                .long("numbers")
+                .value_name("POLICY")
+                .help("Sets how numbers of --where are read, only plain ones like 1.23E+02 (strict) or also ones like 1,23E+02 (lenient)")
+                .possible_values(&["strict", "lenient"])
+                .default_value("lenient")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the output SD file, defaults to standard output")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("names")
+                .long("name")
+                .value_name("NAME")
+                .help("Only extracts records named NAME; may be given several times")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("name-from")
+                .long("name-from")
+                .value_name("SOURCE")
+                .help("Sets where molecule names are read from, the title line (title) or an SD data tag (tag:NAME); defaults to the rule stored in the archive, or title")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("name-match")
+                .long("name-match")
+                .value_name("MODE")
+                .help("Sets how names are compared: exactly (exact), ignoring case (ci) or also ignoring padding and -, _ or space separators (normalized)")
+                .possible_values(&["exact", "ci", "normalized"])
+                .default_value("exact")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary for shards compressed with one that is not embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("prune")
+            .about("Copies a CmZ archive without the records whose retention expired")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the output CmZ file")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("expired")
+                .long("expired")
+                .help("Drops the records kept past the retention given to zip --retain")
+                .required(true)
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("merge")
+            .about("Merges shard archives into one archive without recompressing their records")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets a shard archive to merge; may be given several times or as a quoted glob like 'shard_*.cmz'")
+                .required_unless("manifest")
+                .conflicts_with("manifest")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("manifest")
+                .long("manifest")
+                .value_name("MANIFEST")
+                .help("Sets the manifest (.cmzd) listing the shard archives to merge, one per line")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the merged archive")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary for shards compressed with one that is not embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("dedup")
+            .about("Copies a CmZ archive without duplicate records")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the output CmZ file")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("by")
+                .long("by")
+                .value_name("KEY")
+                .help("Sets what makes records duplicates: identical content (hash), title line (title), molecule name from --name-from (name) or the value of an SD data tag (tag NAME)")
+                .takes_value(true)
+                .min_values(1)
+                .max_values(2)
+                .default_value("hash")
+            )
+            .arg(Arg::with_name("name-from")
+                .long("name-from")
+                .value_name("SOURCE")
+                .help("Sets where --by name reads molecule names from, the title line (title) or an SD data tag (tag:NAME); defaults to the rule stored in the archive, or title")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("name-match")
+                .long("name-match")
+                .value_name("MODE")
+                .help("Sets how names are compared: exactly (exact), ignoring case (ci) or also ignoring padding and -, _ or space separators (normalized)")
+                .possible_values(&["exact", "ci", "normalized"])
+                .default_value("exact")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("delete")
+            .about("Removes records from a CmZ archive")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("records")
+                .short("r")
+                .long("record")
+                .value_name("RECORD")
+                .help("Sets the records to delete, single indices or inclusive ranges like 100-200. Indexing starts at 0.")
+                .use_delimiter(true)
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("ignore-missing")
+                .long("ignore-missing")
+                .help("Skips record indices past the end of the archive and names matching no record with a warning, instead of failing")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the CmZ file to write the remaining records to")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("in-place")
+                .long("in-place")
+                .help("Deletes the records from the input file, rewriting it from the first deleted record on")
+                .conflicts_with("output")
+                .required_unless("output")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("redact")
+            .about("Copies a CmZ archive with SD data tags removed from every record, or watermarked for a recipient")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the output CmZ file")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("drop-tags")
+                .long("drop-tags")
+                .value_name("TAGS")
+                .help("Sets the comma separated SD data tags to remove")
+                .use_delimiter(true)
+                .required_unless("watermark")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("watermark")
+                .long("watermark")
+                .value_name("RECIPIENT")
+                .help("Marks the copy for RECIPIENT in the metadata and with a CMZIP_WATERMARK tag on a pseudo-random share of records")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("watermark-rate")
+                .long("watermark-rate")
+                .value_name("PERCENT")
+                .help("Sets the share of records carrying the watermark tag")
+                .default_value("1%")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("update")
+            .about("Replaces a record of a CmZ archive in place")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the CmZ file to update")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("record")
+                .short("r")
+                .long("record")
+                .value_name("RECORD")
+                .help("Sets the index of the record to replace. Indexing starts at 0.")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("with")
+                .long("with")
+                .value_name("SDF")
+                .help("Sets the MDL SD file holding the replacement record")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("lenient")
+                .long("lenient")
+                .help("Ends records at any line containing $$$$, instead of only at lines that are exactly $$$$")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("strict")
+                .long("strict")
+                .help("Fails if the input ends with a record lacking the $$$$ delimiter, instead of compressing it as the last record")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("recompress")
+            .about("Copies a CmZ archive with records compressed at another level or with another codec")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the output CmZ file")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("level")
+                .short("l")
+                .long("level")
+                .value_name("LEVEL")
+                .help("Sets the compression level (0 - 9 for xz, 1 - 22 for zstd), defaults to the level of the input unless the codec changes")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("extreme")
+                .long("extreme")
+                .help("Compresses with the extreme variant of the xz preset (like xz -9e), a few percent smaller but several times slower")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("xz-opts")
+                .long("xz-opts")
+                .value_name("OPTIONS")
+                .help("Sets options of the xz filter chain on top of the preset, e.g. dict=64MiB,lc=4 or delta=4")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("codec")
+                .short("c")
+                .long("codec")
+                .value_name("CODEC")
+                .help("Sets the record codec, defaults to the codec of the input")
+                .possible_values(&["xz", "zstd"])
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the input was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("new-dict")
+                .long("new-dict")
+                .value_name("DICT")
+                .help("Compresses records with a zstd dictionary created by dict train")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("embed-dict")
+                .long("embed-dict")
+                .help("Stores the new dictionary in the archive instead of only referencing it")
+                .requires("new-dict")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("threads")
+                .short("t")
+                .long("threads")
+                .value_name("THREADS")
+                .help("Sets the number of worker threads")
+                .default_value("1")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("index")
+            .about("Writes the uncompressed index of a CmZ archive to a sidecar file next to it")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the sidecar file to write, defaults to INPUT.idx where it is picked up automatically")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("extract-footer")
+            .about("Copies the raw file footer of a CmZ archive, everything behind the last record, to a file")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the file to write the footer to")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("use-backup-index")
+                .long("use-backup-index")
+                .help("Finds the last record from the backup index, for archives with a damaged footer")
+                .takes_value(false)
+            )
+        )
+        .subcommand(SubCommand::with_name("replace-footer")
+            .about("Replaces everything behind the last record of a CmZ archive with a footer read from a file")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("footer")
+                .long("footer")
+                .value_name("FOOTER")
+                .help("Sets the footer file to splice in, as written by extract-footer or fetch-index")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the CmZ file to write the records and the new footer to")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("in-place")
+                .long("in-place")
+                .help("Replaces the footer of the input file, the old footer is lost")
+                .conflicts_with("output")
+                .required_unless("output")
+                .takes_value(false)
+            )
+        )
+        .subcommand(SubCommand::with_name("repair")
+            .about("Rebuilds the index of a CmZ archive with a lost or damaged footer from the records it still holds")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the damaged CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the CmZ file to write the recovered records to")
+                .required(true)
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("info")
+            .about("Prints a summary of a CmZ archive")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("encoder")
+                .long("encoder")
+                .help("Also prints the encoder version and parameters the archive was produced with")
+                .takes_value(false)
+            )
+        )
+        .subcommand(SubCommand::with_name("history")
+            .about("Prints the log of the cmzip commands that wrote a CmZ archive")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("verify")
+            .about("Checks that every record of a CmZ archive decompresses, and its signature")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("pubkey")
+                .long("pubkey")
+                .value_name("KEY")
+                .help("Also checks the minisign signature of the archive against public key KEY")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("signature")
+                .long("signature")
+                .value_name("SIG")
+                .help("Sets the signature file to check, defaults to INPUT.minisig")
+                .requires("pubkey")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("threads")
+                .short("t")
+                .long("threads")
+                .value_name("THREADS")
+                .help("Sets the number of threads decompressing records, defaults to the number of CPUs")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("manifest")
+                .long("manifest")
+                .value_name("MANIFEST")
+                .help("Also checks the archive, and the records if it lists them, against the SHA-256 manifest written by cmzip hash")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("max-ratio")
+                .long("max-ratio")
+                .value_name("RATIO")
+                .help("Reports records expanding to more than RATIO times their compressed size as suspicious")
+                .default_value("100")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("no-molblock-check")
+                .long("no-molblock-check")
+                .help("Doesn't report records that aren't well-formed molfiles as suspicious")
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("hash")
+            .about("Writes a SHA-256 manifest of a CmZ archive, optionally listing every decompressed record")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("MANIFEST")
+                .help("Sets the manifest file to write, defaults to INPUT.sha256")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("per-record")
+                .long("per-record")
+                .help("Also lists the SHA-256 of every decompressed record")
+            )
+            .arg(Arg::with_name("threads")
+                .short("t")
+                .long("threads")
+                .value_name("THREADS")
+                .help("Sets the number of threads decompressing records, defaults to the number of CPUs")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("sign")
+            .about("Signs a CmZ archive with an ed25519 key in a minisign signature file")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("key")
+                .long("key")
+                .value_name("KEY")
+                .help("Sets the minisign secret key file to sign with")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("SIG")
+                .help("Sets the signature file to write, defaults to INPUT.minisig")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("comment")
+                .long("comment")
+                .value_name("TEXT")
+                .help("Sets the trusted comment of the signature, defaults to the signing time and file name")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("count")
+            .about("Prints the number of records in a CmZ archive")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("list")
+            .about("Lists the records of a CmZ archive with their offsets, sizes and molecule names")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("threads")
+                .short("t")
+                .long("threads")
+                .value_name("THREADS")
+                .help("Sets the number of threads decompressing records, defaults to the number of CPUs")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("stats")
+            .about("Estimates the number of distinct values of SD data tags in a CmZ archive, in one pass")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("distinct")
+                .long("distinct")
+                .value_name("TAG")
+                .help("Estimates the number of distinct values of SD data tag TAG; may be given several times")
+                .required_unless("titles")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("titles")
+                .long("titles")
+                .help("Also estimates the number of distinct title lines (molecule names)")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("precision")
+                .long("precision")
+                .value_name("BITS")
+                .help("Sets the HyperLogLog precision (4 - 18), each step doubles the memory per tag and divides the error by 1.4")
+                .default_value("14")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("threads")
+                .short("t")
+                .long("threads")
+                .value_name("THREADS")
+                .help("Sets the number of threads decompressing records, defaults to the number of CPUs")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("bench")
+            .about("Compresses a sample of the records of an SD file with every codec and level given, to compare ratio, speed and memory")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input SD file to sample")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("codecs")
+                .long("codecs")
+                .value_name("CODECS")
+                .help("Sets the comma separated codecs to compare")
+                .default_value("xz,zstd")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("levels")
+                .long("levels")
+                .value_name("LEVELS")
+                .help("Sets the comma separated compression levels to compare, levels above the highest of a codec are left out for it")
+                .default_value("1,6,9")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("sample")
+                .long("sample")
+                .value_name("N")
+                .help("Sets the number of records compressed, from the start of INPUT")
+                .default_value("1000")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Compresses the zstd configurations with dictionary DICT")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("chunks")
+            .about("Lists content-defined chunks and their digests of a CmZ archive, for deduplicating storage")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the file the chunks are listed in, defaults to standard output")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("avg-size")
+                .long("avg-size")
+                .value_name("SIZE")
+                .help("Sets the average chunk size, e.g. 4MiB")
+                .default_value("1MiB")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("min-size")
+                .long("min-size")
+                .value_name("SIZE")
+                .help("Sets the smallest chunk size, defaults to a quarter of the average")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("max-size")
+                .long("max-size")
+                .value_name("SIZE")
+                .help("Sets the largest chunk size, defaults to four times the average")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("offset")
+            .about("Prints the compressed byte range of a record")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("record")
+                .short("r")
+                .long("record")
+                .value_name("RECORD")
+                .help("Sets the record to locate. Indexing starts at 0.")
+                .required(true)
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("script")
+            .about("Writes a shell script extracting records with dd and xz, for machines without cmzip")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the file to write the script to, defaults to stdout")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("records")
+                .short("r")
+                .long("record")
+                .alias("records")
+                .value_name("RECORD")
+                .help("Only extract specified records in specified order, single indices or inclusive ranges like 100-200. Indexing starts at 0.")
+                .use_delimiter(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("ignore-missing")
+                .long("ignore-missing")
+                .help("Skips record indices past the end of the archive and names matching no record with a warning, instead of failing")
+                .takes_value(false)
+            )
+            .arg(Arg::with_name("records-from")
+                .long("records-from")
+                .value_name("FILE")
+                .help("Also extract the records listed in FILE (- for stdin), indices, ranges or molecule names one per line or comma separated")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("emit")
+                .long("emit")
+                .value_name("FORMAT")
+                .help("Sets the script language")
+                .possible_values(&["sh"])
+                .default_value("sh")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("fetch-index")
+            .about("Downloads only the index and metadata of a remote CmZ archive")
+            .arg(Arg::with_name("url")
+                .long("url")
+                .value_name("URL")
+                .help("Sets the HTTP(S) URL of the archive, the server must support range requests")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the index filename and path to write, .cmzi is appended if missing")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .long("limit-rate")
+                .value_name("RATE")
+                .help("Limits the download to RATE, a size per second like 50MB/s")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("serve")
+            .about("Serves decompressed records of a CmZ archive over HTTP")
+            .arg(Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("INPUT")
+                .help("Sets the input CmZ file to use")
+                .required(true)
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("port")
+                .short("p")
+                .long("port")
+                .value_name("PORT")
+                .help("Sets the port to listen on")
+                .default_value("8080")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("host")
+                .long("host")
+                .value_name("HOST")
+                .help("Sets the address to listen on")
+                .default_value("127.0.0.1")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("workers")
+                .short("w")
+                .long("workers")
+                .value_name("WORKERS")
+                .help("Sets the number of worker threads handling queued requests")
+                .default_value("4")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("dict")
+                .long("dict")
+                .value_name("DICT")
+                .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("limit-rate")
+                .long("limit-rate")
+                .value_name("RATE")
+                .help("Limits all responses together to RATE, a size per second like 50MB/s")
+                .takes_value(true)
+            )
+            .arg(Arg::with_name("jobs-dir")
+                .long("jobs-dir")
+                .value_name("DIR")
+                .help("Accepts compress and recompress jobs on files in DIR, queued with the requests")
+                .takes_value(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("raw")
+            .about("Copies compressed records in and out of CmZ archives without decompressing them")
+            .subcommand(SubCommand::with_name("get")
+                .about("Writes a compressed record exactly as stored in the archive")
+                .arg(Arg::with_name("input")
+                    .short("i")
+                    .long("input")
+                    .value_name("INPUT")
+                    .help("Sets the input CmZ file to use")
+                    .required(true)
+                    .takes_value(true)
+                )
+                .arg(Arg::with_name("record")
+                    .short("r")
+                    .long("record")
+                    .value_name("RECORD")
+                    .help("Sets the record to copy. Indexing starts at 0.")
+                    .required(true)
+                    .takes_value(true)
+                )
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .value_name("OUTPUT")
+                    .help("Sets the file to write the compressed record to, defaults to stdout")
+                    .takes_value(true)
+                )
+            )
+            .subcommand(SubCommand::with_name("put")
+                .about("Appends a compressed record to an archive, after checking it decompresses")
+                .arg(Arg::with_name("input")
+                    .short("i")
+                    .long("input")
+                    .value_name("INPUT")
+                    .help("Sets the compressed record file to read, defaults to stdin")
+                    .takes_value(true)
+                )
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .value_name("OUTPUT")
+                    .help("Sets the CmZ file to append the record to")
+                    .required(true)
+                    .takes_value(true)
+                )
+                .arg(Arg::with_name("size")
+                    .long("size")
+                    .value_name("BYTES")
+                    .help("Rejects the record unless it is exactly this size")
+                    .takes_value(true)
+                )
+                .arg(Arg::with_name("sha256")
+                    .long("sha256")
+                    .value_name("DIGEST")
+                    .help("Rejects the record unless its SHA-256 digest matches")
+                    .takes_value(true)
+                )
+                .arg(Arg::with_name("dict")
+                    .long("dict")
+                    .value_name("DICT")
+                    .help("Sets the dictionary the archive was compressed with, unless it is embedded")
+                    .takes_value(true)
+                )
+            )
+        )
+        .subcommand(SubCommand::with_name("dict")
+            .about("Trains, exports and inspects zstd dictionaries shared between archives")
+            .subcommand(SubCommand::with_name("train")
+                .about("Trains a dictionary on records of an MDL SD file")
+                .arg(Arg::with_name("input")
+                    .short("i")
+                    .long("input")
+                    .value_name("INPUT")
+                    .help("Sets the sample SDF file to use")
+                    .required(true)
+                    .takes_value(true)
+                )
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .value_name("OUTPUT")
+                    .help("Sets the dictionary filename and path to write")
+                    .required(true)
+                    .takes_value(true)
+                )
+                .arg(Arg::with_name("size")
+                    .short("s")
+                    .long("size")
+                    .value_name("SIZE")
+                    .help("Sets the maximum dictionary size, e.g. 64KiB")
+                    .default_value("110KiB")
+                    .takes_value(true)
+                )
+                .arg(Arg::with_name("records")
+                    .short("n")
+                    .long("records")
+                    .value_name("N")
+                    .help("Sets the number of records from the start of the file to train on, or the percentage of records sampled evenly across the file, e.g. 1%")
+                    .default_value("10000")
+                    .takes_value(true)
+                )
+                .arg(Arg::with_name("lenient")
+                    .long("lenient")
+                    .help("Ends records at any line containing $$$$, instead of only at lines that are exactly $$$$")
+                    .takes_value(false)
+                )
+                .arg(Arg::with_name("strict")
+                    .long("strict")
+                    .help("Fails if the input ends with a record lacking the $$$$ delimiter, instead of compressing it as the last record")
+                    .takes_value(false)
+                )
+            )
+            .subcommand(SubCommand::with_name("export")
+                .about("Writes the dictionary embedded in a CmZ archive to a file")
+                .arg(Arg::with_name("input")
+                    .short("i")
+                    .long("input")
+                    .value_name("INPUT")
+                    .help("Sets the input CmZ file to use")
+                    .required(true)
+                    .takes_value(true)
+                )
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .value_name("OUTPUT")
+                    .help("Sets the dictionary filename and path to write")
+                    .required(true)
+                    .takes_value(true)
+                )
+            )
+            .subcommand(SubCommand::with_name("info")
+                .about("Prints the id and digest of a dictionary")
+                .arg(Arg::with_name("input")
+                    .short("i")
+                    .long("input")
+                    .value_name("INPUT")
+                    .help("Sets the dictionary file to use")
+                    .required(true)
+                    .takes_value(true)
+                )
+            )
+        )
+        .get_matches_safe()
+        .unwrap_or_else(|e| match e.kind {
+            clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => e.exit(),
+            _ => {
+                eprintln!("{}", e.message);
+                process::exit(diagnostic::EXIT_USAGE);
+            },
+        });
+
+    if matches.is_present("capabilities") {
+        println!("{}", capabilities::report());
+        return;
+    }
+    let json = matches.is_present("json");
+    if json {
+        diagnostic::set_json();
+    }
+    // Global flags are propagated to the subcommand with the occurrences given before it
+    let verbosity = matches.subcommand().1.map_or(matches.occurrences_of("verbose"), |m| m.occurrences_of("verbose"));
+    logging::init(verbosity, matches.is_present("quiet"), json);
+    FORCE.store(matches.is_present("force"), std::sync::atomic::Ordering::Relaxed);
+    if let (_, Some(m)) = matches.subcommand() {
+        for filename in ["input", "footer", "with"].iter().flat_map(|arg| m.values_of(arg).into_iter().flatten()) {
+            note_input(filename);
+        }
+    }
+    password::init(matches.value_of("keyfile").or_else(|| matches.subcommand().1.and_then(|m| m.value_of("keyfile"))));
+    if let Err(e) = run(matches) {
+        Diagnostic::error(&e).exit();
+    }
+}
+
+// run() starts appropriate subcommand
+fn run(matches: ArgMatches) -> Result<(), String> {
+    match matches.subcommand() {
+        ("zip", Some(m)) => zip(m),
+        ("watch", Some(m)) => watch(m),
+        ("unzip", Some(m)) => unzip(m),
+        ("cat", Some(m)) => cat(m),
+        ("head", Some(m)) => head_tail(m, false),
+        ("tail", Some(m)) => head_tail(m, true),
+        ("grep", Some(m)) => grep(m),
+        ("unzip-many", Some(m)) => unzip_many(m),
+        ("merge", Some(m)) => merge(m),
+        ("dedup", Some(m)) => dedup(m),
+        ("prune", Some(m)) => prune(m),
+        ("delete", Some(m)) => delete(m),
+        ("redact", Some(m)) => redact(m),
+        ("update", Some(m)) => update(m),
+        ("recompress", Some(m)) => recompress(m),
+        ("index", Some(m)) => index(m),
+        ("extract-footer", Some(m)) => extract_footer(m),
+        ("replace-footer", Some(m)) => replace_footer(m),
+        ("repair", Some(m)) => repair(m),
+        ("info", Some(m)) => info(m),
+        ("history", Some(m)) => history(m),
+        ("count", Some(m)) => {
+            let count = Archive::count(m.value_of("input").unwrap()).or_exit("Unable to read archive index!");
+            match m.is_present("json") {
+                true => println!("{}", json::object(&[("records", count.to_string())])),
+                false => println!("{}", count),
+            }
+            Ok(())
+        },
+        ("list", Some(m)) => list(m),
+        ("verify", Some(m)) => verify(m),
+        ("hash", Some(m)) => hash(m),
+        ("sign", Some(m)) => sign(m),
+        ("stats", Some(m)) => stats(m),
+        ("bench", Some(m)) => bench(m),
+        ("offset", Some(m)) => offset(m),
+        ("chunks", Some(m)) => chunks(m),
+        ("script", Some(m)) => script(m),
+        ("fetch-index", Some(m)) => fetch_index(m),
+        ("serve", Some(m)) => serve(m),
+        ("raw", Some(m)) => raw(m),
+        ("dict", Some(m)) => dict(m),
+        _ => {
+            eprintln!("Operating mode not selected!");
+            eprintln!("Use cmzip -h for reference on how to use the utility.");
+            process::exit(diagnostic::EXIT_USAGE);
+        },
+    }
+}
+
+// Entrypoint for zip subcommand
+fn zip(matches: &ArgMatches) -> Result<(), String> {
+    // Compression keeps every CPU busy
+    let threads = thread_count(matches, cpu_count());
+    if let Some(dir) = matches.value_of("dir") {
+        return zip_dir(matches, dir, threads);
+    }
+    let input_filenames = inputs::expand(matches.values_of("input").unwrap());
+    let output_filename = zip_files(matches, input_filenames.clone(), matches.value_of("output").unwrap(), threads)?;
+    if matches.is_present("rm-input") {
+        let inputs: Vec<&std::path::Path> = input_filenames.iter().map(std::path::Path::new).collect();
+        remove_inputs(&inputs, std::path::Path::new(&output_filename))?;
+    }
+    Ok(())
+}
+
+// Deletes the compressed input files once the archive is verified to hold exactly their contents and
+// is synced to disk, for zip --rm-input. Inputs are kept if the archive doesn't match them.
+fn remove_inputs(inputs: &[&std::path::Path], archive: &std::path::Path) -> Result<(), String> {
+    batch::verify(inputs, archive).map_err(|e| format!("Kept the input files, {}", e))?;
+    std::fs::File::open(archive).and_then(|file| file.sync_all()).map_err(|e| format!("Kept the input files, couldn't sync {}: {}", archive.display(), e))?;
+    for input in inputs {
+        std::fs::remove_file(input).map_err(|e| format!("Couldn't delete {}: {}", input.display(), e))?;
+    }
+    Ok(())
+}
+
+// Compresses the SD files of --dir on threads workers, each file on one thread
+fn zip_dir(matches: &ArgMatches, dir: &str, threads: usize) -> Result<(), String> {
+    let out_dir = std::path::Path::new(matches.value_of("out-dir").unwrap());
+    let jobs = batch::jobs(std::path::Path::new(dir), out_dir, matches.is_present("recursive"));
+    if jobs.is_empty() {
+        Diagnostic::error(&format!("{} holds no .sd or .sdf files!", dir)).code("not_found").exit();
+    }
+    for (_, output) in &jobs {
+        check_overwrite(&output.display().to_string()); // Before any file is compressed
+    }
+    let progress = batch::Progress::new(jobs.len());
+    let staging = staging::Staging::new(); // Archives only get their names once all of them are written
+    let queue = Mutex::new(jobs.iter());
+    thread::scope(|s| {
+        let workers: Vec<_> = (0..threads.min(jobs.len())).map(|_| s.spawn(|| -> Result<(), String> {
+                loop {
+                    let next = queue.lock().unwrap().next();
+                    let (input, output) = match next {
+                        Some(job) => job,
+                        None => return Ok(()),
+                    };
+                    if let Some(parent) = filenames::parent_dir(output) {
+                        std::fs::create_dir_all(parent).map_err(|e| format!("Couldn't create {}: {}", parent.display(), e))?;
+                    }
+                    let staged = staging.stage(output);
+                    zip_files(matches, vec![input.display().to_string()], &staged.display().to_string(), 1)?;
+                    progress.finished(input, output, &staged);
+                }
+            })).collect();
+        // Every worker runs until the queue is empty, the first failure is reported
+        workers.into_iter().map(|worker| worker.join().unwrap()).collect::<Result<Vec<()>, String>>()
+    })?;
+    staging.commit()?;
+    if matches.is_present("rm-input") {
+        for (input, output) in &jobs {
+            remove_inputs(&[input], output)?;
+        }
+    }
+    Ok(())
+}
+
+// Entrypoint for watch subcommand, compressing the SD files of a directory once they stop changing.
+// Files with an archive are skipped, so files compressed before a restart aren't compressed again.
+fn watch(matches: &ArgMatches) -> Result<(), String> {
+    let dir = std::path::Path::new(matches.value_of("dir").unwrap());
+    let out_dir = matches.value_of("out-dir").map(std::path::Path::new).unwrap_or(dir);
+    let interval = std::time::Duration::from_secs(units::parse_duration(matches.value_of("interval").unwrap()).unwrap_or_else(|e| invalid_argument(&e)));
+    let delete = matches.is_present("delete-originals");
+    let once = matches.is_present("once");
+
+    // Size and modification time of every file when it was last scanned
+    let mut scanned: HashMap<std::path::PathBuf, (u64, Option<std::time::SystemTime>)> = HashMap::new();
+    loop {
+        for (input, output) in batch::jobs(dir, out_dir, matches.is_present("recursive")) {
+            let state = match std::fs::metadata(&input) {
+                Ok(metadata) => (metadata.len(), metadata.modified().ok()),
+                Err(_) => continue, // Deleted since the scan
+            };
+            if output.exists() || (!once && scanned.insert(input.clone(), state) != Some(state)) {
+                continue;
+            }
+            scanned.remove(&input);
+            if let Err(e) = watch_compress(matches, &input, &output, delete) {
+                Diagnostic::error(&e).emit();
+            }
+        }
+        if once {
+            return Ok(());
+        }
+        thread::sleep(interval);
+    }
+}
+
+// Compresses input into output for watch, deleting input afterwards if delete is set
+fn watch_compress(matches: &ArgMatches, input: &std::path::Path, output: &std::path::Path, delete: bool) -> Result<(), String> {
+    if let Some(parent) = filenames::parent_dir(output) {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Couldn't create {}: {}", parent.display(), e))?;
+    }
+    let staging = staging::Staging::new();
+    let staged = staging.stage(output);
+    zip_files(matches, vec![input.display().to_string()], &staged.display().to_string(), 1)?;
+    if delete {
+        batch::verify(&[input], &staged)?;
+    }
+    staging.commit()?;
+    log::info!("Compressed {} into {}", input.display(), output.display());
+    if delete {
+        std::fs::remove_file(input).map_err(|e| format!("Couldn't delete {}: {}", input.display(), e))?;
+        log::info!("Deleted {}", input.display());
+    }
+    Ok(())
+}
+
+// What zip --resume has to find unchanged to continue an archive: the settings shaping its records
+// and the input files with their sizes
+fn resume_settings(matches: &ArgMatches, input_filenames: &[String], codec: Codec, level: u32, dictionary: Option<&Dictionary>) -> String {
+    let mut settings: Vec<String> = vec![codec.name().to_string(), level.to_string(), dictionary.map(Dictionary::digest).unwrap_or_else(|| "-".to_string())];
+    for flag in ["normalize-newlines", "validate", "lenient", "strict", "extreme"] {
+        if matches.is_present(flag) {
+            settings.push(format!("--{}", flag));
+        }
+    }
+    for option in ["on-invalid", "format", "delimiter", "delimiter-at", "xz-opts"] {
+        if let Some(value) = matches.value_of(option) {
+            settings.push(format!("--{} {}", option, shell_quote(value)));
+        }
+    }
+    for filename in input_filenames {
+        let size = std::fs::metadata(filename).map(|metadata| metadata.len()).unwrap_or(0);
+        settings.push(format!("{} {}", shell_quote(filename), size));
+    }
+    settings.join(" ")
+}
+
+// Compresses the records of input_filenames into one archive and returns its name, which gets a
+// .cmz extension if it lacks one
+fn zip_files(matches: &ArgMatches, input_filenames: Vec<String>, output_filename: &str, threads: usize) -> Result<String, String> {
+    // Setup variables from command line input
+    input_filenames.iter().for_each(|filename| note_input(filename)); // Globs are only expanded by now
+    let mut output_filename = output_filename.to_string();
+    let codec = matches.value_of("codec").unwrap().parse::<Codec>().unwrap_or_else(|e| invalid_argument(&e));
+    let target_mbps = (matches.value_of("level") == Some("auto")).then(|| matches.value_of("target-mbps").unwrap().parse::<f64>().ok()
+        .filter(|&mbps| mbps > 0.0 && mbps.is_finite()).unwrap_or_else(|| invalid_argument("Specified target throughput is invalid!")));
+    let numa = matches.is_present("numa");
+    let resume = matches.is_present("resume");
+    if resume && (threads > 1 || numa) {
+        invalid_argument("Compression can only be resumed with one thread!");
+    }
+    if resume && target_mbps.is_some() {
+        invalid_argument("Compression can only be resumed at a fixed level!");
+    }
+    let xz = xz_settings(matches, codec);
+    let split = split_options(matches);
+    if matches.is_present("validate") && split.delimiter.is_some() {
+        invalid_argument("Records can only be validated in SD files!");
+    }
+    let mut validation = matches.is_present("validate").then(|| Validation::new(matches.value_of("on-invalid").unwrap()));
+    let inspect = matches.value_of("inspect").map(|x| x.parse::<usize>().ok().filter(|&n| n > 0).unwrap_or_else(|| invalid_argument("Specified inspect interval is invalid!")));
+    let write_buffer = matches.value_of("write-buffer").map(units::parse_size).transpose().unwrap_or_else(|e| invalid_argument(&e));
+    let sync_every = matches.value_of("sync-every").map(|x| x.parse::<usize>().ok().filter(|&n| n > 0).unwrap_or_else(|| invalid_argument("Specified sync interval is invalid!")));
+    let retain = matches.value_of("retain").map(units::parse_duration).transpose().unwrap_or_else(|e| invalid_argument(&e));
+    let dictionary = matches.value_of("dict").map(|filename| Dictionary::load(filename).or_exit("No such dictionary!"));
+    let level = match target_mbps {
+        Some(target_mbps) => auto_level(&input_filenames[0], split, codec, &xz, dictionary.as_ref(), target_mbps, threads),
+        None => matches.value_of("level").unwrap().parse::<u32>().ok().filter(|&level| level <= codec.max_level())
+            .unwrap_or_else(|| invalid_argument("Specified level is invalid!")),
+    };
+    let key = match matches.is_present("encrypt") {
+        true => Some(cmzip::crypto::Key::generate(&password::new()?).map_err(|e| format!("Unable to derive key: {}", e))?),
+        false => None,
+    };
+    // Always end archive file names with .cmz
+    if !output_filename.ends_with(".cmz") {
+        output_filename = output_filename.to_string() + ".cmz";
+    }
+
+    if !matches.is_present("no-preflight") {
+        let mut needed: u64 = input_filenames.iter().map(|filename| estimate_compressed_size(filename, codec, level, dictionary.as_ref(), split)).sum();
+        if let Some(size) = matches.value_of("max-output-bytes") {
+            needed = needed.min(units::parse_size(size).unwrap_or_else(|e| invalid_argument(&e)));
+        }
+        preflight::check(&output_filename, needed)?;
+    }
+
+    // Initialize the input and output buffers. With --resume, the archive left by an interrupted
+    // run is continued after the last checkpoint of its journal.
+    let settings = resume_settings(matches, &input_filenames, codec, level, dictionary.as_ref());
+    let input_size = input_filenames.iter()
+        .map(|filename| std::fs::metadata(filename).ok().filter(std::fs::Metadata::is_file).map(|metadata| metadata.len()))
+        .sum::<Option<u64>>();
+    let mut inputs = Inputs::new(input_filenames, split);
+    let atomic = atomic_output(matches, &output_filename);
+    let journal_path = journal::Journal::path(&output_filename);
+    let checkpoint = match resume && std::path::Path::new(atomic.filename()).exists() {
+        true => journal::Journal::load(&journal_path, &settings)?,
+        false => None,
+    };
+    let file = match &checkpoint {
+        Some(checkpoint) => {
+            let mut file = std::fs::OpenOptions::new().write(true).open(atomic.filename()).or_exit("Unable to open archive to resume!");
+            let records_end: u64 = checkpoint.sizes.iter().sum();
+            if file.metadata().or_exit("Unable to open archive to resume!").len() < records_end {
+                return Err(format!("{} is shorter than its journal says, remove {} to start over!", atomic.filename(), journal_path.display()));
+            }
+            // Records written after the last checkpoint are compressed again
+            file.set_len(records_end).and_then(|_| file.seek(std::io::SeekFrom::End(0))).or_exit("Error writing to file!");
+            inputs.resume(checkpoint.file, checkpoint.offset, &checkpoint.admitted)?;
+            log::info!("Resuming {} after {} records", output_filename, checkpoint.sizes.len());
+            file
+        },
+        None => overwrite_file(atomic.filename()),
+    };
+    let output = match write_buffer {
+        Some(size) => BufWriter::with_capacity(size as usize, file),
+        None => BufWriter::new(file),
+    };
+    
+    let mut writer = match &checkpoint {
+        Some(checkpoint) => Writer::resume(output, codec, level, dictionary.as_ref(), &checkpoint.sizes),
+        None => Writer::with_codec(output, codec, level, dictionary.as_ref()),
+    }.or_exit("Unable to create archive!");
+    xz.apply(&mut writer).or_exit("Unable to create archive!");
+    if let Some(key) = &key {
+        writer.encrypt(key.clone()).or_exit("Error writing to file!");
+    }
+    if matches.is_present("embed-dict") {
+        writer.embed_dictionary(dictionary.as_ref().unwrap());
+    }
+    if let Some(size) = matches.value_of("max-output-bytes") {
+        writer.set_size_limit(units::parse_size(size).unwrap_or_else(|e| invalid_argument(&e)));
+    }
+    if matches.value_of("index-format") == Some("fixed") {
+        writer.set_index_format(archive::IndexFormat::Fixed);
+    }
+    if let Some(target_mbps) = target_mbps {
+        writer.metadata_mut().set("level_target_mbps", target_mbps.to_string());
+    }
+    let normalize_newlines = matches.is_present("normalize-newlines");
+    if normalize_newlines {
+        writer = writer.with_transform(NormalizeNewlines);
+    }
+    // Decides whether record n of the input at offset goes into the archive, and notes its molfile version
+    let versions: Mutex<std::collections::BTreeSet<&'static str>> = Default::default();
+    if let Some(checkpoint) = &checkpoint {
+        versions.lock().unwrap().extend(checkpoint.versions.iter().filter_map(|version| ["V2000", "V3000"].iter().copied().find(|known| known == version)));
+    }
+    let mut admit = |n: usize, offset: u64, record: &[u8]| {
+        let admitted = validation.as_mut().is_none_or(|validation| validation.check(n, offset, record));
+        if admitted && split.delimiter.is_none() {
+            versions.lock().unwrap().extend(cmzip::sdf::molfile_version(record));
+        }
+        admitted
+    };
+    let mut journal = match resume {
+        true => Some(journal::Journal::create(&journal_path, &settings).or_exit("Couldn't create journal!")),
+        false => None,
+    };
+    // Checkpoints once the records so far are flushed to the archive
+    let mut checkpoint_at = |writer: &mut Writer<BufWriter<std::fs::File>>, inputs: &Inputs| {
+        if let Some(journal) = &mut journal {
+            writer.get_mut().flush().or_exit("Error writing to file!");
+            let (file, offset, admitted) = inputs.position();
+            let versions: Vec<&str> = versions.lock().unwrap().iter().copied().collect();
+            journal.checkpoint(writer.record_sizes(), file, offset, admitted, &versions).or_exit("Error writing journal!");
+        }
+    };
+    checkpoint_at(&mut writer, &inputs);
+    let mut progress = progress::Progress::new(matches.is_present("progress-json"), &output_filename, input_size);
+    progress.event("start", writer.len(), writer.records_size());
+    let mut limit_offset: Option<u64> = None; // Input offset of the first record that didn't fit
+    if threads > 1 || numa {
+        if !compress_parallel(&mut inputs, &mut writer, codec, level, &xz, dictionary.as_ref(), threads, numa, inspect, normalize_newlines, sync_every, &mut admit, &mut progress) {
+            limit_offset = Some(0);
+        }
+    } else {
+        let mut n = 0; // Records read from the input, including those left out
+        while let Some((offset, vec_record)) = inputs.next_record() {
+            n += 1;
+            if !admit(n - 1, offset, &vec_record) {
+                continue;
+            }
+            inputs.admitted();
+            inspect_record(inspect, writer.len(), offset, &vec_record);
+            // Compress record with specified compression level
+            match writer.add_record(&vec_record) {
+                Ok(_) => {},
+                Err(ref e) if e.kind() == std::io::ErrorKind::FileTooLarge => {
+                    limit_offset = Some(offset);
+                    break;
+                },
+                Err(e) => Diagnostic::io("Error compressing data!", &e)
+                    .record(writer.len()).name(archive::title(&vec_record)).offset(offset).head(&vec_record)
+                    .exit(),
+            }
+            progress.record(vec_record.len() as u64, writer.len(), writer.records_size());
+            // Write to output file, unless records are collected in a larger buffer
+            if write_buffer.is_none() {
+                writer.get_mut().flush().or_exit("Error writing to file!");
+            }
+            sync_output(&mut writer, sync_every);
+            if writer.len().is_multiple_of(journal::INTERVAL) {
+                checkpoint_at(&mut writer, &inputs);
+            }
+        }
+    }
+
+    if let Some(validation) = validation {
+        validation.summary();
+    }
+    let versions = versions.into_inner().unwrap();
+    if !versions.is_empty() {
+        writer.metadata_mut().set("molfile_versions", versions.into_iter().collect::<Vec<&str>>().join(","));
+    }
+    if writer.is_empty() {
+        log::warn!("The input holds no records, {} is an empty archive", output_filename);
+    }
+    let provenance = inputs.provenance(writer.len());
+    writer.metadata_mut().set(inputs::KEY, provenance);
+    if let Some(duration) = retain {
+        let mut retention = Retention::default();
+        retention.set(0..writer.len(), unix_time().saturating_add(duration));
+        set_retention(&mut writer, &retention);
+    }
+    // Records of other formats can be split the same way again, see update
+    if matches.value_of("format") == Some("mol2") {
+        writer.metadata_mut().set("format", "mol2");
+    }
+    if matches.is_present("name-from") {
+        writer.metadata_mut().set(archive::NAME_SOURCE_KEY, name_source(matches, None).to_string());
+    }
+    if let Some(delimiter) = split.delimiter {
+        writer.metadata_mut().set("delimiter", delimiter);
+        writer.metadata_mut().set("delimiter_at", if split.at_start { "start" } else { "end" });
+    }
+
+    // Write file footer
+    let records = writer.len();
+    let records_size = writer.records_size();
+    progress.event("finish", records, records_size);
+    history::append(&mut writer);
+    let output = writer.finish().or_exit("Error writing to file!");
+    if sync_every.is_some() {
+        output.get_ref().sync_data().or_exit("Error writing to file!");
+    }
+    atomic.commit()?;
+    if let Some(journal) = journal {
+        journal.remove();
+    }
+    progress.event("done", records, records_size);
+
+    // The archive is valid, but doesn't hold the whole input
+    if let Some(offset) = limit_offset {
+        let diagnostic = Diagnostic::error(&format!("Output size limit reached, {} holds the first {} records!", output_filename, records))
+            .code("output_limit").record(records);
+        let diagnostic = if threads > 1 || numa { diagnostic } else { diagnostic.offset(offset) };
+        diagnostic.help("compress the remaining records into another archive, or raise --max-output-bytes").exit();
+    }
+    Ok(output_filename)
+}
+
+// Level of zip --level auto, tuned on the first records of the input SD file
+fn auto_level(input_filename: &str, split: Split, codec: Codec, xz: &XzSettings, dictionary: Option<&Dictionary>, target_mbps: f64, threads: usize) -> u32 {
+    const SAMPLE_RECORDS: usize = 256;
+    const SAMPLE_BYTES: usize = 1 << 20;
+    let records = sample_records(input_filename, split, SAMPLE_RECORDS, SAMPLE_BYTES);
+    if records.is_empty() {
+        return 6; // Nothing to tune on, the default level
+    }
+    let measurement = tuning::auto_level(codec, &|level| xz.encoder(codec, level, dictionary), dictionary, &records, target_mbps, threads).or_exit("Error compressing data!");
+    let mbps = measurement.compress_speed() * threads as f64 / 1e6;
+    match mbps >= target_mbps {
+        true => log::info!("Level auto: {} level {}, ratio {:.2} at {:.1} MB/s for a target of {} MB/s", codec, measurement.level, measurement.ratio(), mbps, target_mbps),
+        false => log::warn!("No {} level reaches {} MB/s, using the fastest level {} at {:.1} MB/s", codec, target_mbps, measurement.level, mbps),
+    }
+    measurement.level
+}
+
+// First records of the input SD file, up to count records or until max_bytes are read
+fn sample_records(input_filename: &str, split: Split, count: usize, max_bytes: usize) -> Vec<Vec<u8>> {
+    let file = std::fs::File::open(input_filename).or_exit("Unable to open input file!");
+    let mut records: Vec<Vec<u8>> = Vec::new();
+    let mut size = 0;
+    for record in sdf_records(BufReader::new(file), split).take(count) {
+        let record = record.or_exit("Error reading input file!").into_bytes();
+        size += record.len();
+        records.push(record);
+        if size >= max_bytes {
+            break;
+        }
+    }
+    records
+}
+
+// Expected archive size for the input SD file, from the compression ratio of its first records
+fn estimate_compressed_size(input_filename: &str, codec: Codec, level: u32, dictionary: Option<&Dictionary>, split: Split) -> u64 {
+    const SAMPLE_RECORDS: usize = 64;
+    const SAMPLE_BYTES: usize = 4 << 20;
+    let size = match std::fs::metadata(input_filename) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return 0, // Opening the input reports the error
+    };
+    let (mut encoder, file) = match (Encoder::new(codec, level, dictionary), std::fs::File::open(input_filename)) {
+        (Ok(encoder), Ok(file)) => (encoder, file),
+        _ => return 0,
+    };
+    let records = sdf_records(BufReader::new(file), split);
+    let (mut sampled, mut compressed) = (0usize, 0usize);
+    for record in records.take(SAMPLE_RECORDS).map_while(Result::ok) {
+        if sampled >= SAMPLE_BYTES {
+            break;
+        }
+        let record = record.into_bytes();
+        let mut compressed_data: Vec<u8> = Vec::new();
+        if encoder.compress(&record, &mut compressed_data).is_err() {
+            return 0; // Compression reports the error
+        }
+        sampled += record.len();
+        compressed += compressed_data.len() + 8; // Every record also adds an index entry
+    }
+    if sampled == 0 {
+        return 0;
+    }
+    (size as f64 * compressed as f64 / sampled as f64) as u64
+}
+
+// Size of all records of the archive decompressed, stored in the metadata by newer archives and
+// otherwise estimated from records spread over the archive
+fn estimate_decompressed_size(archive: &Archive) -> u64 {
+    if let Some(size) = archive.metadata().get_str("uncompressed_size").and_then(|size| size.parse::<u64>().ok()) {
+        return size;
+    }
+    let step = (archive.len() / 64).max(1);
+    let (mut compressed, mut decompressed) = (0u64, 0u64);
+    for i in (0..archive.len()).step_by(step) {
+        match (archive.record_range(i), archive.extract(i, &mut std::io::sink())) {
+            (Some(range), Ok(size)) => {
+                compressed += range.end - range.start;
+                decompressed += size;
+            },
+            _ => return 0, // Extraction reports the error
+        }
+    }
+    if compressed == 0 {
+        return 0;
+    }
+    (archive.records_end() as f64 * decompressed as f64 / compressed as f64) as u64
+}
+
+// Entrypoint for unzip subcommand
+fn unzip(matches: &ArgMatches) -> Result<(), String> {
+    // Setup variables from command line input
+    let output_filename = matches.value_of("output").or(matches.value_of("template")).unwrap().to_string();
+    let mut template = matches.value_of("template")
+        .map(|template| filenames::Template::new(template, cfg!(windows) || matches.is_present("windows-names")).unwrap_or_else(|e| invalid_argument(&e)));
+    let split = matches.value_of("split").map(|x| x.parse::<usize>().unwrap_or_else(|_| invalid_argument("Specified split size is invalid!")));
+    if split == Some(0) {
+        invalid_argument("Specified split size is invalid!");
+    }
+
+    // Initialize the input and output buffers
+    check_signature(matches);
+    let mut archive = open_archive(matches)?;
+    let max_record_size = units::parse_size(matches.value_of("max-record-size").unwrap()).ok().filter(|&size| size > 0)
+        .unwrap_or_else(|| invalid_argument("Specified maximum record size is invalid!"));
+    archive.set_max_record_size(Some(max_record_size));
+    if matches.is_present("crlf") {
+        archive = archive.with_transform(CrlfNewlines);
+    }
+    let t_records = sampled_records(matches, &archive, selected_records(matches, &archive));
+    if !matches.is_present("no-preflight") && !archive.is_empty() {
+        let needed = estimate_decompressed_size(&archive) as f64 * t_records.len() as f64 / archive.len() as f64;
+        preflight::check(&output_filename, needed as u64)?;
+    }
+    // Shards only get their names once all of them are written
+    let staging = staging::Staging::new();
+    let shard = |n: usize| {
+        let filename = shard_filename(&output_filename, n);
+        check_overwrite(&filename);
+        staging.stage(std::path::Path::new(&filename)).to_string_lossy().to_string()
+    };
+    // Nothing but the record files is written with --template
+    let mut output = match (&template, split) {
+        (Some(_), _) => BufWriter::new(Box::new(std::io::sink()) as Box<dyn Write>),
+        (None, Some(_)) => BufWriter::new(Box::new(create_file(&shard(1))) as Box<dyn Write>),
+        (None, None) => BufWriter::new(Box::new(create_file(&output_filename)) as Box<dyn Write>),
+    };
+
+    // Decompression loop, a full extraction reads the archive front to back instead of record by record,
+    // unless several threads decompress records
+    let best_effort = matches.is_present("best-effort");
+    let threads = thread_count(matches, cpu_count());
+    let (total, mut damaged) = (t_records.len(), 0);
+    let records: Box<dyn Iterator<Item = (usize, std::io::Result<Vec<u8>>)>> = if threads > 1 {
+        Box::new(read_parallel(&archive, t_records, threads))
+    } else if matches.is_present("records") || matches.is_present("records-from") || matches.is_present("names") || matches.is_present("sample") {
+        Box::new(t_records.into_iter().map(|i| (i, archive.read_record(i))))
+    } else {
+        Box::new(archive.records_sequential().map(|record| record.map(cmzip::Record::into_bytes)).enumerate())
+    };
+    for (n, (i, record)) in records.enumerate() {
+        // Start a new output file every N records when splitting
+        if let Some(split) = split {
+            if n > 0 && n % split == 0 {
+                output.flush().or_exit("Error writing to file!");
+                output = BufWriter::new(Box::new(create_file(&shard(n / split + 1))));
+            }
+        }
+        // Records are decompressed into memory first, so a damaged one leaves nothing behind in the output
+        match record {
+            Ok(record) => match &mut template {
+                Some(template) => write_record_file(&template.filename(i, &archive::title(&record)), &record),
+                None => output.write_all(&record).or_exit("Error writing to file!"),
+            },
+            Err(e) if best_effort => {
+                extraction_error(&archive, i, e).emit();
+                damaged += 1;
+            },
+            Err(e) => {
+                output.flush().or_exit("Error writing to file!"); // Keep the records extracted so far, unless splitting
+                drop(staging);
+                extraction_error(&archive, i, e).exit();
+            },
+        }
+    }
+    output.flush().or_exit("Error writing to file!");
+    drop(output);
+    staging.commit()?;
+
+    if damaged > 0 {
+        Diagnostic::error(&format!("Skipped {} damaged of {} records!", damaged, total)).code("partial_extraction").exit();
+    }
+    Ok(())
+}
+
+// Writes a record of unzip --template to its own file, creating the directories leading to it
+fn write_record_file(filename: &str, record: &[u8]) {
+    if let Some(dir) = filenames::parent_dir(std::path::Path::new(filename)) {
+        std::fs::create_dir_all(dir).or_exit(&format!("Couldn't create {}!", dir.display()));
+    }
+    create_file(filename).write_all(record).or_exit(&format!("Error writing to {}!", filename));
+}
+
+// Entrypoint for cat subcommand
+fn cat(matches: &ArgMatches) -> Result<(), String> {
+    let archive = open_archive(matches)?;
+    let records = sampled_records(matches, &archive, selected_records(matches, &archive));
+    write_records(&archive, records, matches.value_of("output"));
+    Ok(())
+}
+
+// Entrypoint for head and tail subcommands, extracting the first or last N records
+fn head_tail(matches: &ArgMatches, tail: bool) -> Result<(), String> {
+    let archive = open_archive(matches)?;
+    let n = matches.value_of("lines").unwrap().parse::<usize>().unwrap_or_else(|_| invalid_argument("Specified record count is invalid!"));
+    let n = n.min(archive.len());
+    let records = if tail { archive.len() - n..archive.len() } else { 0..n };
+    write_records(&archive, records, matches.value_of("output"));
+    Ok(())
+}
+
+// Decompresses records into output_filename, or to stdout if it is None
+fn write_records<I: IntoIterator<Item = usize>>(archive: &Archive, records: I, output_filename: Option<&str>) {
+    let stdout = std::io::stdout();
+    let mut output: Box<dyn Write> = match output_filename {
+        Some(filename) => Box::new(BufWriter::new(create_file(filename))),
+        None => Box::new(BufWriter::new(stdout.lock())),
+    };
+    for i in records {
+        match archive.extract(i, &mut output) {
+            Ok(_) => {},
+            Err(ref e) if e.kind() == std::io::ErrorKind::BrokenPipe => return, // Reader went away, e.g. piped into head
+            Err(e) => extraction_error(archive, i, e).exit(),
+        }
+    }
+    match output.flush() {
+        Err(ref e) if e.kind() == std::io::ErrorKind::BrokenPipe => {},
+        result => result.or_exit("Error writing to file!"),
+    }
+}
+
+// Entrypoint for grep subcommand
+fn grep(matches: &ArgMatches) -> Result<(), String> {
+    let archive = open_archive(matches)?;
+    let pattern = regex::Regex::new(matches.value_of("pattern").unwrap()).unwrap_or_else(|e| invalid_argument(&format!("Invalid pattern: {}", e)));
+    let tag = matches.value_of("tag");
+    let list_only = matches.is_present("list-only");
+    let threads = thread_count(matches, cpu_count());
+
+    let stdout = std::io::stdout();
+    let mut output = BufWriter::new(stdout.lock());
+    let result = filter_ordered(archive.len(), threads, |i| {
+        let record = archive.read_record(i).map(cmzip::Record::new)
+            .unwrap_or_else(|e| extraction_error(&archive, i, e).exit());
+        let found = match tag {
+            Some(tag) => record.tag(tag).is_some_and(|value| pattern.is_match(value)),
+            None => pattern.is_match(&String::from_utf8_lossy(record.as_bytes())),
+        };
+        found.then(|| record.into_bytes())
+    }, |i, record| if list_only { writeln!(output, "{}", i) } else { output.write_all(&record) })
+        .and_then(|_| output.flush());
+    match result {
+        Err(ref e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}, // Reader went away, e.g. piped into head
+        result => result.or_exit("Error writing to stdout!"),
+    }
+    Ok(())
+}
+
+// Entrypoint for unzip-many subcommand, extracting the matching records of every shard in a manifest
+fn unzip_many(matches: &ArgMatches) -> Result<(), String> {
+    let manifest = matches.value_of("manifest").unwrap();
+    let conditions = match matches.values_of("where") {
+        Some(values) => {
+            let policy = matches.value_of("numbers").unwrap().parse::<numbers::Policy>().unwrap_or_else(|e| invalid_argument(&e));
+            values.map(|condition| shards::Condition::parse(condition, policy)).collect::<Result<Vec<_>, _>>()?
+        },
+        None => Vec::new(),
+    };
+    let names: Option<Vec<&str>> = matches.values_of("names").map(Iterator::collect);
+    let dictionary = matches.value_of("dict").map(|filename| Dictionary::load(filename).or_exit("No such dictionary!"));
+    let threads = thread_count(matches, cpu_count());
+
+    // Every shard is opened up front so a missing or unreadable one fails before any output
+    let mut archives: Vec<Archive> = Vec::new();
+    for path in shards::read_manifest(manifest).or_exit("Unable to read manifest!") {
+        note_input(&path.display().to_string());
+        let mut archive = Archive::open_cached(&path).or_exit(&format!("Unable to read archive index of {}!", path.display()));
+        if archive.is_index_only() {
+            invalid_argument(&format!("{} only holds the index of an archive, its records are stored elsewhere!", path.display()));
+        }
+        archive.set_memlimit(memlimit(matches));
+        // Shards may mix codecs, --dict only applies to those that can't be read without it
+        if let (Some(dictionary), Err(_)) = (&dictionary, archive.check_readable()) {
+            archive.set_dictionary(dictionary).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        archive.check_readable().map_err(|e| format!("{}: {}", path.display(), e))?;
+        archives.push(archive);
+    }
+    // Names are read as every shard stores it unless --name-from is given
+    let filters: Option<Vec<NameFilter>> = names.map(|names| archives.iter()
+        .map(|archive| NameFilter::new(name_source(matches, Some(archive)), name_match(matches), names.iter().copied()))
+        .collect());
+    // Shard s holds the merged records from starts[s] up to starts[s + 1]
+    let mut starts: Vec<usize> = vec![0];
+    for archive in &archives {
+        starts.push(starts.last().unwrap() + archive.len());
+    }
+
+    let stdout = std::io::stdout();
+    let mut output: Box<dyn Write> = match matches.value_of("output") {
+        Some(filename) => Box::new(BufWriter::new(create_file(filename))),
+        None => Box::new(BufWriter::new(stdout.lock())),
+    };
+    let result = filter_ordered(*starts.last().unwrap(), threads, |n| {
+        let shard = starts.partition_point(|&start| start <= n) - 1;
+        let (archive, i) = (&archives[shard], n - starts[shard]);
+        let data = archive.read_record(i).unwrap_or_else(|e| extraction_error(archive, i, e).exit());
+        if conditions.is_empty() && filters.is_none() {
+            return Some(data);
+        }
+        let record = cmzip::Record::new(data);
+        let named = filters.as_ref().is_none_or(|filters| filters[shard].matching(&record).is_some());
+        (named && conditions.iter().all(|condition| condition.matches(&record))).then(|| record.into_bytes())
+    }, |_, record| output.write_all(&record))
+        .and_then(|_| output.flush());
+    match result {
+        Err(ref e) if e.kind() == std::io::ErrorKind::BrokenPipe => {},
+        result => result.or_exit("Error writing to file!"),
+    }
+    Ok(())
+}
+
+// Entrypoint for merge subcommand, copying the compressed records of every shard into one archive.
+// Shard indexes are read on several threads ahead of the shard being copied, the merged index
+// only grows by the index of every copied shard.
+fn merge(matches: &ArgMatches) -> Result<(), String> {
+    let paths: Vec<String> = match matches.value_of("manifest") {
+        Some(manifest) => shards::read_manifest(manifest).or_exit("Unable to read manifest!")
+            .iter().map(|path| path.display().to_string()).collect(),
+        None => inputs::expand(matches.values_of("input").unwrap()),
+    };
+    paths.iter().for_each(|path| note_input(path));
+    let output_filename = matches.value_of("output").unwrap();
+    if paths.iter().any(|path| same_file(path, output_filename)) {
+        invalid_argument("Output file is one of the shards!");
+    }
+    let dictionary = matches.value_of("dict").map(|filename| Dictionary::load(filename).or_exit("No such dictionary!"));
+    let threads = thread_count(matches, paths.len());
+
+    let atomic = atomic_output(matches, output_filename);
+    let settings = |archive: &Archive| (archive.codec(), archive.metadata().get("dictionary_sha256").map(<[u8]>::to_vec));
+    let mut writer: Option<(Writer<std::fs::File>, _)> = None; // Takes the settings of the first shard
+    let mut retention = Retention::default();
+    let mut sources: Vec<String> = Vec::new();
+    let result = filter_ordered(paths.len(), threads, |n| {
+        let path = &paths[n];
+        Some(Archive::open_cached(path).map_err(|e| std::io::Error::new(e.kind(), format!("Unable to read archive index of {}: {}", path, e))))
+    }, |n, archive| {
+        let archive = archive?;
+        let path = &paths[n];
+        if archive.is_index_only() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} only holds the index of an archive, its records are stored elsewhere!", path)));
+        }
+        let writer = match &mut writer {
+            Some((writer, first_settings)) => {
+                if settings(&archive) != *first_settings {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} uses another codec or dictionary than {}, its records can't be copied!", path, paths[0])));
+                }
+                writer
+            },
+            None => {
+                let created = Writer::with_settings_of(overwrite_file(atomic.filename()), &archive, dictionary.as_ref())
+                    .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+                &mut writer.insert((created, settings(&archive))).0
+            },
+        };
+        let first = writer.len();
+        writer.copy_records(&archive).map_err(|e| std::io::Error::new(e.kind(), format!("Error copying records of {}: {}", path, e)))?;
+        retention.extend(&archive.retention(), first);
+        sources.push(match writer.len() > first {
+            true => format!("{}-{} {}", first, writer.len() - 1, path),
+            false => format!("none {}", path),
+        });
+        Ok(())
+    });
+    if let Err(e) = result {
+        if writer.is_some() {
+            let _ = std::fs::remove_file(atomic.filename()); // Holds the records of the shards before
+        }
+        Diagnostic::io("Unable to merge shards!", &e).exit();
+    }
+
+    let mut writer = writer.ok_or("No shards to merge!")?.0;
+    for key in [inputs::KEY, history::KEY] {
+        writer.metadata_mut().remove(key); // Of the first shard
+    }
+    writer.metadata_mut().set(inputs::KEY, sources.join("\n"));
+    set_retention(&mut writer, &retention);
+    let records = writer.len();
+    history::append(&mut writer);
+    writer.finish().or_exit("Error writing to file!");
+    atomic.commit()?;
+    log::info!("Merged {} records of {} shards into {}", records, paths.len(), output_filename);
+    Ok(())
+}
+
+// Entrypoint for dedup subcommand, keeping the first of every set of duplicate records
+fn dedup(matches: &ArgMatches) -> Result<(), String> {
+    let by: Vec<&str> = matches.values_of("by").unwrap().collect();
+    if !matches!(by.as_slice(), ["hash"] | ["title"] | ["name"] | ["tag", _]) {
+        match by.as_slice() {
+            ["tag"] => invalid_argument("--by tag needs the name of the SD data tag!"),
+            _ => invalid_argument("Specified key is invalid, expected hash, title, name or tag NAME!"),
+        }
+    }
+    let mode = name_match(matches);
+    let input_filename = matches.value_of("input").unwrap();
+    let output_filename = matches.value_of("output").unwrap();
+    if same_file(input_filename, output_filename) {
+        invalid_argument("Output file is the input file!");
+    }
+    let archive = open_archive(matches)?;
+    let source = match by.as_slice() {
+        ["name"] => Some(name_source(matches, Some(&archive))),
+        ["tag", name] => Some(NameSource::Tag(name.to_string())),
+        _ => None,
+    };
+    let dictionary = matches.value_of("dict").map(|filename| Dictionary::load(filename).or_exit("No such dictionary!"));
+    let atomic = atomic_output(matches, output_filename);
+    let mut writer = Writer::with_settings_of(BufWriter::new(overwrite_file(atomic.filename())), &archive, dictionary.as_ref())
+        .or_exit("Unable to create archive!");
+
+    // Compressed records are copied as they are, only decompressed to compute their key
+    let mut seen: std::collections::HashSet<Vec<u8>> = std::collections::HashSet::new();
+    let mut copied: Vec<usize> = Vec::new();
+    for i in 0..archive.len() {
+        let data = archive.read_record(i).unwrap_or_else(|e| extraction_error(&archive, i, e).exit());
+        let key = match (by[0], &source) {
+            ("title", _) => Some(archive::title(&data).into_bytes()),
+            ("name", Some(source)) => cmzip::Record::new(data).name(source).map(|name| mode.key(&name).into_bytes()), // Records without a name are kept
+            (_, Some(source)) => cmzip::Record::new(data).name(source).map(String::into_bytes),
+            _ => Some(Sha256::digest(&data).to_vec()),
+        };
+        if key.is_some_and(|key| !seen.insert(key)) {
+            continue;
+        }
+        let compressed = archive.read_raw(i).unwrap_or_else(|e| extraction_error(&archive, i, e).exit());
+        writer.add_compressed(&compressed).or_exit("Error writing to file!");
+        copied.push(i);
+    }
+    set_retention(&mut writer, &archive.retention().remap(copied));
+    let kept = writer.len();
+    history::append(&mut writer);
+    writer.finish().or_exit("Error writing to file!");
+    atomic.commit()?;
+    log::info!("Dropped {} duplicate records, kept {}", archive.len() - kept, kept);
+    Ok(())
+}
+
+// Entrypoint for prune subcommand, copying the records whose retention didn't expire
+fn prune(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let output_filename = matches.value_of("output").unwrap();
+    if same_file(input_filename, output_filename) {
+        invalid_argument("Output file is the input file!");
+    }
+    let archive = open_archive(matches)?;
+    let dictionary = matches.value_of("dict").map(|filename| Dictionary::load(filename).or_exit("No such dictionary!"));
+    let atomic = atomic_output(matches, output_filename);
+    let mut writer = Writer::with_settings_of(BufWriter::new(overwrite_file(atomic.filename())), &archive, dictionary.as_ref())
+        .or_exit("Unable to create archive!");
+
+    let retention = archive.retention();
+    let now = unix_time();
+    let kept: Vec<usize> = (0..archive.len()).filter(|&i| retention.expiry(i).is_none_or(|expiry| expiry > now)).collect();
+    for &i in &kept {
+        let compressed = archive.read_raw(i).unwrap_or_else(|e| extraction_error(&archive, i, e).exit());
+        writer.add_compressed(&compressed).or_exit("Error writing to file!");
+    }
+    set_retention(&mut writer, &retention.remap(kept));
+    let kept = writer.len();
+    history::append(&mut writer);
+    writer.finish().or_exit("Error writing to file!");
+    atomic.commit()?;
+    log::info!("Dropped {} expired records, kept {}", archive.len() - kept, kept);
+    Ok(())
+}
+
+// Stores the retention of the records written, replacing the one copied with the metadata of the input
+fn set_retention<W: Write>(writer: &mut Writer<W>, retention: &Retention) {
+    if retention.is_empty() {
+        writer.metadata_mut().remove(retention::KEY);
+    } else {
+        writer.metadata_mut().set(retention::KEY, retention.encode());
+    }
+}
+
+// Seconds since the Unix epoch
+fn unix_time() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0)
+}
+
+// Entrypoint for delete subcommand
+fn delete(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let dictionary = matches.value_of("dict").map(|filename| Dictionary::load(filename).or_exit("No such dictionary!"));
+    let archive = open_archive(matches)?;
+    let mut deleted = vec![false; archive.len()];
+    for i in selection::resolve(&record_entries(matches), &archive, archive.name_source(), NameMatch::Exact, matches.is_present("ignore-missing")).unwrap_or_else(|e| invalid_argument(&e)) {
+        deleted[i] = true;
+    }
+    let kept = deleted.iter().filter(|&&deleted| !deleted).count();
+
+    match matches.value_of("output") {
+        Some(output_filename) => {
+            if same_file(input_filename, output_filename) {
+                invalid_argument("Output file is the input file, use --in-place to delete records from it!");
+            }
+            let atomic = atomic_output(matches, output_filename);
+            let mut writer = Writer::with_settings_of(BufWriter::new(overwrite_file(atomic.filename())), &archive, dictionary.as_ref())
+                .or_exit("Unable to create archive!");
+            for i in (0..archive.len()).filter(|&i| !deleted[i]) {
+                let compressed = archive.read_raw(i).unwrap_or_else(|e| extraction_error(&archive, i, e).exit());
+                writer.add_compressed(&compressed).or_exit("Error writing to file!");
+            }
+            set_retention(&mut writer, &archive.retention().remap((0..archive.len()).filter(|&i| !deleted[i])));
+            history::append(&mut writer);
+            writer.finish().or_exit("Error writing to file!");
+            atomic.commit()?;
+        },
+        None => {
+            // Records in front of the first deleted one stay where they are, the runs of records
+            // kept after it are copied back from the intent record of the truncation
+            let first = match deleted.iter().position(|&deleted| deleted) {
+                Some(first) => first,
+                None => return Ok(()),
+            };
+            let retention = archive.retention().remap((0..archive.len()).filter(|&i| !deleted[i]));
+            drop(archive);
+            let mut writer = Writer::truncate(input_filename, first, dictionary.as_ref()).or_exit("Unable to open archive for writing!");
+            let mut start = first;
+            while start < deleted.len() {
+                let run = deleted[start];
+                let end = start + deleted[start..].iter().position(|&deleted| deleted != run).unwrap_or(deleted.len() - start);
+                if !run {
+                    writer.copy_original(start..end).or_exit("Error writing to file!");
+                }
+                start = end;
+            }
+            set_retention(&mut writer, &retention);
+            history::append(&mut writer);
+            writer.finish().or_exit("Error writing to file!");
+        },
+    }
+    log::info!("Deleted {} records, kept {}", deleted.len() - kept, kept);
+    Ok(())
+}
+
+// Entrypoint for redact subcommand, recompressing every record without the given SD data tags
+fn redact(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let output_filename = matches.value_of("output").unwrap();
+    if same_file(input_filename, output_filename) {
+        invalid_argument("Output file is the input file!");
+    }
+    let tags: Vec<&str> = matches.values_of("drop-tags").into_iter().flatten().map(str::trim).filter(|tag| !tag.is_empty()).collect();
+    let rate = units::parse_percent(matches.value_of("watermark-rate").unwrap()).unwrap_or_else(|e| invalid_argument(&e));
+    let archive = open_archive(matches)?;
+    let dictionary = matches.value_of("dict").map(|filename| Dictionary::load(filename).or_exit("No such dictionary!"));
+    let atomic = atomic_output(matches, output_filename);
+    let mut writer = Writer::with_settings_of(BufWriter::new(overwrite_file(atomic.filename())), &archive, dictionary.as_ref())
+        .or_exit("Unable to create archive!")
+        .with_transform(cmzip::transform::DropTags::new(tags.iter().copied()));
+    // Records are marked after redaction, so the mark survives dropping its own tag
+    if let Some(recipient) = matches.value_of("watermark") {
+        writer = writer.with_transform(cmzip::transform::Watermark::new(recipient, rate));
+        writer.metadata_mut().set("watermark", recipient);
+        writer.metadata_mut().set("watermark_rate", matches.value_of("watermark-rate").unwrap().trim());
+    }
+
+    // Tags dropped by earlier redactions stay listed
+    let mut redacted: Vec<String> = archive.metadata().get_str("redacted_tags").unwrap_or("")
+        .split(',').filter(|tag| !tag.is_empty()).map(str::to_string).collect();
+    for tag in &tags {
+        if !redacted.iter().any(|t| t == tag) {
+            redacted.push(tag.to_string());
+        }
+    }
+    if !redacted.is_empty() {
+        writer.metadata_mut().set("redacted_tags", redacted.join(","));
+    }
+
+    for i in 0..archive.len() {
+        let record = archive.read_record(i).unwrap_or_else(|e| extraction_error(&archive, i, e).exit());
+        if let Err(e) = writer.add_record(&record) {
+            Diagnostic::io("Error compressing data!", &e).record(i).name(archive::title(&record)).exit();
+        }
+    }
+    history::append(&mut writer);
+    writer.finish().or_exit("Error writing to file!");
+    atomic.commit()?;
+    Ok(())
+}
+
+// Entrypoint for update subcommand, replacing one record of an archive in place
+fn update(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let dictionary = matches.value_of("dict").map(|filename| Dictionary::load(filename).or_exit("No such dictionary!"));
+    let i = matches.value_of("record").unwrap().parse::<usize>().unwrap_or_else(|_| invalid_argument("Invalid record index!"));
+    let archive = open_archive(matches)?;
+    if i >= archive.len() {
+        invalid_argument(&format!("Record {} does not exist, the archive holds {} records!", i, archive.len()));
+    }
+
+    // The replacement must hold exactly one record
+    let data = std::fs::read(matches.value_of("with").unwrap()).or_exit("No such file!");
+    let mut split = split_options(matches);
+    split.delimiter = archive.metadata().get_str("delimiter");
+    split.at_start = archive.metadata().get_str("delimiter_at") == Some("start");
+    let mut records = sdf_records(&data[..], split);
+    let record = next_record(&mut records, split).unwrap_or_else(|| invalid_argument("Replacement holds no record!"));
+    if next_record(&mut records, split).is_some() {
+        invalid_argument("Replacement holds more than one record!");
+    }
+
+    // Records in front of record i stay where they are, the records after it are copied back
+    // from the intent record of the truncation after the replacement
+    let records = archive.len();
+    drop(archive);
+    let mut writer = Writer::truncate(input_filename, i, dictionary.as_ref()).or_exit("Unable to open archive for writing!");
+    if let Err(e) = writer.add_record(&record) {
+        Diagnostic::io("Error compressing data!", &e).record(i).name(archive::title(&record)).head(&record).exit();
+    }
+    writer.copy_original(i + 1..records).or_exit("Error writing to file!");
+    history::append(&mut writer);
+    writer.finish().or_exit("Error writing to file!");
+    Ok(())
+}
+
+// Entrypoint for recompress subcommand, decompressing every record and compressing it again with
+// another level, codec or dictionary
+fn recompress(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let output_filename = matches.value_of("output").unwrap();
+    if same_file(input_filename, output_filename) {
+        invalid_argument("Output file is the input file!");
+    }
+    let archive = open_archive(matches)?;
+    let codec = match matches.value_of("codec") {
+        Some(codec) => codec.parse::<Codec>().unwrap_or_else(|e| invalid_argument(&e)),
+        None => archive.codec(),
+    };
+    // The level of the input is kept unless the codec changes
+    let level = match matches.value_of("level") {
+        Some(level) => level.parse::<u32>().unwrap_or_else(|_| invalid_argument("Specified level is invalid!")),
+        None if codec == archive.codec() => archive.metadata().get_str("level").and_then(|level| level.parse::<u32>().ok()).unwrap_or(6),
+        None => 6,
+    };
+    if level > codec.max_level() {
+        invalid_argument("Specified level is invalid!");
+    }
+    let xz = xz_settings(matches, codec);
+    let threads = thread_count(matches, cpu_count());
+    let dictionary = matches.value_of("new-dict").map(|filename| Dictionary::load(filename).or_exit("No such dictionary!"));
+
+    let atomic = atomic_output(matches, output_filename);
+    let mut writer = Writer::with_codec(BufWriter::new(overwrite_file(atomic.filename())), codec, level, dictionary.as_ref())
+        .or_exit("Unable to create archive!");
+    xz.apply(&mut writer).or_exit("Unable to create archive!");
+    if let Some(key) = archive.key() {
+        writer.encrypt(key.clone()).or_exit("Error writing to file!");
+    }
+    if matches.is_present("embed-dict") {
+        writer.embed_dictionary(dictionary.as_ref().unwrap());
+    }
+    // Entries not describing the compression, e.g. redacted_tags, are carried over
+    let written = writer.metadata_mut().clone();
+    for (key, value) in archive.metadata().iter() {
+        if written.get(key).is_none() && !key.starts_with("dictionary") && key != "records" && !["level_target_mbps", "preset", "xz_options"].contains(&key) {
+            writer.metadata_mut().set(key, value);
+        }
+    }
+
+    // Every worker borrows an encoder from the pool for each record it compresses
+    let encoders: Mutex<Vec<Encoder>> = Mutex::new(Vec::new());
+    let result = filter_ordered(archive.len(), threads, |i| {
+        let record = archive.read_record(i).unwrap_or_else(|e| extraction_error(&archive, i, e).exit());
+        let pooled = encoders.lock().unwrap().pop();
+        let mut encoder = pooled.unwrap_or_else(|| xz.encoder(codec, level, dictionary.as_ref()).or_exit("Error compressing data!"));
+        let mut compressed: Vec<u8> = Vec::new();
+        if let Err(e) = encoder.compress(&record, &mut compressed) {
+            Diagnostic::io("Error compressing data!", &e).record(i).name(archive::title(&record)).head(&record).exit();
+        }
+        encoders.lock().unwrap().push(encoder);
+        Some((compressed, record.len() as u64))
+    }, |_, (compressed, size)| writer.add_compressed_with_size(&compressed, size));
+    result.or_exit("Error writing to file!");
+    history::append(&mut writer);
+    writer.finish().or_exit("Error writing to file!");
+    atomic.commit()?;
+    Ok(())
+}
+
+// Entrypoint for index subcommand, writing the index sidecar of an archive
+fn index(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let archive = Archive::open(input_filename).or_exit("Unable to read archive index!");
+    if archive.is_index_only() {
+        invalid_argument(&format!("{} only holds the index of an archive, its records are stored elsewhere!", input_filename));
+    }
+    let path = match matches.value_of("output") {
+        Some(filename) => std::path::PathBuf::from(filename),
+        None => cmzip::sidecar::path(std::path::Path::new(input_filename)),
+    };
+    archive.write_sidecar(&path).or_exit(&format!("Couldn't create {}!", path.display()));
+    println!("{}", path.display());
+    Ok(())
+}
+
+// Entrypoint for extract-footer subcommand
+fn extract_footer(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let archive = if matches.is_present("use-backup-index") {
+        Archive::open_backup(input_filename).or_exit("Unable to read backup index!")
+    } else {
+        Archive::open(input_filename).or_exit("Unable to read archive index!")
+    };
+    if archive.is_index_only() {
+        invalid_argument(&format!("{} only holds the index of an archive, its records are stored elsewhere!", input_filename));
+    }
+    let records_end = archive.records_end();
+    drop(archive);
+
+    let mut input = std::fs::File::open(input_filename).or_exit("No such file!");
+    input.seek(std::io::SeekFrom::Start(records_end)).or_exit("Error reading archive!");
+    let copied = std::io::copy(&mut input, &mut create_file(matches.value_of("output").unwrap())).or_exit("Error writing to file!");
+    log::info!("Wrote {} footer bytes from offset {}", copied, records_end);
+    Ok(())
+}
+
+// Entrypoint for replace-footer subcommand
+fn replace_footer(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let footer_filename = matches.value_of("footer").unwrap();
+
+    // The footer has to open on its own, its index tells where the records it describes end
+    let footer = Archive::open(footer_filename).or_exit("Unable to read footer index!");
+    if !footer.is_index_only() && !footer.is_empty() {
+        invalid_argument(&format!("{} is a complete archive, not a footer!", footer_filename));
+    }
+    let records_end = footer.records_end();
+    let records = footer.len();
+    drop(footer);
+    // The archive isn't opened as one, replacing its footer in place locks it and rolls back an
+    // interrupted write of it here
+    let file = match matches.value_of("output") {
+        Some(_) => {
+            intent::check(input_filename);
+            None
+        },
+        None => Some(intent::open(input_filename).map_err(|e| format!("Couldn't open {} for writing: {}", input_filename, e))?),
+    };
+    let input_size = std::fs::metadata(input_filename).or_exit("No such file!").len();
+    if input_size < records_end {
+        return Err(format!("Footer describes {} bytes of records, but {} only holds {} bytes!", records_end, input_filename, input_size));
+    }
+    let footer = std::fs::read(footer_filename).or_exit("No such file!");
+
+    let output_filename = match matches.value_of("output") {
+        Some(output_filename) => {
+            if same_file(input_filename, output_filename) {
+                invalid_argument("Output file is the input file, use --in-place to replace its footer!");
+            }
+            let mut input = std::fs::File::open(input_filename).or_exit("No such file!").take(records_end);
+            let atomic = atomic_output(matches, output_filename);
+            let mut output = BufWriter::new(overwrite_file(atomic.filename()));
+            std::io::copy(&mut input, &mut output).or_exit("Error writing to file!");
+            output.write_all(&footer).or_exit("Error writing to file!");
+            output.flush().or_exit("Error writing to file!");
+            drop(output);
+            atomic.commit()?;
+            output_filename
+        },
+        None => {
+            let mut file = file.unwrap();
+            let intent = Intent::begin(input_filename, &file, records_end).map_err(|e| format!("Couldn't write intent record: {}", e))?;
+            file.set_len(records_end).or_exit("Error writing to file!");
+            file.seek(std::io::SeekFrom::End(0)).or_exit("Error writing to file!");
+            file.write_all(&footer).or_exit("Error writing to file!");
+            intent.finish().or_exit("Error writing to file!");
+            input_filename
+        },
+    };
+    Archive::open(output_filename).or_exit("Unable to read archive index!");
+    log::info!("Replaced footer of {}, it now holds {} records", output_filename, records);
+    Ok(())
+}
+
+// Entrypoint for repair subcommand
+fn repair(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let output_filename = matches.value_of("output").unwrap();
+    if same_file(input_filename, output_filename) {
+        invalid_argument("Output file is the input file, repair writes a new archive!");
+    }
+
+    // Settings and metadata come from whichever footer copy is still readable
+    let original = Archive::open(input_filename).or_else(|_| Archive::open_backup(input_filename)).ok();
+    let atomic = atomic_output(matches, output_filename);
+    let output = BufWriter::new(overwrite_file(atomic.filename()));
+    let mut writer = match &original {
+        Some(archive) if archive.codec() != Codec::Xz => {
+            return Err(format!("{} uses {}, repair only recovers xz records!", input_filename, archive.codec()));
+        },
+        Some(archive) => Writer::with_settings_of(output, archive, None).or_exit("Unable to create archive!"),
+        None => {
+            log::warn!("No readable footer left, the repaired archive records default settings");
+            Writer::new(output, 6)
+        },
+    };
+
+    let mut input = std::fs::File::open(input_filename).or_exit("No such file!");
+    let mut skipped_bytes = 0;
+    repair::scan(&mut input, |found| writer.add_compressed_with_size(&found.compressed, found.size), |offset, length| {
+        log::warn!("Skipped {} damaged bytes at offset {}", length, offset);
+        skipped_bytes += length;
+    }).or_exit("Error repairing archive!");
+    let recovered = writer.len();
+    history::append(&mut writer);
+    writer.finish().or_exit("Error writing to file!");
+    atomic.commit()?;
+
+    match original.map(|archive| archive.len()) {
+        Some(records) if records != recovered => log::info!("Recovered {} of {} records, skipped {} damaged bytes", recovered, records, skipped_bytes),
+        _ => log::info!("Recovered {} records, skipped {} damaged bytes", recovered, skipped_bytes),
+    }
+    Ok(())
+}
+
+// True if both names refer to the same existing file
+fn same_file(a: &str, b: &str) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// Calls work for items 0..count on the given number of threads and hands every result that is not
+// None to output, in item order. Items are processed in batches, every thread taking every n-th
+// item of a batch, and a batch is written before the next one is started.
+fn filter_ordered<T, F, O>(count: usize, threads: usize, work: F, mut output: O) -> std::io::Result<()>
+where
+    T: Send,
+    F: Fn(usize) -> Option<T> + Sync,
+    O: FnMut(usize, T) -> std::io::Result<()>,
+{
+    let batch_size = threads * 64;
+    for batch_start in (0..count).step_by(batch_size) {
+        let batch_end = (batch_start + batch_size).min(count);
+        let slots: Vec<Mutex<Option<T>>> = (batch_start..batch_end).map(|_| Mutex::new(None)).collect();
+        thread::scope(|scope| {
+            for t in 0..threads {
+                let (work, slots) = (&work, &slots);
+                scope.spawn(move || {
+                    for n in (t..slots.len()).step_by(threads) {
+                        *slots[n].lock().unwrap() = work(batch_start + n);
+                    }
+                });
+            }
+        });
+        for (n, slot) in slots.into_iter().enumerate() {
+            if let Some(data) = slot.into_inner().unwrap() {
+                output(batch_start + n, data)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Decompresses the given records on several threads, yielding them in the given order. Records are
+// decompressed in batches, a batch is yielded before the next one is started.
+fn read_parallel(archive: &Archive, records: Vec<usize>, threads: usize) -> impl Iterator<Item = (usize, std::io::Result<Vec<u8>>)> + '_ {
+    let batches: Vec<Vec<usize>> = records.chunks(threads * 64).map(<[usize]>::to_vec).collect();
+    batches.into_iter().flat_map(move |batch| {
+        let mut results: Vec<(usize, std::io::Result<Vec<u8>>)> = Vec::with_capacity(batch.len());
+        filter_ordered(batch.len(), threads, |n| Some(archive.read_record(batch[n])), |n, record| {
+            results.push((batch[n], record));
+            Ok(())
+        }).unwrap();
+        results
+    })
+}
+
+// Number of threads of a mode: its --threads if given, otherwise the global --jobs, otherwise the
+// default of --threads or the number of CPUs. Counts not given by --threads are capped at cap, the
+// most threads the mode can keep busy.
+fn thread_count(matches: &ArgMatches, cap: usize) -> usize {
+    let parse = |threads: &str| threads.parse::<usize>().ok().filter(|&threads| threads > 0)
+        .unwrap_or_else(|| invalid_argument("Specified thread count is invalid!"));
+    let threads = match (matches.value_of("jobs"), matches.value_of("threads")) {
+        _ if matches.occurrences_of("threads") > 0 => parse(matches.value_of("threads").unwrap()),
+        (Some(jobs), _) => parse(jobs).min(cap.max(1)),
+        (None, Some(default)) => parse(default).min(cap.max(1)),
+        (None, None) => cpu_count().min(cap.max(1)),
+    };
+    log::debug!("Threads: {}", threads);
+    threads
+}
+
+fn cpu_count() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+// Archive written by a command, under OUTPUT.tmp until it is complete unless --no-atomic is given
+fn atomic_output(matches: &ArgMatches, filename: &str) -> staging::AtomicOutput {
+    check_overwrite(filename);
+    staging::AtomicOutput::new(filename, !matches.is_present("no-atomic"))
+}
+
+// Bytes of memory a record may take to decompress, given by --memlimit
+fn memlimit(matches: &ArgMatches) -> Option<u64> {
+    matches.value_of("memlimit").map(|size| units::parse_size(size).ok().filter(|&size| size > 0)
+        .unwrap_or_else(|| invalid_argument("Specified memory limit is invalid!")))
+}
+
+// Opens the archive given by --input, with the dictionary given by --dict and the memory limit
+// given by --memlimit if any
+fn open_archive(matches: &ArgMatches) -> Result<Archive, String> {
+    let mut archive = if matches.is_present("use-backup-index") {
+        Archive::open_backup(matches.value_of("input").unwrap()).or_exit("Unable to read backup index!")
+    } else {
+        Archive::open_cached(matches.value_of("input").unwrap()).or_exit("Unable to read archive index!") // Get index from file footer
+    };
+    if archive.is_index_only() {
+        invalid_argument(&format!("{} only holds the index of an archive, its records are stored elsewhere!", matches.value_of("input").unwrap()));
+    }
+    archive.set_memlimit(memlimit(matches));
+    if let Some(filename) = matches.value_of("dict") {
+        archive.set_dictionary(&Dictionary::load(filename).or_exit("No such dictionary!")).or_exit("Unable to use dictionary!");
+    }
+    archive.check_readable().or_exit("Unable to read archive!");
+    log::debug!("Opened {}: {} records, {} codec, {} byte index", matches.value_of("input").unwrap(), archive.len(), archive.codec(), archive.index_size());
+    Ok(archive)
+}
+
+// Record indices given by --record and --records-from in the given order, see selection.rs, those of
+// the records named by --name in archive order, or every record when none is specified
+fn selected_records(matches: &ArgMatches, archive: &Archive) -> Vec<usize> {
+    if let Some(names) = matches.values_of("names") {
+        return named_records(archive, &NameFilter::new(name_source(matches, Some(archive)), name_match(matches), names), matches.is_present("ignore-missing"));
+    }
+    if !matches.is_present("records") && !matches.is_present("records-from") {
+        return (0..archive.len()).collect();
+    }
+    selection::resolve(&record_entries(matches), archive, name_source(matches, Some(archive)), name_match(matches), matches.is_present("ignore-missing")).unwrap_or_else(|e| invalid_argument(&e))
+}
+
+// The records of a random sample given by --sample, stratified by the tag given by --stratify-by,
+// in the order of records, see sample.rs. Counts of every stratum are reported on stderr.
+fn sampled_records(matches: &ArgMatches, archive: &Archive, records: Vec<usize>) -> Vec<usize> {
+    let count = match matches.value_of("sample") {
+        Some(amount) => match units::parse_amount(amount).unwrap_or_else(|e| invalid_argument(&e)) {
+            units::Amount::Count(count) => count as usize,
+            // A share of the whole archive, whatever was selected
+            units::Amount::Fraction(fraction) => (archive.len() as f64 * fraction).round() as usize,
+        },
+        None => return records,
+    };
+    let allocation = matches.value_of("allocation").unwrap().parse::<sample::Allocation>().unwrap_or_else(|e| invalid_argument(&e));
+    let seed = matches.value_of("seed").unwrap().parse::<u64>().unwrap_or_else(|_| invalid_argument("Specified seed is invalid!"));
+    let tag = matches.value_of("stratify-by");
+
+    let mut strata: Vec<(usize, Option<String>)> = Vec::with_capacity(records.len());
+    match tag {
+        // Finding the tag values decompresses every selected record once
+        Some(tag) => filter_ordered(records.len(), thread_count(matches, cpu_count()), |n| {
+            let i = records[n];
+            let record = archive.read_record(i).map(cmzip::Record::new).unwrap_or_else(|e| extraction_error(archive, i, e).exit());
+            Some(record.tag(tag).map(str::to_string))
+        }, |n, value| {
+            strata.push((records[n], value));
+            Ok(())
+        }).or_exit("Error extracting records!"),
+        None => strata.extend(records.iter().map(|&i| (i, None))),
+    }
+    let (picked, strata) = sample::sample(&strata, count, allocation, seed);
+    log::info!("Sampled {} of {} records", picked.len(), records.len());
+    if let Some(tag) = tag {
+        for stratum in &strata {
+            let value = stratum.value.as_ref().map_or_else(|| format!("no {}", tag), |value| format!("{}={}", tag, value));
+            log::info!("  {}: {} of {}", value, stratum.sampled, stratum.records);
+        }
+    }
+    records.into_iter().filter(|i| picked.contains(i)).collect()
+}
+
+// Selection entries of --record followed by those of --records-from
+fn record_entries(matches: &ArgMatches) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = matches.values_of("records").into_iter().flatten()
+        .map(|value| Entry::parse(value, false).unwrap_or_else(|e| invalid_argument(&e)))
+        .collect();
+    if let Some(path) = matches.value_of("records-from") {
+        entries.extend(selection::read_entries(path).unwrap_or_else(|e| invalid_argument(&e)));
+    }
+    entries
+}
+
+// Indices of the records whose name the filter selects, fails if a name is not found unless
+// ignore_missing is set
+fn named_records(archive: &Archive, filter: &NameFilter, ignore_missing: bool) -> Vec<usize> {
+    let mut found: std::collections::HashSet<String> = std::collections::HashSet::new();
+    let mut records: Vec<usize> = Vec::new();
+    for i in 0..archive.len() {
+        let data = archive.read_record(i).unwrap_or_else(|e| extraction_error(archive, i, e).exit());
+        if let Some(name) = filter.matching(&cmzip::Record::new(data)) {
+            found.insert(name);
+            records.push(i);
+        }
+    }
+    match filter.missing(&found) {
+        Some(name) if ignore_missing => log::warn!("No record is named {}, skipping it", name),
+        Some(name) => invalid_argument(&format!("No record is named {}!", name)),
+        None => {},
+    }
+    records
+}
+
+// Source of molecule names given by --name-from, otherwise the one stored in archive
+fn name_source(matches: &ArgMatches, archive: Option<&Archive>) -> NameSource {
+    match matches.value_of("name-from") {
+        Some(source) => source.parse::<NameSource>().unwrap_or_else(|e| invalid_argument(&e)),
+        None => archive.map_or(NameSource::Title, Archive::name_source),
+    }
+}
+
+// Name comparison given by --name-match
+fn name_match(matches: &ArgMatches) -> NameMatch {
+    matches.value_of("name-match").unwrap_or("exact").parse::<NameMatch>().unwrap_or_else(|e| invalid_argument(&e))
+}
+
+// Describes why record i could not be extracted, showing what was decompressed before the failure
+fn extraction_error(archive: &Archive, i: usize, error: std::io::Error) -> Diagnostic {
+    let diagnostic = Diagnostic::io("Error extracting record!", &error).record(i);
+    let range = match archive.record_range(i) {
+        Some(range) => range,
+        None => return diagnostic.help(&format!("the archive holds {} records, indexing starts at 0", archive.len())),
+    };
+    if error.kind() == std::io::ErrorKind::UnexpectedEof {
+        return diagnostic.code("truncated_record").offset(range.start)
+            .help("the archive is cut short, records from here on are lost; unzip --best-effort extracts the intact ones");
+    }
+    if error.kind() == std::io::ErrorKind::OutOfMemory {
+        return diagnostic.code("memory_limit").offset(range.start)
+            .help("the record may be a decompression bomb, raise --memlimit if the archive is trusted");
+    }
+    if error.kind() == std::io::ErrorKind::FileTooLarge {
+        return diagnostic.code("record_too_large").offset(range.start)
+            .help("the record may be a decompression bomb, raise --max-record-size if the archive is trusted");
+    }
+    let mut partial: Vec<u8> = Vec::new();
+    let _ = archive.extract(i, &mut partial);
+    let diagnostic = diagnostic.name(archive::title(&partial)).offset(range.start);
+    if diagnostic.get_code() != "corrupt_data" {
+        return diagnostic; // The record itself is fine, writing the output failed
+    }
+    diagnostic.code("corrupt_record")
+        .tail(&partial, "decompression stopped here")
+        .help("the compressed record is damaged, re-create the archive from the source SD file")
+}
+
+// Entrypoint for info subcommand
+fn info(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let archive = Archive::open_cached(input_filename).or_exit("Unable to read archive index!");
+    let metadata = archive.metadata();
+    if matches.is_present("json") {
+        println!("{}", info_json(matches, &archive));
+        return Ok(());
+    }
+
+    println!("records: {}", archive.len());
+    println!("file size: {}", archive.file_size());
+    println!("index size: {}", archive.index_size());
+    println!("codec: {}", archive.codec());
+    match (metadata.get_str("level"), metadata.get_str("level_target_mbps")) {
+        (Some(level), Some(target_mbps)) => println!("level: {} (auto, for {} MB/s)", level, target_mbps),
+        (Some(level), None) => println!("level: {}", level),
+        _ => {},
+    }
+    if let Some(preset) = xz_preset(metadata, archive.codec()) {
+        println!("preset: {}", preset);
+    }
+    if let Some(options) = metadata.get_str("xz_options") {
+        println!("xz options: {}", options);
+    }
+    if let Some(id) = metadata.get_str("dictionary_id") {
+        let embedded = if metadata.get("dictionary").is_some() { "embedded" } else { "referenced" };
+        println!("dictionary: {} ({})", id, embedded);
+    }
+    if archive.is_encrypted() {
+        println!("encryption: {}", metadata.get_str("encryption").unwrap_or(cmzip::crypto::ALGORITHM));
+    }
+    if let Some(tags) = metadata.get_str("redacted_tags") {
+        println!("redacted tags: {}", tags);
+    }
+    if let Some(recipient) = metadata.get_str("watermark") {
+        println!("watermark: {} ({})", recipient, cmzip::transform::Watermark::mark(recipient));
+    }
+    // Only recorded by cmzip zip
+    for line in metadata.get_str(inputs::KEY).unwrap_or("").lines() {
+        match line.split_once(' ') {
+            Some(("none", path)) => println!("source: {} (no records)", path),
+            Some((range, path)) => println!("source: {} (records {})", path, range),
+            None => {},
+        }
+    }
+    let retention = archive.retention();
+    if !retention.is_empty() {
+        println!("retention: {} records expire, {} of them expired", retention.expiring(), retention.expired(unix_time()));
+    }
+    let format = match metadata.get_str("format") {
+        Some(format) => format,
+        None if metadata.get("delimiter").is_some() => "custom",
+        None => "sdf",
+    };
+    println!("format: {}", format);
+    match metadata.get_str("molfile_versions") {
+        Some(versions) => println!("molfile versions: {}", versions),
+        None if archive.is_empty() => println!("molfile versions: none"),
+        None => println!("molfile versions: unknown"),
+    }
+    match metadata.get_str("delimiter") {
+        Some(delimiter) if metadata.get_str("delimiter_at") == Some("start") => println!("delimiter: {} (starts records)", delimiter),
+        Some(delimiter) => println!("delimiter: {} (ends records)", delimiter),
+        None => println!("delimiter: $$$$ (ends records)"),
+    }
+    println!("names: {}", archive.name_source());
+    match archive.health() {
+        Some(_) if archive.is_empty() => println!("record sizes: none"),
+        Some(health) => {
+            println!("record sizes: {} to {}, {} in total", units::format_size(health.min_size), units::format_size(health.max_size), units::format_size(health.total_size));
+            let state = if archive.health_matches() == Some(true) { "matches the index" } else { "does not match the index" };
+            println!("health: {} {} ({})", health.checksum, health.index_digest, state);
+        },
+        None => println!("health: unknown"),
+    }
+
+    if matches.is_present("encoder") {
+        // Archives from before encoder tracking carry none of these fields
+        let fields = ["encoder", "codec_version", "parameters"];
+        let values: Vec<&str> = fields.iter().map(|key| metadata.get_str(key).unwrap_or("unknown")).collect();
+        println!("encoder: {}", values[0]);
+        println!("codec version: {}", values[1]);
+        println!("parameters: {}", values[2]);
+        // Short digest of all three, equal fingerprints mean identical encoder settings
+        if fields.iter().all(|key| metadata.get_str(key).is_some()) {
+            let digest = Sha256::digest(values.join("\n").as_bytes());
+            let fingerprint: String = digest.iter().take(8).map(|b| format!("{:02x}", b)).collect();
+            println!("fingerprint: {}", fingerprint);
+        } else {
+            println!("fingerprint: unknown");
+        }
+    }
+    Ok(())
+}
+
+// Effective xz preset of an archive, e.g. 9e, from its level for archives written before presets
+// were recorded
+fn xz_preset(metadata: &cmzip::metadata::Metadata, codec: Codec) -> Option<String> {
+    match codec {
+        Codec::Xz => metadata.get_str("preset").or_else(|| metadata.get_str("level")).map(str::to_string),
+        Codec::Zstd => None,
+    }
+}
+
+// Fields of info as one JSON object
+fn info_json(matches: &ArgMatches, archive: &Archive) -> String {
+    let metadata = archive.metadata();
+    let list = |values: Vec<String>| format!("[{}]", values.join(","));
+    let mut fields: Vec<(&str, String)> = vec![
+        ("records", archive.len().to_string()),
+        ("file_size", archive.file_size().to_string()),
+        ("index_size", archive.index_size().to_string()),
+        ("codec", json::string(&archive.codec().to_string())),
+        ("level", metadata.get_str("level").and_then(|level| level.parse::<u32>().ok()).map_or_else(|| "null".to_string(), |level| level.to_string())),
+        ("preset", json::optional(xz_preset(metadata, archive.codec()).as_deref())),
+        ("xz_options", json::optional(metadata.get_str("xz_options"))),
+        ("level_target_mbps", metadata.get_str("level_target_mbps").and_then(|mbps| mbps.parse::<f64>().ok()).map_or_else(|| "null".to_string(), |mbps| mbps.to_string())),
+    ];
+    if let Some(id) = metadata.get_str("dictionary_id") {
+        fields.push(("dictionary", json::object(&[("id", json::string(id)), ("embedded", metadata.get("dictionary").is_some().to_string())])));
+    }
+    if archive.is_encrypted() {
+        fields.push(("encryption", json::string(metadata.get_str("encryption").unwrap_or(cmzip::crypto::ALGORITHM))));
+    }
+    if let Some(tags) = metadata.get_str("redacted_tags") {
+        fields.push(("redacted_tags", list(tags.split(',').map(json::string).collect())));
+    }
+    if let Some(recipient) = metadata.get_str("watermark") {
+        fields.push(("watermark", json::object(&[("recipient", json::string(recipient)), ("mark", json::string(&cmzip::transform::Watermark::mark(recipient)))])));
+    }
+    let sources = metadata.get_str(inputs::KEY).unwrap_or("").lines().filter_map(|line| match line.split_once(' ') {
+        Some(("none", path)) => Some(json::object(&[("path", json::string(path)), ("records", "null".to_string())])),
+        Some((range, path)) => Some(json::object(&[("path", json::string(path)), ("records", json::string(range))])),
+        None => None,
+    }).collect();
+    fields.push(("sources", list(sources)));
+    let retention = archive.retention();
+    if !retention.is_empty() {
+        fields.push(("retention", json::object(&[("expiring", retention.expiring().to_string()), ("expired", retention.expired(unix_time()).to_string())])));
+    }
+    let format = match metadata.get_str("format") {
+        Some(format) => format,
+        None if metadata.get("delimiter").is_some() => "custom",
+        None => "sdf",
+    };
+    fields.push(("format", json::string(format)));
+    let versions = match metadata.get_str("molfile_versions") {
+        Some(versions) => list(versions.split(',').map(json::string).collect()),
+        None if archive.is_empty() => list(Vec::new()),
+        None => "null".to_string(),
+    };
+    fields.push(("molfile_versions", versions));
+    fields.push(("delimiter", json::object(&[
+        ("text", json::string(metadata.get_str("delimiter").unwrap_or("$$$$"))),
+        ("starts_records", (metadata.get_str("delimiter_at") == Some("start")).to_string()),
+    ])));
+    fields.push(("name_from", json::string(&archive.name_source().to_string())));
+    fields.push(("health", match archive.health() {
+        Some(health) => json::object(&[
+            ("min_record_size", if archive.is_empty() { "null".to_string() } else { health.min_size.to_string() }),
+            ("max_record_size", if archive.is_empty() { "null".to_string() } else { health.max_size.to_string() }),
+            ("total_record_size", health.total_size.to_string()),
+            ("checksum", json::string(&health.checksum)),
+            ("index_digest", json::string(&health.index_digest)),
+            ("matches", (archive.health_matches() == Some(true)).to_string()),
+        ]),
+        None => "null".to_string(),
+    }));
+    if matches.is_present("encoder") {
+        let keys = ["encoder", "codec_version", "parameters"];
+        let mut encoder: Vec<(&str, String)> = keys.iter().map(|&key| (key, json::optional(metadata.get_str(key)))).collect();
+        let values: Option<Vec<&str>> = keys.iter().map(|key| metadata.get_str(key)).collect();
+        let fingerprint = values.map(|values| Sha256::digest(values.join("\n").as_bytes()).iter().take(8).map(|b| format!("{:02x}", b)).collect::<String>());
+        encoder.push(("fingerprint", json::optional(fingerprint.as_deref())));
+        fields.push(("encoder", json::object(&encoder)));
+    }
+    json::object(&fields)
+}
+
+// Entrypoint for list subcommand, printing the index, offset, compressed and decompressed size and
+// molecule name of every record, or with --json one JSON object per record and line
+fn list(matches: &ArgMatches) -> Result<(), String> {
+    let archive = open_archive(matches)?;
+    let json = matches.is_present("json");
+    // Only SD records hold molfiles, mol2 and records split at a custom delimiter don't
+    let metadata = archive.metadata();
+    let sdf = metadata.get_str("format").is_none_or(|format| format == "sdf") && metadata.get("delimiter").is_none();
+    let stdout = std::io::stdout();
+    let mut output = BufWriter::new(stdout.lock());
+    let result = filter_ordered(archive.len(), thread_count(matches, cpu_count()), |i| {
+        let record = archive.read_record(i).unwrap_or_else(|e| extraction_error(&archive, i, e).exit());
+        let range = archive.record_range(i).unwrap();
+        let (offset, compressed) = (range.start, range.end - range.start);
+        let version = if sdf { cmzip::sdf::molfile_version(&record) } else { None };
+        Some(match json {
+            true => json::object(&[
+                ("index", i.to_string()),
+                ("offset", offset.to_string()),
+                ("compressed_size", compressed.to_string()),
+                ("size", record.len().to_string()),
+                ("version", json::optional(version)),
+                ("name", json::string(&archive::title(&record))),
+            ]),
+            false => format!("{}\t{}\t{}\t{}\t{}\t{}", i, offset, compressed, record.len(), version.unwrap_or("-"), archive::title(&record)),
+        })
+    }, |_, line| writeln!(output, "{}", line)).and_then(|_| output.flush());
+    match result {
+        Err(ref e) if e.kind() == std::io::ErrorKind::BrokenPipe => {},
+        result => result.or_exit("Error writing to stdout!"),
+    }
+    Ok(())
+}
+
+// Entrypoint for history subcommand, printing the audit log of an archive oldest first
+fn history(matches: &ArgMatches) -> Result<(), String> {
+    let metadata = Archive::read_metadata(matches.value_of("input").unwrap()).or_exit("Unable to read archive index!");
+    let entries = history::entries(&metadata);
+    if entries.is_empty() {
+        log::info!("The archive holds no history, it was written before cmzip recorded it or by other tools.");
+    }
+    for entry in entries {
+        println!("{}", entry);
+    }
+    Ok(())
+}
+
+// Entrypoint for verify subcommand, decompressing every record to find damaged ones
+fn verify(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let json = matches.is_present("json");
+    let signature = check_signature(matches);
+    let archive = open_archive(matches)?;
+    if archive.health_matches() == Some(false) {
+        Diagnostic::error(&format!("Index of {} doesn't match its health checksum!", input_filename)).code("checksum_mismatch").exit();
+    }
+    let max_ratio: f64 = matches.value_of("max-ratio").unwrap().parse().ok().filter(|&ratio: &f64| ratio >= 1.0)
+        .unwrap_or_else(|| invalid_argument("Specified maximum ratio is invalid, expected a number of at least 1!"));
+    // Only SD records hold molblocks, mol2 and records split at a custom delimiter don't
+    let metadata = archive.metadata();
+    let sdf = metadata.get_str("format").is_none_or(|format| format == "sdf") && metadata.get("delimiter").is_none();
+    let check_molblock = sdf && !matches.is_present("no-molblock-check");
+    let manifest = matches.value_of("manifest").map(checksums::read).transpose()?;
+    let mut archive_matches = true;
+    if let Some(manifest) = &manifest {
+        archive_matches = checksums::file_digest(input_filename).or_exit("Error reading archive!") == manifest.archive;
+        if !json {
+            println!("archive: {}", if archive_matches { "matches the manifest" } else { "differs from the manifest" });
+        }
+    }
+    let digests = manifest.as_ref().and_then(|manifest| manifest.records.as_ref());
+    let damaged = std::sync::atomic::AtomicUsize::new(0);
+    let (mut suspicious, mut changed) = (0, 0);
+    let mut findings: Vec<String> = Vec::new(); // JSON objects of the changed and suspicious records
+    filter_ordered(archive.len(), thread_count(matches, cpu_count()), |i| {
+        let range = archive.record_range(i).unwrap();
+        let limit = ((range.end - range.start) as f64 * max_ratio) as usize;
+        let mut record = CappedBuffer { data: Vec::new(), limit, exceeded: false };
+        if let Err(e) = archive.extract(i, &mut record) {
+            if !record.exceeded {
+                extraction_error(&archive, i, e).emit();
+                damaged.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                return None;
+            }
+        }
+        let problem = match record.exceeded {
+            true => Some(format!("expands more than {}x, stopped at {} bytes", max_ratio, limit)),
+            false if check_molblock => cmzip::sdf::validate(&record.data).err(),
+            false => None,
+        };
+        let differs = !record.exceeded && digests.is_some_and(|digests| digests.get(i).is_some_and(|digest| *digest != hex_digest(&record.data)));
+        if problem.is_none() && !differs {
+            return None;
+        }
+        Some((i, archive::title(&record.data), range.start, problem, differs))
+    }, |_, (i, name, offset, problem, differs)| {
+        changed += usize::from(differs);
+        suspicious += usize::from(problem.is_some());
+        if json {
+            findings.push(json::object(&[
+                ("record", i.to_string()),
+                ("name", json::string(&name)),
+                ("offset", offset.to_string()),
+                ("changed", differs.to_string()),
+                ("problem", json::optional(problem.as_deref())),
+            ]));
+            return Ok(());
+        }
+        let record = format!("record {} \"{}\" at byte {}", i, name, offset);
+        if differs {
+            writeln!(std::io::stdout(), "changed {}: doesn't match the manifest", record)?;
+        }
+        if let Some(problem) = problem {
+            writeln!(std::io::stdout(), "suspicious {}: {}", record, problem)?;
+        }
+        Ok(())
+    }).or_exit("Error writing to stdout!");
+    let damaged = damaged.into_inner();
+
+    let failure = match digests {
+        _ if damaged > 0 => Some(("corrupt_record", format!("{} of {} records of {} are damaged!", damaged, archive.len(), input_filename))),
+        Some(digests) if digests.len() != archive.len() => {
+            Some(("checksum_mismatch", format!("The manifest lists {} records, {} holds {}!", digests.len(), input_filename, archive.len())))
+        },
+        Some(_) if changed > 0 => {
+            Some(("checksum_mismatch", format!("{} of {} records of {} don't match the manifest!", changed, archive.len(), input_filename)))
+        },
+        None if !archive_matches => Some(("checksum_mismatch", format!("{} doesn't match its manifest!", input_filename))),
+        _ if suspicious > 0 => Some(("suspicious_record", format!("{} of {} records of {} are suspicious!", suspicious, archive.len(), input_filename))),
+        _ => None,
+    };
+    if json {
+        let manifest = manifest.as_ref().map_or_else(|| "null".to_string(), |manifest| json::object(&[
+            ("archive_matches", archive_matches.to_string()),
+            ("records", manifest.records.as_ref().map_or_else(|| "null".to_string(), |digests| digests.len().to_string())),
+        ]));
+        println!("{}", json::object(&[
+            ("input", json::string(input_filename)),
+            ("records", archive.len().to_string()),
+            ("signature", signature.as_deref().map_or_else(|| "null".to_string(), |comment| json::object(&[("trusted_comment", json::string(comment))]))),
+            ("manifest", manifest),
+            ("damaged", damaged.to_string()),
+            ("changed", changed.to_string()),
+            ("suspicious", suspicious.to_string()),
+            ("findings", format!("[{}]", findings.join(","))),
+            ("verified", failure.is_none().to_string()),
+        ]));
+    }
+    if let Some((code, message)) = failure {
+        Diagnostic::error(&message).code(code).exit();
+    }
+    if !json {
+        println!("Verified {} records of {}", archive.len(), input_filename);
+    }
+    Ok(())
+}
+
+// Output of a record that fails once it holds more than limit bytes, so verify decompresses
+// runaway records only that far
+struct CappedBuffer {
+    data: Vec<u8>,
+    limit: usize,
+    exceeded: bool,
+}
+
+impl Write for CappedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.data.len() + buf.len() > self.limit {
+            self.exceeded = true;
+            return Err(std::io::Error::other("Record exceeds the size limit!"));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Checks the signature given by --signature, or INPUT.minisig, against --pubkey if it is given,
+// returns its trusted comment
+fn check_signature(matches: &ArgMatches) -> Option<String> {
+    let key = matches.value_of("pubkey")?;
+    let input_filename = matches.value_of("input").unwrap();
+    let signature = matches.value_of("signature").map_or_else(|| signing::signature_path(input_filename), str::to_string);
+    let comment = signing::verify(input_filename, &signature, key).unwrap_or_else(|diagnostic| diagnostic.exit());
+    log::info!("Good signature of {}, trusted comment: {}", input_filename, comment);
+    Some(comment)
+}
+
+// Entrypoint for hash subcommand, writing the digest of the archive and with --per-record those of
+// its decompressed records
+fn hash(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let archive = open_archive(matches)?;
+    let output_filename = matches.value_of("output").map_or_else(|| checksums::manifest_path(input_filename), str::to_string);
+    check_overwrite(&output_filename);
+    let digest = checksums::file_digest(input_filename).or_exit("Error reading archive!");
+    let mut output = BufWriter::new(create_file(&output_filename));
+    output.write_all(checksums::archive_line(&digest, input_filename).as_bytes()).or_exit("Error writing to file!");
+    if matches.is_present("per-record") {
+        filter_ordered(archive.len(), thread_count(matches, cpu_count()), |i| {
+            let record = archive.read_record(i).unwrap_or_else(|e| extraction_error(&archive, i, e).exit());
+            Some(checksums::record_line(i, &hex_digest(&record), &archive::title(&record)))
+        }, |_, line| output.write_all(line.as_bytes())).or_exit("Error writing to file!");
+    }
+    output.flush().or_exit("Error writing to file!");
+    log::info!("Wrote the SHA-256 manifest of {} to {}", input_filename, output_filename);
+    Ok(())
+}
+
+// Entrypoint for sign subcommand, writing a detached minisign signature of an archive
+fn sign(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    Archive::open(input_filename).or_exit("Unable to read archive index!");
+    let output_filename = matches.value_of("output").map_or_else(|| signing::signature_path(input_filename), str::to_string);
+    check_overwrite(&output_filename);
+    let signature = signing::sign(input_filename, matches.value_of("key").unwrap(), matches.value_of("comment"))?;
+    std::fs::write(&output_filename, signature).or_exit("Error writing to file!");
+    log::info!("Signed {} in {}", input_filename, output_filename);
+    Ok(())
+}
+
+// Entrypoint for stats subcommand, estimating the distinct values of tags with one HyperLogLog sketch
+// per tag (see hll.rs), so memory stays the same however many records the archive holds
+fn stats(matches: &ArgMatches) -> Result<(), String> {
+    let archive = open_archive(matches)?;
+    let precision = matches.value_of("precision").unwrap().parse::<u32>().ok().filter(|precision| hll::PRECISIONS.contains(precision))
+        .unwrap_or_else(|| invalid_argument("Specified precision is invalid!"));
+    let mut tags: Vec<Option<&str>> = matches.values_of("distinct").into_iter().flatten().map(Some).collect();
+    if matches.is_present("titles") {
+        tags.push(None);
+    }
+    let mut sketches: Vec<hll::HyperLogLog> = tags.iter().map(|_| hll::HyperLogLog::new(precision)).collect();
+    let mut missing: Vec<usize> = vec![0; tags.len()];
+
+    filter_ordered(archive.len(), thread_count(matches, cpu_count()), |i| {
+        let record = archive.read_record(i).map(cmzip::Record::new).unwrap_or_else(|e| extraction_error(&archive, i, e).exit());
+        Some(tags.iter().map(|tag| match tag {
+            Some(tag) => record.tag(tag).map(str::to_string),
+            None => Some(record.title()),
+        }).collect::<Vec<Option<String>>>())
+    }, |_, values| {
+        for (n, value) in values.into_iter().enumerate() {
+            match value {
+                Some(value) => sketches[n].insert(value.as_bytes()),
+                None => missing[n] += 1,
+            }
+        }
+        Ok(())
+    }).or_exit("Error extracting records!");
+
+    println!("records: {}", archive.len());
+    for ((tag, sketch), missing) in tags.iter().zip(&sketches).zip(missing) {
+        let estimate = if missing == archive.len() { 0 } else { sketch.estimate() };
+        println!("distinct {}: ~{} (±{:.1}%), {} records without it", tag.unwrap_or("titles"), estimate, sketch.error() * 100.0, missing);
+    }
+    Ok(())
+}
+
+// Entrypoint for bench subcommand, compressing and decompressing the first records of an SD file
+// with every configuration, one record at a time as in an archive
+fn bench(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let codecs = matches.value_of("codecs").unwrap().split(',').map(|codec| codec.trim().parse::<Codec>()).collect::<Result<Vec<Codec>, String>>().unwrap_or_else(|e| invalid_argument(&e));
+    let levels = matches.value_of("levels").unwrap().split(',').map(|level| level.trim().parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>().unwrap_or_else(|| invalid_argument("Specified levels are invalid!"));
+    let sample = matches.value_of("sample").unwrap().parse::<usize>().ok().filter(|&n| n > 0)
+        .unwrap_or_else(|| invalid_argument("Specified sample size is invalid!"));
+    let dictionary = matches.value_of("dict").map(|filename| Dictionary::load(filename).or_exit("No such dictionary!"));
+    let records = sample_records(input_filename, split_options(matches), sample, usize::MAX);
+    if records.is_empty() {
+        return Err(format!("{} holds no records!", input_filename));
+    }
+    let size: u64 = records.iter().map(|record| record.len() as u64).sum();
+    log::info!("Sample: {} records, {}", records.len(), units::format_size(size));
+    let speed = |bytes_per_second: f64| units::format_size(bytes_per_second as u64) + "/s";
+
+    let json = matches.is_present("json");
+    if !json {
+        println!("{:<6} {:>5} {:>7} {:>14} {:>14} {:>11}", "codec", "level", "ratio", "compress", "decompress", "memory");
+    }
+    for &codec in &codecs {
+        for &level in levels.iter().filter(|&&level| level <= codec.max_level()) {
+            let dictionary = dictionary.as_ref().filter(|_| codec == Codec::Zstd);
+            let measurement = Encoder::new(codec, level, dictionary).and_then(|encoder| tuning::measure(encoder, dictionary, &records)).or_exit("Error compressing data!");
+            if json {
+                println!("{}", json::object(&[
+                    ("codec", json::string(codec.name())),
+                    ("level", level.to_string()),
+                    ("records", records.len().to_string()),
+                    ("size", size.to_string()),
+                    ("compressed_size", measurement.compressed_size.to_string()),
+                    ("ratio", format!("{:.3}", measurement.ratio())),
+                    ("compress_seconds", format!("{:.6}", measurement.compress_time.as_secs_f64())),
+                    ("decompress_seconds", format!("{:.6}", measurement.decompress_time.as_secs_f64())),
+                    ("memory", measurement.memory.to_string()),
+                ]));
+            } else {
+                println!("{:<6} {:>5} {:>7.2} {:>14} {:>14} {:>11}", codec.name(), level, measurement.ratio(),
+                    speed(measurement.compress_speed()), speed(measurement.decompress_speed()), units::format_size(measurement.memory));
+            }
+        }
+        if let Some(level) = levels.iter().find(|&&level| level > codec.max_level()) {
+            log::warn!("Level {} is above the highest {} level {}, left out.", level, codec, codec.max_level());
+        }
+    }
+    Ok(())
+}
+
+// Entrypoint for offset subcommand
+fn offset(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let i = matches.value_of("record").unwrap().parse::<usize>().unwrap_or_else(|_| invalid_argument("Invalid record index!"));
+    let archive = Archive::open_cached(input_filename).or_exit("Unable to read archive index!");
+    let range = archive.record_range(i)
+        .unwrap_or_else(|| invalid_argument(&format!("Record {} does not exist, the archive holds {} records!", i, archive.len())));
+
+    // The range is inclusive, as expected by HTTP range requests (curl -r)
+    println!("record: {}", i);
+    println!("offset: {}", range.start);
+    println!("size: {}", range.end - range.start);
+    println!("range: {}-{}", range.start, range.end - 1);
+    println!("codec: {}", archive.codec());
+    if archive.is_encrypted() {
+        // The range holds the sealed record, standard tools can't decompress it
+        println!("encryption: {}", cmzip::crypto::ALGORITHM);
+        log::warn!("{} is encrypted, the range holds a {}-byte nonce, the encrypted record and its tag; raw get decrypts it", input_filename, cmzip::crypto::NONCE_SIZE);
+    }
+    Ok(())
+}
+
+// Entrypoint for chunks subcommand, listing the content-defined chunks of an archive file as
+// "offset length sha256" lines
+fn chunks(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let avg = units::parse_size(matches.value_of("avg-size").unwrap()).unwrap_or_else(|e| invalid_argument(&e));
+    let min = matches.value_of("min-size").map(units::parse_size).transpose().unwrap_or_else(|e| invalid_argument(&e)).unwrap_or(avg / 4);
+    let max = matches.value_of("max-size").map(units::parse_size).transpose().unwrap_or_else(|e| invalid_argument(&e)).unwrap_or(avg.saturating_mul(4));
+    if min == 0 || min >= avg || avg > max {
+        invalid_argument("Chunk sizes have to be ordered as 0 < min-size < avg-size <= max-size!");
+    }
+    Archive::open_cached(input_filename).or_exit("Unable to read archive index!"); // Only archives are chunked
+    let input = std::fs::File::open(input_filename).or_exit("No such file!");
+
+    let stdout = std::io::stdout();
+    let mut output: Box<dyn Write> = match matches.value_of("output") {
+        Some(filename) => Box::new(BufWriter::new(create_file(filename))),
+        None => Box::new(BufWriter::new(stdout.lock())),
+    };
+    let (mut count, mut total) = (0u64, 0u64);
+    let result = chunks::split(input, &chunks::Sizes { min, avg, max }, |chunk| {
+        count += 1;
+        total += chunk.length;
+        let digest: String = chunk.digest.iter().map(|b| format!("{:02x}", b)).collect();
+        writeln!(output, "{} {} {}", chunk.offset, chunk.length, digest)
+    }).and_then(|_| output.flush());
+    match result {
+        Err(ref e) if e.kind() == std::io::ErrorKind::BrokenPipe => {},
+        result => result.or_exit("Error chunking archive!"),
+    }
+    log::info!("{} chunks of {} on average", count, units::format_size(total / count.max(1)));
+    Ok(())
+}
+
+// Entrypoint for script subcommand, writing a POSIX shell script that extracts the selected records
+// with dd and the codec's command line tool, using the byte ranges of the index
+fn script(matches: &ArgMatches) -> Result<(), String> {
+    let input_filename = matches.value_of("input").unwrap();
+    let archive = Archive::open_cached(input_filename).or_exit("Unable to read archive index!");
+    if archive.is_index_only() {
+        invalid_argument(&format!("{} only holds the index of an archive, its records are stored elsewhere!", input_filename));
+    }
+    if archive.is_encrypted() {
+        invalid_argument(&format!("{} is encrypted, {} can't decompress its sealed records!", input_filename, archive.codec()));
+    }
+    let records = selected_records(matches, &archive);
+
+    // Delta filters are part of standard xz streams, anything else in front of the stream magic isn't
+    let magic: &[u8] = match archive.codec() {
+        Codec::Xz => &[0xFD, b'7', b'z', b'X', b'Z', 0x00],
+        _ => &[0x28, 0xB5, 0x2F, 0xFD],
+    };
+    let mut input = std::fs::File::open(input_filename).or_exit("Unable to read archive!");
+    for &i in &records {
+        let mut head = vec![0; magic.len()];
+        let range = archive.record_range(i).unwrap();
+        input.seek(std::io::SeekFrom::Start(range.start)).and_then(|_| input.read_exact(&mut head)).or_exit("Unable to read archive!");
+        if head != magic {
+            invalid_argument(&format!("Record {} of {} isn't a standard {} stream, {} can't decompress it!", i, input_filename, archive.codec(), archive.codec()));
+        }
+    }
+
+    // Records are complete xz or zstd streams, zstd archives with a dictionary need it as a file
+    let dictionary = archive.metadata().get_str("dictionary_sha256").is_some();
+    let decompress = match (archive.codec(), dictionary) {
+        (Codec::Xz, _) => "xz -dc",
+        (_, false) => "zstd -dcq",
+        (_, true) => "zstd -dcq -D \"$dictionary\"",
+    };
+    let mut script = String::new();
+    script += "#!/bin/sh\n";
+    script += &format!("# Extracts {} records of {} ({}), written by cmzip script.\n", records.len(), input_filename, archive.codec());
+    if dictionary {
+        script += "# Usage: sh script.sh ARCHIVE DICTIONARY > records.sdf\n";
+        script += "# The dictionary is written by cmzip dict export if it is embedded in the archive.\n";
+    } else {
+        script += "# Usage: sh script.sh [ARCHIVE] > records.sdf\n";
+    }
+    script += "set -e\n";
+    script += &format!("archive={}\n", shell_quote(input_filename));
+    script += "archive=\"${1:-$archive}\"\n";
+    if dictionary {
+        script += "dictionary=\"${2:?the archive was compressed with a dictionary, pass it as the second argument}\"\n";
+    }
+    script += &format!("record() {{\n    dd if=\"$archive\" bs=1 skip=\"$1\" count=\"$2\" 2>/dev/null | {}\n}}\n", decompress);
+    for i in records {
+        let range = archive.record_range(i).unwrap();
+        script += &format!("record {} {} # {}\n", range.start, range.end - range.start, i);
+    }
+
+    match matches.value_of("output") {
+        Some(filename) => create_file(filename).write_all(script.as_bytes()).or_exit("Error writing to file!"),
+        None => print!("{}", script),
+    }
+    Ok(())
+}
+
+// Word the shell reads as s, in single quotes unless it only has safe characters
+fn shell_quote(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "._-/+".contains(c)) {
+        return s.to_string();
+    }
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+// Entrypoint for fetch-index subcommand
+fn fetch_index(matches: &ArgMatches) -> Result<(), String> {
+    let url = matches.value_of("url").unwrap();
+    let mut output_filename = matches.value_of("output").unwrap().to_string();
+    if !output_filename.ends_with(".cmzi") {
+        output_filename += ".cmzi";
+    }
+
+    // The footer on its own opens as an archive whose records are stored elsewhere
+    let limit = matches.value_of("limit-rate").map(rate::RateLimit::parse).transpose().unwrap_or_else(|e| invalid_argument(&e));
+    let footer = remote::fetch_footer(url, limit.as_ref()).or_exit("Unable to fetch archive index!");
+    create_file(&output_filename).write_all(&footer).or_exit("Error writing to file!");
+    let archive = Archive::open(&output_filename).or_exit("Unable to read archive index!");
+    log::info!("Fetched index of {} records ({} bytes) from {}", archive.len(), footer.len(), url);
+    Ok(())
+}
+
+// Entrypoint for serve subcommand
+fn serve(matches: &ArgMatches) -> Result<(), String> {
+    // Setup variables from command line input
+    let input_filename = matches.value_of("input").unwrap();
+    let host = matches.value_of("host").unwrap();
+    let port = matches.value_of("port").unwrap().parse::<u16>().unwrap_or_else(|_| invalid_argument("Specified port is invalid!"));
+    let workers = matches.value_of("workers").unwrap().parse::<usize>().unwrap_or_else(|_| invalid_argument("Specified worker count is invalid!"));
+    if workers == 0 {
+        invalid_argument("Specified worker count is invalid!");
+    }
+
+    let mut archive = Archive::open_cached(input_filename).or_exit("Unable to read archive index!");
+    archive.set_memlimit(memlimit(matches));
+    if let Some(filename) = matches.value_of("dict") {
+        archive.set_dictionary(&Dictionary::load(filename).or_exit("No such dictionary!")).or_exit("Unable to use dictionary!");
+    }
+    archive.check_readable().or_exit("Unable to read archive!");
+    let limit = matches.value_of("limit-rate").map(rate::RateLimit::parse).transpose().unwrap_or_else(|e| invalid_argument(&e));
+    let jobs = matches.value_of("jobs-dir").map(std::path::PathBuf::from);
+    if jobs.as_ref().is_some_and(|jobs| !jobs.is_dir()) {
+        invalid_argument("Specified jobs directory does not exist!");
+    }
+    server::run(archive, input_filename, jobs, host, port, workers, limit).or_exit("Unable to serve archive!");
+    Ok(())
+}
+
+// Entrypoint for dict subcommand
+fn dict(matches: &ArgMatches) -> Result<(), String> {
+    match matches.subcommand() {
+        ("train", Some(m)) => {
+            let input_filename = m.value_of("input").unwrap();
+            let output_filename = m.value_of("output").unwrap();
+            let size = units::parse_size(m.value_of("size").unwrap()).unwrap_or_else(|e| invalid_argument(&e)) as usize;
+            let records = units::parse_amount(m.value_of("records").unwrap()).unwrap_or_else(|e| invalid_argument(&e));
+
+            // Dictionaries are trained on whole records, the same units that get compressed
+            let split = split_options(m);
+            let mut input = sdf_records(BufReader::new(std::fs::File::open(input_filename).or_exit("No such file!")), split);
+            let mut samples: Vec<Vec<u8>> = Vec::new();
+            let mut n: u64 = 0; // Records read so far
+            while !matches!(records, units::Amount::Count(count) if n >= count) {
+                let vec_record = match next_record(&mut input, split) {
+                    Some(vec_record) => vec_record,
+                    None => break,
+                };
+                n += 1;
+                match records {
+                    // Keep a record whenever the sampled share grows by a whole record
+                    units::Amount::Fraction(fraction) if (n as f64 * fraction).floor() == ((n - 1) as f64 * fraction).floor() => {},
+                    _ => samples.push(vec_record),
+                }
+            }
+            let dictionary = Dictionary::train(&samples, size).map_err(|e| format!("Unable to train dictionary: {}", e))?;
+            dictionary.save(output_filename).or_exit("Error writing to file!");
+            log::info!("Trained dictionary {} ({} bytes) on {} records", dictionary.id(), dictionary.len(), samples.len());
+            Ok(())
+        },
+        ("export", Some(m)) => {
+            let archive = Archive::open_cached(m.value_of("input").unwrap()).or_exit("Unable to read archive index!");
+            match archive.embedded_dictionary() {
+                Some(dictionary) => {
+                    dictionary.save(m.value_of("output").unwrap()).or_exit("Error writing to file!");
+                    Ok(())
+                },
+                None => Err("Archive has no embedded dictionary!".to_string()),
+            }
+        },
+        ("info", Some(m)) => {
+            let dictionary = Dictionary::load(m.value_of("input").unwrap()).or_exit("No such dictionary!");
+            println!("id: {}", dictionary.id());
+            println!("sha256: {}", dictionary.digest());
+            println!("size: {}", dictionary.len());
+            Ok(())
+        },
+        _ => {
+            eprintln!("Use cmzip dict -h for reference on how to use dictionaries.");
+            process::exit(diagnostic::EXIT_USAGE);
+        },
+    }
+}
+
+// Entrypoint for raw subcommand
+fn raw(matches: &ArgMatches) -> Result<(), String> {
+    match matches.subcommand() {
+        ("get", Some(m)) => {
+            let i = m.value_of("record").unwrap().parse::<usize>().unwrap_or_else(|_| invalid_argument("Invalid record index!"));
+            let archive = Archive::open_cached(m.value_of("input").unwrap()).or_exit("Unable to read archive index!");
+            if i >= archive.len() {
+                invalid_argument(&format!("Record {} does not exist, the archive holds {} records!", i, archive.len()));
+            }
+            let record = archive.read_raw(i).unwrap_or_else(|e| extraction_error(&archive, i, e).exit());
+            match m.value_of("output") {
+                Some(filename) => create_file(filename).write_all(&record).or_exit("Error writing to file!"),
+                None => std::io::stdout().write_all(&record).or_exit("Error writing to stdout!"),
+            }
+            // Reported on stderr, so the values can be passed to raw put for validation
+            log::info!("size: {}", record.len());
+            log::info!("sha256: {}", hex_digest(&record));
+            Ok(())
+        },
+        ("put", Some(m)) => {
+            let mut record: Vec<u8> = Vec::new();
+            match m.value_of("input") {
+                Some(filename) => std::fs::File::open(filename).or_exit("No such file!").read_to_end(&mut record),
+                None => std::io::stdin().read_to_end(&mut record),
+            }.or_exit("Error reading input file!");
+            if let Some(size) = m.value_of("size") {
+                let size = units::parse_size(size).unwrap_or_else(|e| invalid_argument(&e));
+                if record.len() as u64 != size {
+                    Diagnostic::error(&format!("Record is {} bytes, expected {}!", record.len(), size)).code("checksum_mismatch").exit();
+                }
+            }
+            if let Some(digest) = m.value_of("sha256") {
+                if !hex_digest(&record).eq_ignore_ascii_case(digest) {
+                    Diagnostic::error(&format!("Record sha256 is {}, expected {}!", hex_digest(&record), digest)).code("checksum_mismatch").exit();
+                }
+            }
+
+            let output_filename = m.value_of("output").unwrap();
+            let dictionary = m.value_of("dict").map(|filename| Dictionary::load(filename).or_exit("No such dictionary!"));
+            let mut archive = Archive::open(output_filename).or_exit("Unable to read archive index!");
+            if let Some(dictionary) = &dictionary {
+                archive.set_dictionary(dictionary).or_exit("Unable to use dictionary!");
+            }
+            // Only records the archive can decompress are accepted
+            archive.decompress(&record, &mut std::io::sink())
+                .map_err(|e| format!("Record can't be decompressed with the settings of {}: {}", output_filename, e))?;
+
+            let mut writer = Writer::append(output_filename, dictionary.as_ref()).or_exit("Unable to open archive for writing!");
+            writer.add_compressed(&record).or_exit("Error writing to file!");
+            println!("record: {}", writer.len() - 1);
+            history::append(&mut writer);
+            writer.finish().or_exit("Error writing to file!");
+            Ok(())
+        },
+        _ => {
+            eprintln!("Use cmzip raw -h for reference on how to copy compressed records.");
+            process::exit(diagnostic::EXIT_USAGE);
+        },
+    }
+}
+
+// Hex encoded SHA-256 of data
+fn hex_digest(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// xz preset variant and filter chain of --extreme and --xz-opts, applied to every encoder
+#[derive(Clone, Default)]
+struct XzSettings {
+    extreme: bool,
+    options: Option<cmzip::xz::Options>,
+}
+
+fn xz_settings(matches: &ArgMatches, codec: Codec) -> XzSettings {
+    let xz = XzSettings {
+        extreme: matches.is_present("extreme"),
+        options: matches.value_of("xz-opts").map(|options| options.parse().unwrap_or_else(|e: String| invalid_argument(&e))),
+    };
+    if xz.extreme && codec != Codec::Xz {
+        invalid_argument("The extreme preset is only supported by the xz codec!");
+    }
+    if xz.options.is_some() && codec != Codec::Xz {
+        invalid_argument("xz options are only supported by the xz codec!");
+    }
+    xz
+}
+
+impl XzSettings {
+    fn encoder(&self, codec: Codec, level: u32, dictionary: Option<&Dictionary>) -> std::io::Result<Encoder> {
+        let mut encoder = Encoder::new(codec, level, dictionary)?;
+        encoder.set_extreme(self.extreme)?;
+        encoder.set_xz_options(self.options.clone())?;
+        Ok(encoder)
+    }
+
+    fn apply<W: Write>(&self, writer: &mut Writer<W>) -> std::io::Result<()> {
+        writer.set_extreme(self.extreme)?;
+        writer.set_xz_options(self.options.clone())
+    }
+}
+
+// How SD file input is split into records
+#[derive(Clone, Copy)]
+struct Split<'a> {
+    lenient: bool, // Any line containing $$$$ ends a record
+    strict: bool, // A last record lacking $$$$ is an error
+    delimiter: Option<&'a str>, // Instead of $$$$
+    at_start: bool, // The delimiter starts records
+}
+
+fn split_options<'a>(matches: &'a ArgMatches) -> Split<'a> {
+    let split = Split {
+        lenient: matches.is_present("lenient"),
+        strict: matches.is_present("strict"),
+        delimiter: matches.value_of("delimiter"),
+        at_start: matches.value_of("delimiter-at") == Some("start"),
+    };
+    match split.delimiter {
+        Some("") => invalid_argument("Record delimiter must not be empty!"),
+        _ if matches.value_of("format") == Some("mol2") => Split { delimiter: std::str::from_utf8(cmzip::sdf::MOL2_DELIMITER).ok(), at_start: true, ..split },
+        _ => split,
+    }
+}
+
+fn sdf_records<R: BufRead>(input: R, split: Split) -> SdfRecords<R> {
+    let mut records = SdfRecords::new(input);
+    match split.delimiter {
+        Some(delimiter) if split.at_start => records.set_start_delimiter(delimiter.as_bytes()),
+        Some(delimiter) => records.set_delimiter(delimiter.as_bytes()),
+        None => {},
+    }
+    records.set_lenient(split.lenient);
+    records
+}
+
+// Next record of input, this way we compress each record by itself. None once the input is exhausted.
+// Data after the last $$$$ is the last record, with a warning, unless split.strict is set.
+fn next_record<R: BufRead>(records: &mut SdfRecords<R>, split: Split) -> Option<Vec<u8>> {
+    match records.next()? {
+        Ok(record) if !records.unterminated() => Some(record.into_bytes()),
+        Ok(record) if split.strict => Diagnostic::error(&format!("Input ends with a record lacking the {} delimiter!", split.delimiter.unwrap_or("$$$$")))
+            .code("unterminated_record").name(record.title()).head(record.as_bytes())
+            .help(&format!("end the record with a {} line, or leave out --strict to keep it as the last record", split.delimiter.unwrap_or("$$$$"))).exit(),
+        Ok(record) => {
+            log::warn!("Input ends without {}, the last {} bytes are kept as the last record.", split.delimiter.unwrap_or("$$$$"), record.as_bytes().len());
+            Some(record.into_bytes())
+        },
+        Err(e) => {
+            Diagnostic::io("Error reading input file!", &e).emit();
+            None
+        }
+    }
+}
+
+// Record checks of --validate, see sdf::validate()
+struct Validation {
+    action: String, // Of --on-invalid
+    checked: usize,
+    invalid: usize,
+}
+
+impl Validation {
+    fn new(action: &str) -> Validation {
+        Validation { action: action.to_string(), checked: 0, invalid: 0 }
+    }
+
+    // Reports record n of the input at offset if it is malformed, false if it is to be left out
+    fn check(&mut self, n: usize, offset: u64, record: &[u8]) -> bool {
+        self.checked += 1;
+        let problem = match cmzip::sdf::validate(record) {
+            Ok(()) => return true,
+            Err(problem) => problem,
+        };
+        self.invalid += 1;
+        let diagnostic = Diagnostic::error("Malformed record!").code("invalid_record").cause(problem)
+            .record(n).name(archive::title(record)).offset(offset).head(record);
+        match self.action.as_str() {
+            "fail" => diagnostic.help("fix the record, or use --on-invalid keep or skip to go on").exit(),
+            "skip" => {
+                diagnostic.help("the record is left out of the archive").warn();
+                false
+            },
+            _ => {
+                diagnostic.warn();
+                true
+            },
+        }
+    }
+
+    fn summary(&self) {
+        if self.invalid > 0 {
+            let action = if self.action == "skip" { "left out" } else { "compressed anyway" };
+            log::warn!("{} of {} records are malformed and were {}.", self.invalid, self.checked, action);
+        }
+    }
+}
+
+// Prints where record i starts and what it looks like if it is one of every n records to inspect,
+// or with -vv every record
+fn inspect_record(inspect: Option<usize>, i: usize, offset: u64, record: &[u8]) {
+    let inspected = inspect.is_some_and(|n| i.is_multiple_of(n));
+    if inspected || log::log_enabled!(log::Level::Trace) {
+        let title = archive::title(record);
+        let title = if title.is_empty() { "(empty title line)".to_string() } else { title };
+        let level = if inspected { log::Level::Info } else { log::Level::Trace };
+        log::log!(level, "record {} at byte {}: {} ({}, {} lines)", i, offset, title, units::format_size(record.len() as u64), record.split(|&b| b == b'\n').count() - 1);
+    }
+}
+
+// Compresses records on a pool of worker threads and writes them to output in input order.
+// With numa set, workers are spread over NUMA nodes, pinned to the node's CPUs and fed from a per-node queue,
+// and compress into buffers recycled through a per-node pool, so compression buffers stay in node-local memory.
+#[allow(clippy::too_many_arguments)]
+// Returns false if compression stopped early because the archive reached its size limit
+fn compress_parallel(inputs: &mut Inputs, writer: &mut Writer<BufWriter<std::fs::File>>, codec: Codec, level: u32, xz: &XzSettings, dictionary: Option<&Dictionary>, threads: usize, numa: bool, inspect: Option<usize>, normalize_newlines: bool, sync_every: Option<usize>, admit: &mut (dyn FnMut(usize, u64, &[u8]) -> bool + Send), progress: &mut progress::Progress) -> bool {
+    let nodes = if numa { numa::nodes() } else { Vec::new() };
+    if numa && nodes.is_empty() {
+        log::warn!("NUMA topology not available, workers will not be pinned.");
+    }
+    // Every pool needs a worker of its own, with fewer threads than nodes some nodes stay unused
+    let pools = nodes.len().min(threads).max(1);
+    // Buffers of every pool handed back once written, so workers reuse memory first touched on
+    // their node instead of allocating it for every record
+    let buffers: Vec<Mutex<Vec<Vec<u8>>>> = (0..pools).map(|_| Mutex::new(Vec::new())).collect();
+    let (result_tx, result_rx) = mpsc::sync_channel::<(usize, Vec<u8>, u64, usize)>(threads * 2);
+    thread::scope(|s| {
+        let mut queues = Vec::new();
+        for pool in 0..pools {
+            let (job_tx, job_rx) = mpsc::sync_channel::<(usize, Vec<u8>)>(threads * 2);
+            let job_rx = Arc::new(Mutex::new(job_rx));
+            queues.push(job_tx);
+            for _ in (0..threads).filter(|w| w % pools == pool) {
+                let job_rx = Arc::clone(&job_rx);
+                let result_tx = result_tx.clone();
+                let cpus = nodes.get(pool);
+                let buffers = &buffers[pool];
+                s.spawn(move || {
+                    if let Some(cpus) = cpus {
+                        numa::pin_to(cpus);
+                    }
+                    let mut encoder = xz.encoder(codec, level, dictionary).or_exit("Error compressing data!");
+                    loop {
+                        let job = job_rx.lock().unwrap().recv();
+                        let (seq, record) = match job {
+                            Ok(job) => job,
+                            Err(_) => break, // Reader finished
+                        };
+                        // Records compressed here bypass the transforms of the writer
+                        let record = if normalize_newlines { NormalizeNewlines::to_lf(&record) } else { record };
+                        let mut compressed_data = buffers.lock().unwrap().pop().unwrap_or_default();
+                        encoder.compress(&record, &mut compressed_data).or_exit("Error compressing data!");
+                        if result_tx.send((seq, compressed_data, record.len() as u64, pool)).is_err() {
+                            break; // Writer stopped at the size limit
+                        }
+                    }
+                });
+            }
+        }
+        drop(result_tx);
+
+        // Reader thread, hands records to node queues round-robin. Returns false if the workers
+        // stopped taking records before the input ended.
+        let reader = s.spawn(move || {
+            let (mut seq, mut n) = (0, 0);
+            while let Some((offset, vec_record)) = inputs.next_record() {
+                n += 1;
+                if !admit(n - 1, offset, &vec_record) {
+                    continue;
+                }
+                inputs.admitted();
+                inspect_record(inspect, seq, offset, &vec_record);
+                if queues[seq % pools].send((seq, vec_record)).is_err() {
+                    return false;
+                }
+                seq += 1;
+            }
+            true
+        });
+
+        // Workers finish out of order, so hold results back until it is their turn
+        let mut pending: BTreeMap<usize, (Vec<u8>, u64, usize)> = BTreeMap::new();
+        let mut next = 0;
+        let mut complete = true;
+        'results: for (seq, compressed_data, size, pool) in result_rx.iter() {
+            pending.insert(seq, (compressed_data, size, pool));
+            while let Some((mut compressed_data, size, pool)) = pending.remove(&next) {
+                match writer.add_compressed_with_size(&compressed_data, size) {
+                    Ok(_) => {
+                        // Back to its node's pool, which keeps about as many buffers as are in flight
+                        let mut pooled = buffers[pool].lock().unwrap();
+                        if pooled.len() < threads * 2 {
+                            compressed_data.clear();
+                            pooled.push(compressed_data);
+                        }
+                        drop(pooled);
+                        next += 1;
+                        progress.record(size, writer.len(), writer.records_size());
+                        sync_output(writer, sync_every);
+                    },
+                    Err(ref e) if e.kind() == std::io::ErrorKind::FileTooLarge => {
+                        complete = false;
+                        break 'results;
+                    },
+                    Err(e) => Err(e).or_exit("Error writing to file!"),
+                }
+            }
+        }
+        drop(result_rx); // Stops the workers and the reader
+        // Workers only stop early when the writer stopped at the size limit, anything else would
+        // leave a silently truncated archive
+        if !reader.join().unwrap() && complete {
+            Diagnostic::error("Compression workers stopped before the input ended!").exit();
+        }
+        writer.get_mut().flush().or_exit("Error writing to file!");
+        complete
+    })
+}
+
+// Flushes the archive written so far to disk once every sync_every records
+fn sync_output(writer: &mut Writer<BufWriter<std::fs::File>>, sync_every: Option<usize>) {
+    if let Some(n) = sync_every {
+        if writer.len().is_multiple_of(n) {
+            let output = writer.get_mut();
+            output.flush().and_then(|_| output.get_ref().sync_data()).or_exit("Error writing to file!");
+        }
+    }
+}
+
+// Inserts the shard number between file stem and extension, e.g. out.sdf -> out_0001.sdf
+fn shard_filename(filename: &str, shard: usize) -> String {
+    let path = std::path::Path::new(filename);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{:04}.{}", stem, shard, ext.to_string_lossy()),
+        None => format!("{}_{:04}", stem, shard),
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+// Creates filename, refusing to overwrite an existing file unless --force is given
+fn create_file(filename: &str) -> std::fs::File {
+    check_overwrite(filename);
+    overwrite_file(filename)
+}
+
+// Notes filename as an input of the mode, see check_overwrite()
+fn note_input(filename: &str) {
+    if let Ok(path) = std::fs::canonicalize(filename) {
+        INPUTS.lock().unwrap().push(path);
+    }
+}
+
+// Ends the program if filename is an input of the mode, which would be truncated before it is
+// read, or if it exists, unless --force is given
+fn check_overwrite(filename: &str) {
+    if std::fs::canonicalize(filename).is_ok_and(|path| INPUTS.lock().unwrap().contains(&path)) {
+        invalid_argument(&format!("{} is an input file, refusing to overwrite it!", filename));
+    }
+    if !FORCE.load(std::sync::atomic::Ordering::Relaxed) && std::path::Path::new(filename).exists() {
+        invalid_argument(&format!("{} already exists, use --force to overwrite it!", filename));
+    }
+}
+
+// Creates filename and the directories leading to it, truncating an existing file. Relative,
+// drive-rooted and UNC paths work alike on Windows, long ones get the \\?\ prefix.
+fn overwrite_file(filename: &str) -> std::fs::File {
+    let filename = filenames::long_path(filename.to_string());
+    let path = std::path::Path::new(&filename);
+    if let Some(dir) = filenames::parent_dir(path) {
+        std::fs::create_dir_all(dir).or_exit(&format!("Couldn't create {}!", dir.display()));
+    }
+    std::fs::File::create(path).or_exit(&format!("Couldn't create {}!", path.display()))
+}
+
+// Ends the program with a report instead of panicking when an I/O operation fails
+trait OrExit<T> {
+    fn or_exit(self, message: &str) -> T;
+}
+
+impl<T> OrExit<T> for std::io::Result<T> {
+    fn or_exit(self, message: &str) -> T {
+        self.unwrap_or_else(|e| Diagnostic::io(message, &e).exit())
+    }
+}
 
-    return file;
+fn invalid_argument(message: &str) -> ! {
+    Diagnostic::error(message).code("invalid_argument").exit()
 }
\ No newline at end of file
@@ -0,0 +1,90 @@
+// Archive metadata: key/value pairs stored in the file footer between the last record and the
+// index, followed by their encoded size and MAGIC. Readers that only know the index skip them,
+// since record offsets never reach past the last record.
+
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    io
+};
+
+pub const MAGIC: [u8; 8] = *b"CMZ\0META";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl Metadata {
+    pub fn new() -> Metadata {
+        Metadata::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries.get(key).map(|value| &value[..])
+    }
+
+    // Returns the value of key if it is valid UTF-8 text
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    pub fn set<V: Into<Vec<u8>>>(&mut self, key: &str, value: V) {
+        self.entries.insert(key.to_string(), value.into());
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Vec<u8>> {
+        self.entries.remove(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), &value[..]))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Every entry is written as a little endian u64 key length, the key, a little endian u64 value length and the value
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        for (key, value) in &self.entries {
+            data.extend_from_slice(&(key.len() as u64).to_le_bytes());
+            data.extend_from_slice(key.as_bytes());
+            data.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            data.extend_from_slice(value);
+        }
+        data
+    }
+
+    // Size of encode() without encoding
+    pub fn encoded_len(&self) -> usize {
+        self.entries.iter().map(|(key, value)| 16 + key.len() + value.len()).sum()
+    }
+
+    pub fn decode(mut data: &[u8]) -> io::Result<Metadata> {
+        let mut metadata = Metadata::new();
+        while !data.is_empty() {
+            let key = String::from_utf8(take_field(&mut data)?.to_vec())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Metadata key is not valid UTF-8!"))?;
+            let value = take_field(&mut data)?.to_vec();
+            metadata.entries.insert(key, value);
+        }
+        Ok(metadata)
+    }
+}
+
+// Splits a length prefixed field off the front of data
+fn take_field<'a>(data: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "Metadata is corrupt!");
+    if data.len() < 8 {
+        return Err(corrupt());
+    }
+    let len = u64::from_le_bytes(data[..8].try_into().unwrap());
+    if len > (data.len() - 8) as u64 {
+        return Err(corrupt());
+    }
+    let (field, rest) = data[8..].split_at(len as usize);
+    *data = rest;
+    Ok(field)
+}
@@ -0,0 +1,69 @@
+// Selection of records by molecule name (--name). Names are read from the title line or an SD data
+// tag, as set by --name-from, so archives of generators that keep the name in a tag are queried
+// the same way as those that put it in the title line. --name-match sets how strictly names have
+// to agree, since IDs from different vendors differ in case and padding.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr
+};
+use cmzip::{Record, record::NameSource};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameMatch {
+    Exact,
+    CaseInsensitive,
+    Normalized, // Case-insensitive, ignoring surrounding whitespace, with -, _ and inner whitespace as one separator
+}
+
+impl NameMatch {
+    // Form of name that is compared, names with the same key match
+    pub fn key(self, name: &str) -> String {
+        match self {
+            NameMatch::Exact => name.to_string(),
+            NameMatch::CaseInsensitive => name.to_lowercase(),
+            NameMatch::Normalized => name
+                .split(|c: char| c == '-' || c == '_' || c.is_whitespace())
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<&str>>()
+                .join("-")
+                .to_lowercase(),
+        }
+    }
+}
+
+impl FromStr for NameMatch {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<NameMatch, String> {
+        match mode {
+            "exact" => Ok(NameMatch::Exact),
+            "ci" => Ok(NameMatch::CaseInsensitive),
+            "normalized" => Ok(NameMatch::Normalized),
+            _ => Err(format!("Unknown name matching {}, expected exact, ci or normalized!", mode)),
+        }
+    }
+}
+
+pub struct NameFilter {
+    source: NameSource,
+    mode: NameMatch,
+    names: HashMap<String, String>, // Key of every selected name, with the name as given
+}
+
+impl NameFilter {
+    pub fn new<'a, I: IntoIterator<Item = &'a str>>(source: NameSource, mode: NameMatch, names: I) -> NameFilter {
+        let names = names.into_iter().map(|name| (mode.key(name), name.to_string())).collect();
+        NameFilter { source, mode, names }
+    }
+
+    // Key of the record's name if it matches one of the selected names
+    pub fn matching(&self, record: &Record) -> Option<String> {
+        record.name(&self.source).map(|name| self.mode.key(&name)).filter(|key| self.names.contains_key(key))
+    }
+
+    // A selected name, as given, whose key is not among the found ones
+    pub fn missing(&self, found: &HashSet<String>) -> Option<&str> {
+        self.names.iter().find(|(key, _)| !found.contains(*key)).map(|(_, name)| name.as_str())
+    }
+}
@@ -0,0 +1,64 @@
+// NUMA topology discovery and CPU pinning for compression workers.
+// Only Linux exposes the topology we need, other platforms run unpinned.
+
+// Returns the CPUs of every NUMA node, ordered by node number
+#[cfg(target_os = "linux")]
+pub fn nodes() -> Vec<Vec<usize>> {
+    let mut nodes: Vec<(usize, Vec<usize>)> = Vec::new();
+    let entries = match std::fs::read_dir("/sys/devices/system/node") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let node = match name.strip_prefix("node").and_then(|n| n.parse::<usize>().ok()) {
+            Some(node) => node,
+            None => continue,
+        };
+        if let Ok(cpulist) = std::fs::read_to_string(entry.path().join("cpulist")) {
+            let cpus = parse_cpulist(&cpulist);
+            if !cpus.is_empty() {
+                nodes.push((node, cpus));
+            }
+        }
+    }
+    nodes.sort();
+    nodes.into_iter().map(|(_, cpus)| cpus).collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn nodes() -> Vec<Vec<usize>> {
+    Vec::new()
+}
+
+// Restricts the calling thread to the given CPUs
+#[cfg(target_os = "linux")]
+pub fn pin_to(cpus: &[usize]) {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            log::warn!("Unable to pin worker thread to NUMA node!");
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_to(_cpus: &[usize]) {}
+
+// Parses kernel cpulist format, e.g. "0-3,8,10-11"
+#[cfg(target_os = "linux")]
+fn parse_cpulist(cpulist: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    for range in cpulist.trim().split(',').filter(|r| !r.is_empty()) {
+        let mut bounds = range.splitn(2, '-').map(|x| x.parse::<usize>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(first)), Some(Ok(last))) => cpus.extend(first..=last),
+            (Some(Ok(cpu)), None) => cpus.push(cpu),
+            _ => continue,
+        }
+    }
+    cpus
+}
@@ -0,0 +1,63 @@
+// Numbers in SD data tags, e.g. for the numeric --where conditions of unzip-many. Parsing never
+// depends on the locale cmzip runs in. The strict policy only accepts plain decimal numbers with
+// an optional sign, fraction and exponent, like `-9.5`, `+12` or `1.23E+02`. The lenient policy,
+// the default, also reads what European and older vendor software writes: a decimal comma
+// (`1,23E+02`), where a single comma is always the decimal separator, digit grouping with the
+// other separator when both appear (`1.234,5`, `1,234.5`), a Unicode minus sign and Fortran
+// exponents (`1.0D+02`). Infinities and NaN are no numbers under either policy.
+
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    Strict,
+    Lenient,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Policy, String> {
+        match s {
+            "strict" => Ok(Policy::Strict),
+            "lenient" => Ok(Policy::Lenient),
+            _ => Err(format!("Unknown number policy {}, expected strict or lenient!", s)),
+        }
+    }
+}
+
+// Number written in text, None if it isn't one under policy
+pub fn parse(text: &str, policy: Policy) -> Option<f64> {
+    let text = text.trim();
+    if policy == Policy::Strict {
+        return plain(text);
+    }
+    let mut text = text.replace('\u{2212}', "-").replace(['d', 'D'], "e");
+    match (text.rfind('.'), text.rfind(',')) {
+        // The separator that comes last is the decimal one, the other groups digits
+        (Some(dot), Some(comma)) if comma > dot => text = text.replace('.', "").replace(',', "."),
+        (Some(_), Some(_)) => text = text.replace(',', ""),
+        (None, Some(_)) if text.matches(',').count() == 1 => text = text.replace(',', "."),
+        _ => {},
+    }
+    plain(&text)
+}
+
+// Optional sign, digits with an optional fraction and an optional exponent
+fn plain(text: &str) -> Option<f64> {
+    let unsigned = text.strip_prefix(['+', '-']).unwrap_or(text);
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(at) => (&unsigned[..at], Some(&unsigned[at + 1..])),
+        None => (unsigned, None),
+    };
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let valid_mantissa = match mantissa.split_once('.') {
+        Some((whole, fraction)) => (digits(whole) || digits(fraction)) && (whole.is_empty() || digits(whole)) && (fraction.is_empty() || digits(fraction)),
+        None => digits(mantissa),
+    };
+    let valid_exponent = exponent.is_none_or(|exponent| digits(exponent.strip_prefix(['+', '-']).unwrap_or(exponent)));
+    match valid_mantissa && valid_exponent {
+        true => text.parse().ok(),
+        false => None,
+    }
+}
@@ -0,0 +1,67 @@
+// Passphrase of encrypted archives (see cmzip::crypto): the first line of the --keyfile file, the
+// CMZIP_PASSWORD environment variable or a prompt on the terminal, in that order. The prompt is
+// only shown once per run, every archive opened after it gets the same answer.
+
+use std::{
+    fs,
+    sync::{Mutex, OnceLock}
+};
+use cmzip::crypto;
+
+static KEYFILE: OnceLock<String> = OnceLock::new();
+static PROMPTED: OnceLock<Option<String>> = OnceLock::new();
+static PROMPT: Mutex<()> = Mutex::new(()); // Held while prompting, so parallel workers ask once
+
+// Sets up the passphrase source of the library, with the --keyfile value if one was given
+pub fn init(keyfile: Option<&str>) {
+    if let Some(keyfile) = keyfile {
+        let _ = KEYFILE.set(keyfile.to_string());
+    }
+    crypto::set_passphrase_source(|| get().ok());
+}
+
+// Passphrase to open archives with
+pub fn get() -> Result<String, String> {
+    if let Some(passphrase) = configured()? {
+        return Ok(passphrase);
+    }
+    let _prompt = PROMPT.lock().unwrap();
+    PROMPTED.get_or_init(|| rpassword::prompt_password("Passphrase: ").ok()).clone()
+        .ok_or_else(|| format!("No passphrase given, set {} or use --keyfile!", crypto::PASSWORD_VAR))
+}
+
+// Passphrase to encrypt a new archive with, a prompted one has to be typed twice, e.g. for the
+// first archive of zip --dir
+pub fn new() -> Result<String, String> {
+    let passphrase = match configured()? {
+        Some(passphrase) => passphrase,
+        None => {
+            let _prompt = PROMPT.lock().unwrap();
+            match PROMPTED.get().cloned().flatten() {
+                Some(passphrase) => passphrase,
+                None => {
+                    let unavailable = |_| format!("No passphrase given, set {} or use --keyfile!", crypto::PASSWORD_VAR);
+                    let passphrase = rpassword::prompt_password("Passphrase: ").map_err(unavailable)?;
+                    if rpassword::prompt_password("Repeat passphrase: ").map_err(unavailable)? != passphrase {
+                        return Err("Passphrases don't match!".to_string());
+                    }
+                    let _ = PROMPTED.set(Some(passphrase.clone()));
+                    passphrase
+                },
+            }
+        },
+    };
+    if passphrase.is_empty() {
+        return Err("Passphrase is empty!".to_string());
+    }
+    Ok(passphrase)
+}
+
+// Passphrase of --keyfile or the environment, None if neither is given
+fn configured() -> Result<Option<String>, String> {
+    if let Some(keyfile) = KEYFILE.get() {
+        let data = fs::read_to_string(keyfile).map_err(|e| format!("Unable to read {}: {}", keyfile, e))?;
+        return Ok(Some(data.lines().next().unwrap_or("").to_string()));
+    }
+    Ok(std::env::var(crypto::PASSWORD_VAR).ok())
+}
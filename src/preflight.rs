@@ -0,0 +1,43 @@
+// Disk space checks run before long jobs start, so a full filesystem is reported up front instead
+// of hours into compression or extraction. Free space is only known on Linux, elsewhere the check
+// always passes.
+
+use std::path::{Path, PathBuf};
+use cmzip::units;
+
+// Fails if the filesystem that will hold output has less than needed bytes free
+pub fn check(output: &str, needed: u64) -> Result<(), String> {
+    let dir = existing_dir(Path::new(output));
+    match free_space(&dir) {
+        Some(free) if free < needed => Err(format!("Output needs about {}, but only {} are free on {}! Use --no-preflight to start anyway.",
+            units::format_size(needed), units::format_size(free), dir.display())),
+        _ => Ok(()),
+    }
+}
+
+// Closest existing directory the output will be created in
+fn existing_dir(output: &Path) -> PathBuf {
+    let mut dir = output.parent().map(Path::to_path_buf).unwrap_or_default();
+    while !dir.as_os_str().is_empty() && !dir.is_dir() {
+        dir = dir.parent().map(Path::to_path_buf).unwrap_or_default();
+    }
+    if dir.as_os_str().is_empty() { PathBuf::from(".") } else { dir }
+}
+
+#[cfg(target_os = "linux")]
+fn free_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return None;
+        }
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn free_space(_dir: &Path) -> Option<u64> {
+    None
+}
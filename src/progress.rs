@@ -0,0 +1,62 @@
+// Live progress of zip --progress-json for web UIs and workflow managers: newline-delimited JSON
+// events on stderr, written even with --quiet, e.g.
+//
+//   {"event":"progress","phase":"compress","output":"a.cmz","records":5000,"bytes_in":6172840,
+//    "bytes_out":1029021,"input_size":24691360,"elapsed":4.021}
+//
+// The phase is "start" once the archive is opened, "compress" at most every INTERVAL while records
+// are written, "finish" before the footer is written and "done" once the archive is complete.
+// bytes_in counts the decompressed records written so far, bytes_out their compressed size and
+// input_size the size of the input files, null when unknown, e.g. for pipes.
+
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant}
+};
+use crate::json;
+
+const INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct Progress {
+    enabled: bool,
+    output: String,
+    input_size: Option<u64>,
+    start: Instant,
+    last: Instant, // Time of the last event
+    bytes_in: u64,
+}
+
+impl Progress {
+    pub fn new(enabled: bool, output: &str, input_size: Option<u64>) -> Progress {
+        let now = Instant::now();
+        Progress { enabled, output: output.to_string(), input_size, start: now, last: now, bytes_in: 0 }
+    }
+
+    // Notes a record of size bytes written, with the totals of the archive so far, and reports
+    // them once INTERVAL passed since the last event
+    pub fn record(&mut self, size: u64, records: usize, bytes_out: u64) {
+        self.bytes_in += size;
+        if self.enabled && self.last.elapsed() >= INTERVAL {
+            self.event("compress", records, bytes_out);
+        }
+    }
+
+    pub fn event(&mut self, phase: &str, records: usize, bytes_out: u64) {
+        if !self.enabled {
+            return;
+        }
+        self.last = Instant::now();
+        let event = json::object(&[
+            ("event", json::string("progress")),
+            ("phase", json::string(phase)),
+            ("output", json::string(&self.output)),
+            ("records", records.to_string()),
+            ("bytes_in", self.bytes_in.to_string()),
+            ("bytes_out", bytes_out.to_string()),
+            ("input_size", self.input_size.map_or_else(|| "null".to_string(), |size| size.to_string())),
+            ("elapsed", format!("{:.3}", self.start.elapsed().as_secs_f64())),
+        ]);
+        // Progress is best effort, a closed stderr doesn't stop compression
+        let _ = writeln!(io::stderr(), "{}", event);
+    }
+}
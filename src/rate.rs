@@ -0,0 +1,76 @@
+// Bandwidth limit of --limit-rate, e.g. "50MB/s". One limit is shared by every transfer of a
+// command, so the responses of all serve workers together stay below it. Transfers are paced in
+// small chunks without bursts, an idle link doesn't save up bandwidth for later.
+
+use std::{
+    io::{self, Read, Write},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant}
+};
+use cmzip::units;
+
+const CHUNK: usize = 16 << 10;
+
+pub struct RateLimit {
+    bytes_per_second: u64,
+    free: Mutex<Instant>, // When the bytes transferred so far have been paid for
+}
+
+impl RateLimit {
+    // Parses a size per second like "50MB/s" or "2MiB", see units::parse_size
+    pub fn parse(value: &str) -> Result<RateLimit, String> {
+        let size = value.trim();
+        let size = size.strip_suffix("/s").unwrap_or(size);
+        match units::parse_size(size)? {
+            0 => Err(format!("Invalid rate {}, expected a size per second like 50MB/s!", value)),
+            bytes_per_second => Ok(RateLimit { bytes_per_second, free: Mutex::new(Instant::now()) }),
+        }
+    }
+
+    // Waits until count more bytes may be transferred
+    fn wait(&self, count: usize) {
+        let now = Instant::now();
+        let start = {
+            let mut free = self.free.lock().unwrap();
+            let start = (*free).max(now);
+            *free = start + Duration::from_secs_f64(count as f64 / self.bytes_per_second as f64);
+            start
+        };
+        thread::sleep(start - now);
+    }
+}
+
+// Reader or writer whose transfers are paced by a RateLimit
+pub struct Limited<'a, T> {
+    inner: T,
+    limit: &'a RateLimit,
+}
+
+impl<'a, T> Limited<'a, T> {
+    pub fn new(inner: T, limit: &'a RateLimit) -> Limited<'a, T> {
+        Limited { inner, limit }
+    }
+}
+
+impl<T: Read> Read for Limited<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(CHUNK);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.limit.wait(n);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Limited<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(CHUNK);
+        self.limit.wait(len);
+        // Bytes the inner writer didn't take are paid for anyway, so the limit is never exceeded
+        self.inner.write(&buf[..len])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
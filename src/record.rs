@@ -0,0 +1,167 @@
+// Decompressed MDL SD records. The data tags (the "> <NAME>" blocks after the molecule) are only
+// parsed when first asked for, so walking an archive for titles or raw bytes stays cheap.
+
+use std::{
+    sync::OnceLock,
+    collections::BTreeMap,
+    fmt,
+    str::FromStr
+};
+use crate::{archive, sdf};
+
+#[derive(Clone, Debug)]
+pub struct Record {
+    data: Vec<u8>,
+    tags: OnceLock<BTreeMap<String, String>>,
+}
+
+impl Record {
+    pub fn new(data: Vec<u8>) -> Record {
+        Record { data, tags: OnceLock::new() }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    // Title line, which holds the molecule name
+    pub fn title(&self) -> String {
+        archive::title(&self.data)
+    }
+
+    // V2000 or V3000, see sdf::molfile_version()
+    pub fn molfile_version(&self) -> Option<&'static str> {
+        sdf::molfile_version(&self.data)
+    }
+
+    // All SD data tags of the record, parsed on first use
+    pub fn tags(&self) -> &BTreeMap<String, String> {
+        self.tags.get_or_init(|| parse_tags(&self.data))
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags().get(name).map(|value| value.as_str())
+    }
+
+    // Molecule name read from source, None if the title line is empty or the tag is missing
+    pub fn name(&self, source: &NameSource) -> Option<String> {
+        match source {
+            NameSource::Title => Some(self.title()).filter(|title| !title.trim().is_empty()),
+            NameSource::Tag(tag) => self.tag(tag).map(str::to_string),
+        }
+    }
+
+    // Copy of the record without the data items of the given tags, everything else is kept byte
+    // for byte
+    pub fn without_tags<S: AsRef<str>>(&self, names: &[S]) -> Record {
+        let mut data: Vec<u8> = Vec::with_capacity(self.data.len());
+        let mut dropping = false; // Inside the value of a dropped data item
+        for line in self.data.split_inclusive(|&b| b == b'\n') {
+            if dropping {
+                if line.starts_with(b"$$$$") {
+                    data.extend_from_slice(line);
+                }
+                dropping = !(line.trim_ascii().is_empty() || line.starts_with(b"$$$$"));
+                continue;
+            }
+            if line.starts_with(b">") {
+                let header = String::from_utf8_lossy(line);
+                if tag_name(&header).is_some_and(|name| names.iter().any(|n| n.as_ref() == name)) {
+                    dropping = true;
+                    continue;
+                }
+            }
+            data.extend_from_slice(line);
+        }
+        Record::new(data)
+    }
+
+    // Copy of the record with a data item added after the existing ones, in front of the $$$$ line
+    pub fn with_tag(&self, name: &str, value: &str) -> Record {
+        let end = self.data.windows(4).rposition(|window| window == b"$$$$")
+            .filter(|&end| end == 0 || self.data[end - 1] == b'\n')
+            .unwrap_or(self.data.len());
+        let mut data: Vec<u8> = Vec::with_capacity(self.data.len() + name.len() + value.len() + 8);
+        data.extend_from_slice(&self.data[..end]);
+        if !data.is_empty() && !data.ends_with(b"\n") {
+            data.push(b'\n');
+        }
+        // A value directly followed by $$$$ needs the blank line ending it
+        let last_line = data[..data.len().saturating_sub(1)].rsplit(|&b| b == b'\n').next().unwrap_or(b"");
+        if !last_line.trim_ascii().is_empty() && !last_line.starts_with(b"M  END") {
+            data.push(b'\n');
+        }
+        data.extend_from_slice(format!("> <{}>\n{}\n\n", name, value).as_bytes());
+        data.extend_from_slice(&self.data[end..]);
+        Record::new(data)
+    }
+}
+
+// Where records keep the molecule name: the title line, or an SD data tag for generators that
+// leave the title empty or fill it with something else
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NameSource {
+    Title,
+    Tag(String),
+}
+
+impl FromStr for NameSource {
+    type Err = String;
+
+    // Parses "title" or "tag:NAME"
+    fn from_str(source: &str) -> Result<NameSource, String> {
+        match source.split_once(':') {
+            None if source == "title" => Ok(NameSource::Title),
+            Some(("tag", tag)) if !tag.is_empty() => Ok(NameSource::Tag(tag.to_string())),
+            _ => Err(format!("Unknown name source {}, expected title or tag:NAME!", source)),
+        }
+    }
+}
+
+// Written as parsed, the form archives store it in (see Archive::name_source())
+impl fmt::Display for NameSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameSource::Title => write!(f, "title"),
+            NameSource::Tag(tag) => write!(f, "tag:{}", tag),
+        }
+    }
+}
+
+// Name of the data item started by a header line like "> <SCORE> (12)"
+pub(crate) fn tag_name(line: &str) -> Option<&str> {
+    match (line.find('<'), line.rfind('>')) {
+        (Some(start), Some(end)) if end > start => Some(&line[start + 1..end]),
+        _ => None,
+    }
+}
+
+// Data items start with a header line like "> <SCORE>" or ">  <SCORE> (12)", their value runs
+// until the next blank line. Multi-line values keep their line breaks.
+fn parse_tags(data: &[u8]) -> BTreeMap<String, String> {
+    let text = String::from_utf8_lossy(data);
+    let mut tags = BTreeMap::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        if !line.starts_with('>') {
+            continue;
+        }
+        let name = match tag_name(line) {
+            Some(name) => name,
+            None => continue,
+        };
+        let mut value: Vec<&str> = Vec::new();
+        for line in lines.by_ref() {
+            if line.trim().is_empty() || line.starts_with("$$$$") {
+                break;
+            }
+            value.push(line.trim_end());
+        }
+        tags.insert(name.to_string(), value.join("\n"));
+    }
+    tags
+}
@@ -0,0 +1,127 @@
+// Reading parts of archives stored on HTTP(S) servers and object stores with range requests, so
+// only the bytes that are needed get downloaded. Transient failures (connection errors, timeouts,
+// 408, 429 and 5xx responses) are retried with exponential backoff, a response cut short is resumed
+// from the last byte received.
+
+use std::{
+    convert::TryInto,
+    io::{self, Read},
+    thread,
+    time::Duration
+};
+use cmzip::archive;
+use crate::rate::{Limited, RateLimit};
+
+const ATTEMPTS: usize = 5;
+const FIRST_DELAY: Duration = Duration::from_millis(500); // Doubled after every failed attempt
+const MAX_DELAY: Duration = Duration::from_secs(8);
+
+// Downloads the given byte range, e.g. "bytes=-8" for the last 8 bytes. Returns the data and the
+// total size of the remote file. Fails with the errors of every attempt once all of them failed.
+pub fn fetch_range(url: &str, range: &str, limit: Option<&RateLimit>) -> io::Result<(Vec<u8>, u64)> {
+    let mut data: Vec<u8> = Vec::new();
+    let mut span: Option<(u64, u64, u64)> = None; // First and last byte of the range and the file size, once known
+    let mut failures: Vec<String> = Vec::new();
+    let mut delay = FIRST_DELAY;
+    loop {
+        // Bytes received by earlier attempts aren't requested again
+        let request = match span {
+            Some((first, last, _)) => format!("bytes={}-{}", first + data.len() as u64, last),
+            None => range.to_string(),
+        };
+        match attempt(url, &request, limit, &mut data, &mut span) {
+            Ok(total) => return Ok((data, total)),
+            Err(Failure::Permanent(e)) => return Err(e),
+            Err(Failure::Transient(e)) => failures.push(e),
+        }
+        if failures.len() == ATTEMPTS {
+            return Err(io::Error::other(format!("Request to {} failed {} times: {}", url, ATTEMPTS, failures.join("; "))));
+        }
+        thread::sleep(delay);
+        delay = (delay * 2).min(MAX_DELAY);
+    }
+}
+
+enum Failure {
+    Transient(String), // Retried
+    Permanent(io::Error),
+}
+
+// Requests range, appending the bytes received to data even if the response is cut short
+fn attempt(url: &str, range: &str, limit: Option<&RateLimit>, data: &mut Vec<u8>, span: &mut Option<(u64, u64, u64)>) -> Result<u64, Failure> {
+    let response = match ureq::get(url).set("Range", range).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, _)) if status == 408 || status == 429 || status >= 500 => {
+            return Err(Failure::Transient(format!("status {}", status)));
+        },
+        Err(ureq::Error::Transport(e)) => return Err(Failure::Transient(e.to_string())),
+        Err(e) => return Err(Failure::Permanent(io::Error::other(format!("Request to {} failed: {}", url, e)))),
+    };
+    if response.status() != 206 {
+        return Err(Failure::Permanent(io::Error::new(io::ErrorKind::Unsupported, format!("{} does not support range requests!", url))));
+    }
+    // Content-Range: bytes 100-107/108
+    let (first, last, total) = response.header("Content-Range")
+        .and_then(content_range)
+        .ok_or_else(|| Failure::Permanent(io::Error::new(io::ErrorKind::InvalidData, format!("{} sent no valid Content-Range header!", url))))?;
+    match *span {
+        Some((expected_first, expected_last, expected_total)) => {
+            if (first, last, total) != (expected_first + data.len() as u64, expected_last, expected_total) {
+                return Err(Failure::Permanent(io::Error::new(io::ErrorKind::InvalidData, format!("{} changed while it was downloaded!", url))));
+            }
+        },
+        None => *span = Some((first, last, total)),
+    }
+    let (first, last, _) = span.unwrap();
+    let read = match limit {
+        Some(limit) => Limited::new(response.into_reader(), limit).read_to_end(data),
+        None => response.into_reader().read_to_end(data),
+    };
+    if let Err(e) = read {
+        return Err(Failure::Transient(e.to_string()));
+    }
+    if (data.len() as u64) < last + 1 - first {
+        return Err(Failure::Transient(format!("response ended after {} of {} bytes", data.len(), last + 1 - first)));
+    }
+    Ok(total)
+}
+
+// First and last byte and total size of "bytes 100-107/108"
+fn content_range(value: &str) -> Option<(u64, u64, u64)> {
+    let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (first, last) = range.split_once('-')?;
+    let (first, last, total) = (first.trim().parse().ok()?, last.trim().parse().ok()?, total.trim().parse().ok()?);
+    (first <= last && last < total).then_some((first, last, total))
+}
+
+// Downloads the file footer of a remote archive: everything after the last record, which holds
+// the metadata and the index and can be opened as an archive without records.
+pub fn fetch_footer(url: &str, limit: Option<&RateLimit>) -> io::Result<Vec<u8>> {
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "Remote archive footer is corrupt!");
+
+    // The index size is stored in the last 8 bytes, the index directly precedes it
+    let (data, file_size) = fetch_range(url, "bytes=-8", limit)?;
+    let index_size = u64::from_le_bytes(data[..].try_into().map_err(|_| corrupt())?);
+    // Checked before the index is requested, a damaged size would ask for the whole file
+    let footer_size = index_size.checked_add(8).filter(|&size| size <= file_size).ok_or_else(corrupt)?;
+    let (data, total) = fetch_range(url, &format!("bytes=-{}", footer_size), limit)?;
+    if data.len() as u64 != footer_size {
+        return Err(corrupt());
+    }
+    // The metadata is fetched after the index, every record of the remote file takes at least a byte
+    let index = archive::decode_index(&data[..index_size as usize], file_size.saturating_add(1))?;
+
+    // Records end where the footer starts, fetch the remaining footer in front of the index
+    let records_end = index.iter().try_fold(0u64, |end, &size| end.checked_add(size)).ok_or_else(corrupt)?;
+    let index_start = total.checked_sub(footer_size).ok_or_else(corrupt)?;
+    if records_end > index_start {
+        return Err(corrupt());
+    }
+    let mut footer = if records_end < index_start {
+        fetch_range(url, &format!("bytes={}-{}", records_end, index_start - 1), limit)?.0
+    } else {
+        Vec::new()
+    };
+    footer.extend_from_slice(&data);
+    Ok(footer)
+}
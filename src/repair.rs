@@ -0,0 +1,108 @@
+// Record recovery for archives whose footer is lost. Every xz record is a complete xz stream, so
+// records are found by searching for the xz stream magic and decoding a stream from it, which
+// also gives its exact compressed size. Candidates that fail to decode are magic bytes inside
+// damaged or other data and are skipped, streams that decode to binary data (the footer index and
+// its backup copy) are not records.
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom}
+};
+use xz2::stream::{Action, Status, Stream};
+
+pub const XZ_MAGIC: [u8; 6] = [0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00];
+const CHUNK: usize = 8 * 1024;
+
+pub struct Found {
+    pub compressed: Vec<u8>,
+    pub size: u64, // Decompressed size
+}
+
+// Finds the records of the file in file order, calls found for each one and skipped for every
+// damaged range in front of a record
+pub fn scan<F, S>(input: &mut File, mut found: F, mut skipped: S) -> io::Result<()>
+where
+    F: FnMut(Found) -> io::Result<()>,
+    S: FnMut(u64, u64),
+{
+    let mut position = 0;
+    let mut records_end = 0;
+    while let Some(offset) = find_magic(input, position)? {
+        match decode_at(input, offset)? {
+            Some((length, decompressed)) => {
+                position = offset + length;
+                if decompressed.contains(&0) {
+                    continue;
+                }
+                if offset > records_end {
+                    skipped(records_end, offset - records_end);
+                }
+                let mut compressed: Vec<u8> = vec![0u8; length as usize];
+                input.seek(SeekFrom::Start(offset))?;
+                input.read_exact(&mut compressed)?;
+                found(Found { compressed, size: decompressed.len() as u64 })?;
+                records_end = position;
+            },
+            None => position = offset + 1,
+        }
+    }
+    Ok(())
+}
+
+// Offset of the next xz stream magic at or after position
+fn find_magic(input: &mut File, mut position: u64) -> io::Result<Option<u64>> {
+    let mut buf: Vec<u8> = vec![0u8; CHUNK];
+    loop {
+        input.seek(SeekFrom::Start(position))?;
+        let read = read_full(input, &mut buf)?;
+        if read < XZ_MAGIC.len() {
+            return Ok(None);
+        }
+        if let Some(at) = buf[..read].windows(XZ_MAGIC.len()).position(|window| window == XZ_MAGIC) {
+            return Ok(Some(position + at as u64));
+        }
+        // The next chunk overlaps this one, so a magic crossing the border is not missed
+        position += (read - XZ_MAGIC.len() + 1) as u64;
+    }
+}
+
+// Compressed length and contents of the xz stream starting at offset, None if it doesn't decode
+fn decode_at(input: &mut File, offset: u64) -> io::Result<Option<(u64, Vec<u8>)>> {
+    let mut stream = match Stream::new_stream_decoder(u64::MAX, 0) {
+        Ok(stream) => stream,
+        Err(_) => return Ok(None),
+    };
+    let mut buf: Vec<u8> = vec![0u8; CHUNK];
+    let mut output: Vec<u8> = Vec::new();
+    input.seek(SeekFrom::Start(offset))?;
+    loop {
+        let read = read_full(input, &mut buf)?;
+        if read == 0 {
+            return Ok(None); // Cut off by the end of the file
+        }
+        let mut data = &buf[..read];
+        while !data.is_empty() {
+            output.reserve(CHUNK);
+            let consumed = stream.total_in();
+            match stream.process_vec(data, &mut output, Action::Run) {
+                Ok(Status::StreamEnd) => return Ok(Some((stream.total_in(), output))),
+                Ok(_) => data = &data[(stream.total_in() - consumed) as usize..],
+                Err(_) => return Ok(None),
+            }
+        }
+    }
+}
+
+// Fills as much of buf as the file holds
+fn read_full(input: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
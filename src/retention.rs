@@ -0,0 +1,86 @@
+// Retention of records, stored in the metadata as the time records expire, so archives can be
+// pruned by a data retention policy. Written as one line per run of records expiring at the same
+// time, "first-last expiry" with inclusive record indices and the expiry in seconds since the Unix
+// epoch, e.g. "0-4999 1791331200". Records outside every run never expire.
+
+use std::ops::Range;
+
+pub const KEY: &str = "expires";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Retention {
+    runs: Vec<(Range<usize>, u64)>, // Ascending and not overlapping
+}
+
+impl Retention {
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    // Sets the expiry of records, which have to follow the records set before
+    pub fn set(&mut self, records: Range<usize>, expiry: u64) {
+        assert!(self.runs.last().is_none_or(|(last, _)| last.end <= records.start), "Retention runs have to be set in record order!");
+        if records.is_empty() {
+            return;
+        }
+        match self.runs.last_mut() {
+            Some((last, last_expiry)) if last.end == records.start && *last_expiry == expiry => last.end = records.end,
+            _ => self.runs.push((records, expiry)),
+        }
+    }
+
+    // Seconds since the Unix epoch when record i expires, None if it never does
+    pub fn expiry(&self, i: usize) -> Option<u64> {
+        let run = self.runs.partition_point(|(records, _)| records.end <= i);
+        self.runs.get(run).filter(|(records, _)| records.contains(&i)).map(|&(_, expiry)| expiry)
+    }
+
+    // Number of records that expired at now
+    pub fn expired(&self, now: u64) -> usize {
+        self.runs.iter().filter(|&&(_, expiry)| expiry <= now).map(|(records, _)| records.len()).sum()
+    }
+
+    // Number of records that expire at all
+    pub fn expiring(&self) -> usize {
+        self.runs.iter().map(|(records, _)| records.len()).sum()
+    }
+
+    // Adds the retention of an archive whose records follow the records set before, starting at
+    // record first
+    pub fn extend(&mut self, other: &Retention, first: usize) {
+        for (records, expiry) in &other.runs {
+            self.set(first + records.start..first + records.end, *expiry);
+        }
+    }
+
+    // Retention of an archive holding the given records of this one in the given order, for
+    // commands that drop or reorder records
+    pub fn remap<I: IntoIterator<Item = usize>>(&self, kept: I) -> Retention {
+        let mut retention = Retention::default();
+        for (j, i) in kept.into_iter().enumerate() {
+            if let Some(expiry) = self.expiry(i) {
+                retention.set(j..j + 1, expiry);
+            }
+        }
+        retention
+    }
+
+    pub fn encode(&self) -> String {
+        self.runs.iter().map(|(records, expiry)| format!("{}-{} {}", records.start, records.end - 1, expiry)).collect::<Vec<String>>().join("\n")
+    }
+
+    // None if a run is malformed or out of order
+    pub fn decode(text: &str) -> Option<Retention> {
+        let mut retention = Retention::default();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let (range, expiry) = line.trim().split_once(' ')?;
+            let (first, last) = range.split_once('-')?;
+            let (first, last, expiry) = (first.parse::<usize>().ok()?, last.parse::<usize>().ok()?, expiry.trim().parse::<u64>().ok()?);
+            if first > last || retention.runs.last().is_some_and(|(records, _)| records.end > first) {
+                return None;
+            }
+            retention.set(first..last + 1, expiry);
+        }
+        Some(retention)
+    }
+}
@@ -0,0 +1,108 @@
+// Random sampling of records for unzip --sample and cat --sample, e.g. QC subsets of a large
+// campaign. With --stratify-by the records are grouped by the value of an SD data tag and every
+// group (stratum) gets a share of the sample, so the biggest target of a multi-target campaign
+// doesn't make up the whole subset. Every record gets a pseudo-random key from the seed and its
+// index and the records with the lowest keys of a stratum are picked, so the same seed picks the
+// same records again.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    str::FromStr
+};
+
+// How the sample is shared between strata
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Allocation {
+    Proportional, // By the number of records of every stratum
+    Equal, // The same number from every stratum, leftovers of small ones go to the others
+}
+
+impl FromStr for Allocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Allocation, String> {
+        match s {
+            "proportional" => Ok(Allocation::Proportional),
+            "equal" => Ok(Allocation::Equal),
+            _ => Err(format!("Unknown allocation {}, expected proportional or equal!", s)),
+        }
+    }
+}
+
+pub struct Stratum {
+    pub value: Option<String>, // None for the records lacking the tag
+    pub records: usize,
+    pub sampled: usize,
+}
+
+// Picks count of the records, given with their stratum, or all of them if there are fewer.
+// Returns the picked record indices and the strata in order of their values.
+pub fn sample(records: &[(usize, Option<String>)], count: usize, allocation: Allocation, seed: u64) -> (HashSet<usize>, Vec<Stratum>) {
+    let mut groups: BTreeMap<Option<&str>, Vec<usize>> = BTreeMap::new();
+    for (i, value) in records {
+        groups.entry(value.as_deref()).or_default().push(*i);
+    }
+    let sizes: Vec<usize> = groups.values().map(Vec::len).collect();
+    let quotas = match allocation {
+        Allocation::Proportional => proportional(&sizes, count),
+        Allocation::Equal => equal(&sizes, count),
+    };
+
+    let mut picked: HashSet<usize> = HashSet::new();
+    let mut strata: Vec<Stratum> = Vec::new();
+    for ((value, mut members), quota) in groups.into_iter().zip(quotas) {
+        let records = members.len();
+        if quota < records {
+            members.select_nth_unstable_by_key(quota, |&i| key(seed, i));
+            members.truncate(quota);
+        }
+        picked.extend(members);
+        strata.push(Stratum { value: value.map(str::to_string), records, sampled: quota });
+    }
+    (picked, strata)
+}
+
+// Shares of count in proportion to sizes, rounded by largest remainder so they add up to count
+fn proportional(sizes: &[usize], count: usize) -> Vec<usize> {
+    let total: usize = sizes.iter().sum();
+    if count >= total {
+        return sizes.to_vec();
+    }
+    let mut quotas: Vec<usize> = sizes.iter().map(|&size| (size as u128 * count as u128 / total as u128) as usize).collect();
+    let mut remainders: Vec<(u128, usize)> = sizes.iter().enumerate()
+        .map(|(s, &size)| (size as u128 * count as u128 % total as u128, s))
+        .collect();
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    let missing = count - quotas.iter().sum::<usize>();
+    for &(_, s) in remainders.iter().take(missing) {
+        quotas[s] += 1;
+    }
+    quotas
+}
+
+// Equal shares of count, strata with fewer records give all of them and the rest is shared
+// between the others
+fn equal(sizes: &[usize], count: usize) -> Vec<usize> {
+    let mut quotas = vec![0; sizes.len()];
+    let mut left = count;
+    loop {
+        let open: Vec<usize> = (0..sizes.len()).filter(|&s| quotas[s] < sizes[s]).collect();
+        if left == 0 || open.is_empty() {
+            return quotas;
+        }
+        let (share, extra) = (left / open.len(), left % open.len());
+        for (n, &s) in open.iter().enumerate() {
+            let add = (share + usize::from(n < extra)).min(sizes[s] - quotas[s]);
+            quotas[s] += add;
+            left -= add;
+        }
+    }
+}
+
+// Pseudo-random key of record i, splitmix64 of the seed and the index
+fn key(seed: u64, i: usize) -> u64 {
+    let mut z = seed.wrapping_add((i as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
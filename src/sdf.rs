@@ -0,0 +1,326 @@
+// Splitting of MDL SD files into records, the units CmZ archives compress one by one. cmzip zip
+// reads its input with SdfRecords, so other tools using it find exactly the same record
+// boundaries. Input is read a line at a time and a record is never held beyond the record size
+// limit, so input that isn't an SD file (e.g. without any newline) can't exhaust memory. Other
+// record-oriented text files are split the same way with another delimiter, e.g. ENDMDL ending
+// the models of a PDB file, or > starting the sequences of a FASTA file.
+
+use std::{
+    collections::HashSet,
+    io::{self, BufRead}
+};
+use crate::{Record, record::tag_name};
+
+pub const DELIMITER: &[u8] = b"$$$$";
+pub const MOL2_DELIMITER: &[u8] = b"@<TRIPOS>MOLECULE"; // Starts the records of mol2 files
+
+// Iterator over the records of an SD file. A record ends with a line that is exactly the
+// delimiter, apart from trailing whitespace and CR, as in the SDF spec. Data after the last
+// delimiter is returned as the last record unless it is only whitespace, see unterminated().
+pub struct SdfRecords<R: BufRead> {
+    input: R,
+    delimiter: Vec<u8>,
+    starts: bool, // The delimiter starts records instead of ending them
+    pending: Vec<u8>, // Delimiter line read ahead, starting the next record
+    lenient: bool,
+    max_record_size: Option<usize>,
+    unterminated: bool,
+    done: bool,
+}
+
+impl<R: BufRead> SdfRecords<R> {
+    pub fn new(input: R) -> SdfRecords<R> {
+        SdfRecords { input, delimiter: DELIMITER.to_vec(), starts: false, pending: Vec::new(), lenient: false, max_record_size: None, unterminated: false, done: false }
+    }
+
+    // Sets the line ending records instead of $$$$, panics if delimiter is empty
+    pub fn set_delimiter(&mut self, delimiter: &[u8]) {
+        assert!(!delimiter.is_empty(), "Record delimiter must not be empty!");
+        self.delimiter = delimiter.to_vec();
+        self.starts = false;
+    }
+
+    // Sets a prefix of the lines starting records instead, e.g. @<TRIPOS>MOLECULE for mol2 or > for
+    // FASTA files. Lines in front of the first delimiter belong to the first record, and the last
+    // record ends with the input. Panics if delimiter is empty.
+    pub fn set_start_delimiter(&mut self, delimiter: &[u8]) {
+        assert!(!delimiter.is_empty(), "Record delimiter must not be empty!");
+        self.delimiter = delimiter.to_vec();
+        self.starts = true;
+    }
+
+    // With lenient set any line containing the delimiter ends a record (or starts one), as cmzip
+    // did before following the SDF spec
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    // Records growing past size bytes fail with InvalidData and end the iteration
+    pub fn set_max_record_size(&mut self, size: usize) {
+        self.max_record_size = Some(size);
+    }
+
+    // Whether the record returned last lacks the delimiter, which only the last record of the
+    // input can
+    pub fn unterminated(&self) -> bool {
+        self.unterminated
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+
+    // Reads lines into record until the delimiter is reached, false if the input ends first. A
+    // delimiter starting the next record is kept in pending.
+    fn read_delimited(&mut self, record: &mut Vec<u8>) -> io::Result<bool> {
+        let mut started = !record.is_empty(); // Record holds its starting delimiter
+        loop {
+            let start = record.len();
+            if !self.read_line(record)? {
+                return Ok(false);
+            }
+            if !self.is_delimiter(&record[start..]) {
+                continue;
+            }
+            if !self.starts {
+                return Ok(true);
+            }
+            if started {
+                self.pending = record.split_off(start);
+                return Ok(true);
+            }
+            started = true;
+        }
+    }
+
+    fn is_delimiter(&self, line: &[u8]) -> bool {
+        if self.lenient {
+            line.windows(self.delimiter.len()).any(|window| window == self.delimiter.as_slice())
+        } else if self.starts {
+            line.starts_with(&self.delimiter)
+        } else {
+            line.trim_ascii_end() == self.delimiter.as_slice()
+        }
+    }
+
+    // Appends the next line of input to record, false at the end of the input
+    fn read_line(&mut self, record: &mut Vec<u8>) -> io::Result<bool> {
+        let mut read = false;
+        loop {
+            let (complete, used) = {
+                let available = match self.input.fill_buf() {
+                    Ok(available) => available,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                if available.is_empty() {
+                    return Ok(read);
+                }
+                match available.iter().position(|&b| b == b'\n') {
+                    Some(end) => {
+                        record.extend_from_slice(&available[..=end]);
+                        (true, end + 1)
+                    },
+                    None => {
+                        record.extend_from_slice(available);
+                        (false, available.len())
+                    },
+                }
+            };
+            self.input.consume(used);
+            read = true;
+            if let Some(size) = self.max_record_size {
+                if record.len() > size {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Record is larger than the record size limit of {} bytes!", size)));
+                }
+            }
+            if complete {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for SdfRecords<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        if self.done {
+            return None;
+        }
+        let mut record: Vec<u8> = std::mem::take(&mut self.pending);
+        match self.read_delimited(&mut record) {
+            Ok(true) => Some(Ok(Record::new(record))),
+            Ok(false) => {
+                self.done = true;
+                if record.trim_ascii().is_empty() {
+                    return None;
+                }
+                self.unterminated = !self.starts;
+                Some(Ok(Record::new(record)))
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+// Molfile version given by the counts line of record, V2000 for molfiles that don't state it as
+// the spec allows, None if the record has no counts line
+pub fn molfile_version(record: &[u8]) -> Option<&'static str> {
+    let counts = record.split(|&b| b == b'\n').nth(3)?;
+    match counts.trim_ascii_end().ends_with(b"V3000") {
+        true => Some("V3000"),
+        false => Some("V2000"),
+    }
+}
+
+// Checks that record is a well-formed molfile followed by SD data items: the three header lines,
+// a counts line, the connection table, M  END, and data items of a "> <NAME>" header line and a
+// value ended by a blank line. The connection table of V2000 molfiles has as many atom and bond
+// lines as the counts line gives, that of V3000 molfiles as many as its COUNTS line gives. The
+// error describes the first problem with its line, counted from 1 within the record.
+pub fn validate(record: &[u8]) -> Result<(), String> {
+    let text = String::from_utf8_lossy(record);
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() < 4 {
+        return Err("record ends before its counts line".to_string());
+    }
+    let mut n = match molfile_version(record) {
+        Some("V3000") => validate_v3000(&lines)?,
+        _ => validate_v2000(&lines)?,
+    };
+
+    // Data items, each value runs until a blank line
+    n += 1;
+    while let Some(line) = lines.get(n) {
+        if line.trim_end() == "$$$$" {
+            break;
+        }
+        if line.trim().is_empty() {
+            n += 1;
+            continue;
+        }
+        if !line.starts_with('>') || tag_name(line).is_none() {
+            return Err(format!("line {}: expected a data header like > <NAME>: {}", n + 1, line.trim_end()));
+        }
+        let header = n;
+        n += 1;
+        loop {
+            match lines.get(n) {
+                Some(line) if line.trim().is_empty() => break,
+                Some(line) if line.trim_end() != "$$$$" => n += 1,
+                _ => return Err(format!("line {}: data item isn't ended by a blank line", header + 1)),
+            }
+        }
+    }
+    Ok(())
+}
+
+// Checks the counts line and connection table of a V2000 molfile, returns the index of its M  END line
+fn validate_v2000(lines: &[&str]) -> Result<usize, String> {
+    let number = |line: &str, start: usize, end: usize| line.get(start..end).and_then(|field| field.trim().parse::<usize>().ok());
+    let counts = lines[3];
+    let (atoms, bonds) = match (number(counts, 0, 3), number(counts, 3, 6)) {
+        (Some(atoms), Some(bonds)) => (atoms, bonds),
+        _ => return Err(format!("line 4: counts line doesn't give the atom and bond counts: {}", counts.trim_end())),
+    };
+
+    // Atom lines hold the coordinates and element, bond lines the two atoms and bond type
+    let coordinate = |line: &str, start: usize| line.get(start..start + 10).map(|field| field.trim().parse::<f64>().is_ok()) == Some(true);
+    for n in 4..4 + atoms {
+        let line = lines.get(n).ok_or_else(|| format!("record ends after {} of its {} atom lines", n - 4, atoms))?;
+        if !(coordinate(line, 0) && coordinate(line, 10) && coordinate(line, 20)) || line.get(31..).is_none_or(|symbol| symbol.trim().is_empty()) {
+            return Err(format!("line {}: malformed atom line: {}", n + 1, line.trim_end()));
+        }
+    }
+    let atom = |line: &str, start: usize| number(line, start, start + 3).filter(|&i| i >= 1 && i <= atoms).is_some();
+    for n in 4 + atoms..4 + atoms + bonds {
+        let line = lines.get(n).ok_or_else(|| format!("record ends after {} of its {} bond lines", n - 4 - atoms, bonds))?;
+        if !(atom(line, 0) && atom(line, 3) && number(line, 6, 9).is_some()) {
+            return Err(format!("line {}: malformed bond line: {}", n + 1, line.trim_end()));
+        }
+    }
+
+    // Property lines up to M  END
+    let mut n = 4 + atoms + bonds;
+    loop {
+        match lines.get(n) {
+            Some(line) if line.trim_end() == "M  END" => return Ok(n),
+            Some(line) if line.starts_with('>') || line.trim_end() == "$$$$" => return Err(format!("line {}: molfile lacks its M  END line", n + 1)),
+            Some(_) => n += 1,
+            None => return Err("molfile lacks its M  END line".to_string()),
+        }
+    }
+}
+
+// Checks the connection table of a V3000 molfile, returns the index of its M  END line. The table
+// is made of "M  V30" lines, a line ending with - continues on the next one. It runs from BEGIN CTAB
+// to END CTAB, its COUNTS line gives the number of lines in the ATOM and BOND blocks, and bonds
+// have to connect atoms of the ATOM block. Other blocks, e.g. SGROUP, are not checked.
+fn validate_v3000(lines: &[&str]) -> Result<usize, String> {
+    // Joins continued lines, keeping the number of the first one
+    let mut statements: Vec<(usize, String)> = Vec::new();
+    let mut n = 4;
+    let end = loop {
+        let line = match lines.get(n) {
+            Some(line) => line.trim_end(),
+            None => return Err("molfile lacks its M  END line".to_string()),
+        };
+        if line == "M  END" {
+            break n;
+        }
+        let content = line.strip_prefix("M  V30 ").ok_or_else(|| format!("line {}: expected an M  V30 line: {}", n + 1, line))?;
+        match statements.last_mut() {
+            Some((_, statement)) if statement.ends_with('-') => {
+                statement.pop();
+                statement.push_str(content);
+            },
+            _ => statements.push((n + 1, content.to_string())),
+        }
+        n += 1;
+    };
+
+    let mut block: Option<String> = None; // Open block inside the CTAB block
+    let (mut ctab, mut ended) = (false, false);
+    let (mut counts, mut atoms, mut bonds): (Option<(usize, usize)>, HashSet<&str>, usize) = (None, HashSet::new(), 0);
+    for (line, statement) in &statements {
+        let words: Vec<&str> = statement.split_whitespace().collect();
+        match (words.first().copied(), words.get(1).copied(), block.as_deref()) {
+            (Some("BEGIN"), Some("CTAB"), None) if !ctab => ctab = true,
+            (Some("END"), Some("CTAB"), None) if ctab && !ended => ended = true,
+            (Some("COUNTS"), _, None) if ctab && !ended => {
+                let number = |i: usize| words.get(i).and_then(|word| word.parse::<usize>().ok());
+                counts = Some(number(1).zip(number(2)).ok_or_else(|| format!("line {}: COUNTS line doesn't give the atom and bond counts", line))?);
+            },
+            (Some("BEGIN"), Some(name), None) if ctab && !ended => block = Some(name.to_string()),
+            (Some("END"), Some(name), Some(open)) if name == open => block = None,
+            (_, _, Some("ATOM")) => {
+                let coordinates = words.len() >= 5 && words[2..5].iter().all(|word| word.parse::<f64>().is_ok());
+                if !coordinates {
+                    return Err(format!("line {}: malformed atom line: {}", line, statement));
+                }
+                atoms.insert(words[0]);
+            },
+            (_, _, Some("BOND")) => {
+                if words.len() < 4 || !atoms.contains(words[2]) || !atoms.contains(words[3]) {
+                    return Err(format!("line {}: malformed bond line: {}", line, statement));
+                }
+                bonds += 1;
+            },
+            (_, _, Some(_)) => {},
+            _ => return Err(format!("line {}: unexpected V3000 line: {}", line, statement)),
+        }
+    }
+    if !ctab || !ended || block.is_some() {
+        return Err(format!("line {}: connection table isn't ended by M  V30 END CTAB", end + 1));
+    }
+    match counts {
+        None => Err("connection table lacks its COUNTS line".to_string()),
+        Some((expected, _)) if atoms.len() != expected => Err(format!("connection table has {} atom lines, its COUNTS line gives {}", atoms.len(), expected)),
+        Some((_, expected)) if bonds != expected => Err(format!("connection table has {} bond lines, its COUNTS line gives {}", bonds, expected)),
+        Some(_) => Ok(end),
+    }
+}
@@ -0,0 +1,109 @@
+// File-like view of an archive: all records concatenated into one decompressed SD stream with
+// Read + Seek. Only the record covering the current position is held in memory. Decompressed
+// record sizes are not stored in the archive, so they are learned as the stream is read; seeking
+// from the end decompresses every record not seen yet, once.
+
+use std::io::{
+    self,
+    Read, Seek, SeekFrom
+};
+use crate::archive::Archive;
+
+pub struct CmzSeekableReader {
+    archive: Archive,
+    ends: Vec<u64>, // Stream offset just past each record measured so far, in archive order
+    position: u64,
+    current: Option<(usize, Vec<u8>)>, // Most recently decompressed record
+}
+
+impl CmzSeekableReader {
+    pub fn new(archive: Archive) -> CmzSeekableReader {
+        CmzSeekableReader { archive, ends: Vec::new(), position: 0, current: None }
+    }
+
+    pub fn into_inner(self) -> Archive {
+        self.archive
+    }
+
+    // Size of the whole decompressed stream
+    pub fn stream_len(&mut self) -> io::Result<u64> {
+        while self.ends.len() < self.archive.len() {
+            self.load(self.ends.len())?;
+        }
+        Ok(self.ends.last().copied().unwrap_or(0))
+    }
+
+    fn start(&self, i: usize) -> u64 {
+        if i == 0 { 0 } else { self.ends[i - 1] }
+    }
+
+    // Makes record i the current one, measuring it if it is the next unmeasured record
+    fn load(&mut self, i: usize) -> io::Result<()> {
+        if let Some((current, _)) = self.current {
+            if current == i {
+                return Ok(());
+            }
+        }
+        let data = self.archive.read_record(i)?;
+        if i == self.ends.len() {
+            self.ends.push(self.start(i) + data.len() as u64);
+        }
+        self.current = Some((i, data));
+        Ok(())
+    }
+
+    // Returns the record holding the byte at position, None past the end of the stream
+    fn locate(&mut self, position: u64) -> io::Result<Option<usize>> {
+        let i = self.ends.partition_point(|&end| end <= position);
+        if i < self.ends.len() {
+            return Ok(Some(i));
+        }
+        while self.ends.len() < self.archive.len() {
+            let i = self.ends.len();
+            self.load(i)?;
+            if self.ends[i] > position {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Read for CmzSeekableReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let i = match self.locate(self.position)? {
+            Some(i) => i,
+            None => return Ok(0),
+        };
+        self.load(i)?;
+        let start = (self.position - self.start(i)) as usize;
+        let data = &self.current.as_ref().unwrap().1[start..];
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for CmzSeekableReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            },
+            SeekFrom::Current(offset) => (self.position, offset),
+            SeekFrom::End(offset) => (self.stream_len()?, offset),
+        };
+        match base.checked_add_signed(offset) {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            },
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position!")),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// Record selection by -r --record and --records-from. Lists from files can be huge (e.g. every hit
+// of a screen) and mix indices, ranges and molecule names, so they are streamed line by line and
+// ranges are checked against the archive before they are expanded. Records are selected once, in
+// the order they are first given, and all entries that don't exist are reported together, or with
+// --ignore-missing skipped with a warning.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader}
+};
+use cmzip::{Archive, Record, record::NameSource};
+use crate::names::{NameFilter, NameMatch};
+
+const REPORTED: usize = 10; // Offending entries listed in an error, the rest are counted
+
+pub enum Entry {
+    Index(usize),
+    Range(usize, usize), // Inclusive
+    Name(String),
+}
+
+impl Entry {
+    // Parses an index or inclusive range like 5 or 100-200, with names set any other value is a
+    // molecule name
+    pub fn parse(value: &str, names: bool) -> Result<Entry, String> {
+        let value = value.trim();
+        let index = |x: &str| x.trim().parse::<usize>().ok();
+        let entry = match value.split_once('-') {
+            Some((start, end)) => index(start).zip(index(end)).map(|(start, end)| Entry::Range(start, end)),
+            None => index(value).map(Entry::Index),
+        };
+        match entry {
+            Some(Entry::Range(start, end)) if start > end => Err(format!("Invalid record range {}!", value)),
+            Some(entry) => Ok(entry),
+            None if names => Ok(Entry::Name(value.to_string())),
+            None => Err(format!("Invalid record index {}!", value)),
+        }
+    }
+}
+
+// Reads the entries of a list file, or of stdin for -, given one per line or comma separated
+pub fn read_entries(path: &str) -> Result<Vec<Entry>, String> {
+    let input: Box<dyn BufRead> = match path {
+        "-" => Box::new(BufReader::new(io::stdin())),
+        path => Box::new(BufReader::new(File::open(path).map_err(|e| format!("Unable to read {}: {}", path, e))?)),
+    };
+    let mut entries: Vec<Entry> = Vec::new();
+    for line in input.lines() {
+        let line = line.map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        for value in line.split(',').filter(|value| !value.trim().is_empty()) {
+            entries.push(Entry::parse(value, true)?);
+        }
+    }
+    Ok(entries)
+}
+
+// Record indices of the entries in the given order, each record once. A name selects its records
+// in archive order, finding them decompresses the whole archive once. With ignore_missing, indices
+// past the end and names without records are left out and ranges end with the archive.
+pub fn resolve(entries: &[Entry], archive: &Archive, source: NameSource, mode: NameMatch, ignore_missing: bool) -> Result<Vec<usize>, String> {
+    let len = archive.len();
+    let named = named_records(entries, archive, source, mode)?;
+    let (mut missing, mut unknown): (Vec<String>, Vec<&str>) = (Vec::new(), Vec::new());
+    for entry in entries {
+        match *entry {
+            Entry::Index(i) if i >= len => missing.push(i.to_string()),
+            // Ranges reaching past the end are still extracted up to it with ignore_missing
+            Entry::Range(start, end) if end >= len => missing.push(match start.max(len) {
+                first if ignore_missing && first == end => end.to_string(),
+                first if ignore_missing => format!("{}-{}", first, end),
+                _ => format!("{}-{}", start, end),
+            }),
+            Entry::Name(ref name) if !named.contains_key(&mode.key(name)) => unknown.push(name),
+            _ => {},
+        }
+    }
+    if !missing.is_empty() {
+        let noun = if missing.len() == 1 { "Record" } else { "Records" };
+        let verb = if missing.len() == 1 { "does" } else { "do" };
+        let message = format!("{} {} {} not exist, the archive holds {} records", noun, listing(&missing), verb, len);
+        match ignore_missing {
+            true => log::warn!("{}, skipping {}", message, if missing.len() == 1 { "it" } else { "them" }),
+            false => return Err(message + "!"),
+        }
+    }
+    if !unknown.is_empty() {
+        match ignore_missing {
+            true => log::warn!("No record is named {}, skipping {}", listing(&unknown), if unknown.len() == 1 { "it" } else { "them" }),
+            false => return Err(format!("No record is named {}!", listing(&unknown))),
+        }
+    }
+
+    let mut selected = vec![false; len];
+    let mut records: Vec<usize> = Vec::new();
+    let mut select = |i: usize| {
+        if !selected[i] {
+            selected[i] = true;
+            records.push(i);
+        }
+    };
+    for entry in entries {
+        match *entry {
+            Entry::Index(i) if i < len => select(i),
+            Entry::Index(_) => {},
+            Entry::Range(start, end) => (start..len.min(end.saturating_add(1))).for_each(&mut select),
+            Entry::Name(ref name) => named.get(&mode.key(name)).into_iter().flatten().copied().for_each(&mut select),
+        }
+    }
+    Ok(records)
+}
+
+// Records of every name among the entries by name key, empty without names
+fn named_records(entries: &[Entry], archive: &Archive, source: NameSource, mode: NameMatch) -> Result<HashMap<String, Vec<usize>>, String> {
+    let mut named: HashMap<String, Vec<usize>> = HashMap::new();
+    let names: Vec<&str> = entries.iter().filter_map(|entry| match entry {
+        Entry::Name(name) => Some(name.as_str()),
+        _ => None,
+    }).collect();
+    if names.is_empty() {
+        return Ok(named);
+    }
+    let filter = NameFilter::new(source, mode, names);
+    for i in 0..archive.len() {
+        let data = archive.read_record(i).map_err(|e| format!("Error extracting record {}: {}", i, e))?;
+        if let Some(key) = filter.matching(&Record::new(data)) {
+            named.entry(key).or_default().push(i);
+        }
+    }
+    Ok(named)
+}
+
+// Entries for an error message, the first REPORTED of them
+fn listing<S: AsRef<str>>(entries: &[S]) -> String {
+    let listed = entries.iter().take(REPORTED).map(AsRef::as_ref).collect::<Vec<&str>>().join(", ");
+    match entries.len() {
+        n if n > REPORTED => format!("{} and {} more", listed, n - REPORTED),
+        _ => listed,
+    }
+}
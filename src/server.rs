@@ -0,0 +1,478 @@
+// Minimal HTTP/1.1 interface for browsing an archive, started with the serve subcommand.
+//
+// Endpoints:
+//   GET /info             archive summary (JSON)
+//   GET /list             index and molecule name of every record (JSON)
+//   GET /records/{i}      decompressed record i (SD text)
+//   GET /records?name=... all records with the given molecule name (SD text)
+//   POST /compress?input=...&output=...[&level=N]    SD file to a new archive (JSON summary)
+//   POST /recompress?input=...&output=...[&level=N]  archive to a new archive at another level
+//
+// The compression jobs are only enabled with a jobs directory, input and output are file names in
+// it. Connections are queued and handled by a fixed pool of workers in priority order, so a long
+// bulk scan or batch recompression can't starve single-record fetches. Single records and /info
+// default to high priority, /list, name lookups and compression jobs to low; any request may
+// override this with ?priority=high|normal|low. A worker first reads the request of a new
+// connection and queues it again with its priority, so a slow client only holds up that worker.
+// Waiting raises the priority of a request, so low priority requests are still served under a
+// steady load of high priority ones. Among requests of equal priority the archive with the fewest
+// requests being handled goes first, then the client with the fewest, so recompressing one
+// archive can't take every worker from the others, and neither can one client.
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    io::{
+        self,
+        BufReader,
+        prelude::*
+    },
+    fs::{self, File, OpenOptions},
+    net::{IpAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant}
+};
+use cmzip::{Archive, SdfRecords, Writer};
+use crate::{json, rate::{Limited, RateLimit}};
+
+const SDF_CONTENT_TYPE: &str = "chemical/x-mdl-sdfile";
+const JSON_CONTENT_TYPE: &str = "application/json";
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const AGING: Duration = Duration::from_secs(2); // Waiting this long raises a request one priority
+
+struct State {
+    archive: Archive, // Shared by all workers, records are read without locking
+    filename: String, // Of the archive, the fairness key of requests reading it
+    jobs: Option<PathBuf>, // Directory of compression job inputs and outputs, None disables them
+    names: Mutex<Option<Arc<Vec<String>>>>, // Molecule names as the archive reads them, read on first request that needs them
+    limit: Option<RateLimit>, // Of all responses together
+}
+
+impl State {
+    fn names(&self) -> io::Result<Arc<Vec<String>>> {
+        let mut names = self.names.lock().unwrap();
+        if let Some(names) = names.as_ref() {
+            return Ok(Arc::clone(names));
+        }
+        let source = self.archive.name_source();
+        let mut list: Vec<String> = Vec::with_capacity(self.archive.len());
+        for record in self.archive.records() {
+            list.push(record?.name(&source).unwrap_or_default());
+        }
+        let list = Arc::new(list);
+        *names = Some(Arc::clone(&list));
+        Ok(list)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Method {
+    Get,
+    Post,
+}
+
+// Connection waiting for a worker
+struct Job {
+    priority: Priority,
+    accepted: Instant, // Waiting counts from here, also while the request is read
+    seq: u64, // Arrival order, keeps requests of equal priority first come first served
+    client: Option<IpAddr>,
+    archive: Option<String>, // Read or written by the request, None until read
+    stream: TcpStream,
+    request: Option<Result<(Method, String), Response>>, // Request target or an error response for malformed requests, None until read
+}
+
+impl Job {
+    // Priority plus one level for every AGING waited
+    fn rank(&self, now: Instant) -> u128 {
+        self.priority as u128 + now.duration_since(self.accepted).as_millis() / AGING.as_millis()
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    pending: Mutex<Pending>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct Pending {
+    jobs: Vec<Job>,
+    archives: HashMap<Option<String>, usize>, // Jobs on every archive taken by workers
+    clients: HashMap<Option<IpAddr>, usize>, // Jobs of every client taken by workers
+}
+
+impl Queue {
+    fn push(&self, job: Job) {
+        self.pending.lock().unwrap().jobs.push(job);
+        self.ready.notify_one();
+    }
+
+    // Takes the job of highest rank, on the archive and then of the client with the fewest jobs
+    // taken among equal ranks, until done() is called with its archive and client
+    fn pop(&self) -> Job {
+        let mut pending = self.pending.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let Pending { jobs, archives, clients } = &mut *pending;
+            let best = jobs.iter().enumerate()
+                .max_by_key(|(_, job)| (
+                    job.rank(now),
+                    Reverse(archives.get(&job.archive).copied().unwrap_or(0)),
+                    Reverse(clients.get(&job.client).copied().unwrap_or(0)),
+                    Reverse(job.seq),
+                ))
+                .map(|(i, _)| i);
+            if let Some(i) = best {
+                let job = jobs.swap_remove(i);
+                *archives.entry(job.archive.clone()).or_insert(0) += 1;
+                *clients.entry(job.client).or_insert(0) += 1;
+                return job;
+            }
+            pending = self.ready.wait(pending).unwrap();
+        }
+    }
+
+    fn done(&self, archive: Option<String>, client: Option<IpAddr>) {
+        let mut pending = self.pending.lock().unwrap();
+        release(&mut pending.archives, archive);
+        release(&mut pending.clients, client);
+    }
+}
+
+fn release<K: Eq + std::hash::Hash>(active: &mut HashMap<K, usize>, key: K) {
+    if let Some(count) = active.get_mut(&key) {
+        *count -= 1;
+        if *count == 0 {
+            active.remove(&key);
+        }
+    }
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: Vec<u8>) -> Response {
+        Response { status: "200 OK", content_type, body }
+    }
+
+    fn error(status: &'static str, message: &str) -> Response {
+        Response { status, content_type: "text/plain", body: format!("{}\n", message).into_bytes() }
+    }
+}
+
+// Serves the archive until the process is killed. Connections are accepted on this thread and
+// handed to the worker pool through the priority queue, which reads and answers them.
+pub fn run(archive: Archive, filename: &str, jobs: Option<PathBuf>, host: &str, port: u16, workers: usize, limit: Option<RateLimit>) -> io::Result<()> {
+    let listener = TcpListener::bind((host, port))?;
+    log::info!("Serving {} records on http://{}:{}/", archive.len(), host, port);
+    if let Some(jobs) = &jobs {
+        log::info!("Accepting compression jobs in {}", jobs.display());
+    }
+    let state = Arc::new(State { archive, filename: filename.to_string(), jobs, names: Mutex::new(None), limit });
+    let queue = Arc::new(Queue::default());
+
+    for _ in 0..workers {
+        let state = Arc::clone(&state);
+        let queue = Arc::clone(&queue);
+        thread::spawn(move || loop {
+            let mut job = queue.pop();
+            let (archive, client) = (job.archive.clone(), job.client);
+            match job.request.take() {
+                Some(request) => {
+                    if let Err(e) = respond(job.stream, request, &state) {
+                        log::warn!("Error handling request: {}", e);
+                    }
+                },
+                // Read requests are queued again with their own priority
+                None => match read_request(&job.stream) {
+                    Ok(request) => {
+                        job.priority = match &request {
+                            Ok((_, target)) => priority(target),
+                            Err(_) => Priority::High, // Errors are cheap to answer
+                        };
+                        job.archive = Some(match &request {
+                            Ok((_, target)) => target_archive(target, &state),
+                            Err(_) => state.filename.clone(),
+                        });
+                        job.request = Some(request);
+                        queue.push(job);
+                    },
+                    Err(e) => log::warn!("Error reading request: {}", e),
+                },
+            }
+            queue.done(archive, client);
+        });
+    }
+
+    for (seq, stream) in listener.incoming().enumerate() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Connection failed: {}", e);
+                continue;
+            }
+        };
+        // New connections wait at high priority, reading a request is cheap
+        let client = stream.peer_addr().ok().map(|address| address.ip());
+        queue.push(Job { priority: Priority::High, accepted: Instant::now(), seq: seq as u64, client, archive: None, stream, request: None });
+    }
+    Ok(())
+}
+
+// Reads the request line, e.g. "GET /records/12 HTTP/1.1", followed by headers we don't need
+fn read_request(stream: &TcpStream) -> io::Result<Result<(Method, String), Response>> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    Ok(match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => Ok((Method::Get, target.to_string())),
+        (Some("POST"), Some(target)) => Ok((Method::Post, target.to_string())),
+        (Some(_), Some(_)) => Err(Response::error("405 Method Not Allowed", "Only GET and POST requests are supported")),
+        _ => Err(Response::error("400 Bad Request", "Malformed request")),
+    })
+}
+
+// Explicit ?priority= wins, otherwise requests touching many records are low priority
+fn priority(target: &str) -> Priority {
+    let (path, query) = split_target(target);
+    match query_param(query, "priority").as_deref() {
+        Some("high") => return Priority::High,
+        Some("normal") => return Priority::Normal,
+        Some("low") => return Priority::Low,
+        _ => {},
+    }
+    match path.trim_end_matches('/') {
+        "/list" | "/records" | "/compress" | "/recompress" => Priority::Low,
+        _ => Priority::High,
+    }
+}
+
+// Compression jobs are keyed on the archive they write, everything else reads the served archive
+fn target_archive(target: &str, state: &State) -> String {
+    let (path, query) = split_target(target);
+    match (path.trim_end_matches('/'), query_param(query, "output")) {
+        ("/compress" | "/recompress", Some(output)) => output,
+        _ => state.filename.clone(),
+    }
+}
+
+fn respond(mut stream: TcpStream, request: Result<(Method, String), Response>, state: &State) -> io::Result<()> {
+    let response = match request {
+        Ok((method, target)) => route(method, &target, state),
+        Err(response) => response,
+    };
+
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        response.status, response.content_type, response.body.len())?;
+    match &state.limit {
+        Some(limit) => Limited::new(&mut stream, limit).write_all(&response.body)?,
+        None => stream.write_all(&response.body)?,
+    }
+    stream.flush()
+}
+
+fn split_target(target: &str) -> (&str, &str) {
+    match target.find('?') {
+        Some(pos) => (&target[..pos], &target[pos + 1..]),
+        None => (target, ""),
+    }
+}
+
+fn route(method: Method, target: &str, state: &State) -> Response {
+    let (path, query) = split_target(target);
+    let path = path.trim_end_matches('/');
+    let job = path == "/compress" || path == "/recompress";
+    match (method, job) {
+        (Method::Get, true) => return Response::error("405 Method Not Allowed", "Compression jobs are started with POST"),
+        (Method::Post, false) => return Response::error("405 Method Not Allowed", "Only compression jobs are started with POST"),
+        _ => {},
+    }
+    let result = match path {
+        "/info" => info(state),
+        "/list" => list(state),
+        "/compress" | "/recompress" => match job_files(state, query) {
+            Ok((input, output, level)) if path == "/compress" => compress(&input, &output, level),
+            Ok((input, output, level)) => recompress(&input, &output, level),
+            Err(response) => return response,
+        },
+        "/records" => match query_param(query, "name") {
+            Some(name) => records_by_name(state, &name),
+            None => return Response::error("400 Bad Request", "Missing name parameter"),
+        },
+        p if p.starts_with("/records/") => match p["/records/".len()..].parse::<usize>() {
+            Ok(i) => record(state, i),
+            Err(_) => return Response::error("400 Bad Request", "Invalid record index"),
+        },
+        _ => return Response::error("404 Not Found", "No such endpoint"),
+    };
+    match result {
+        Ok(response) => response,
+        Err(e) if job && e.kind() == io::ErrorKind::NotFound => Response::error("404 Not Found", &e.to_string()),
+        Err(e) if job && e.kind() == io::ErrorKind::AlreadyExists => Response::error("409 Conflict", "Output file already exists"),
+        Err(e) => Response::error("500 Internal Server Error", &e.to_string()),
+    }
+}
+
+fn info(state: &State) -> io::Result<Response> {
+    let archive = &state.archive;
+    let body = format!("{{\"records\":{},\"file_size\":{},\"index_size\":{}}}",
+        archive.len(), archive.file_size(), archive.index_size());
+    Ok(Response::ok(JSON_CONTENT_TYPE, body.into_bytes()))
+}
+
+fn list(state: &State) -> io::Result<Response> {
+    let names = state.names()?;
+    let entries: Vec<String> = names.iter().enumerate()
+        .map(|(i, name)| format!("{{\"index\":{},\"name\":{}}}", i, json::string(name)))
+        .collect();
+    Ok(Response::ok(JSON_CONTENT_TYPE, format!("[{}]", entries.join(",")).into_bytes()))
+}
+
+fn record(state: &State, i: usize) -> io::Result<Response> {
+    if i >= state.archive.len() {
+        return Ok(Response::error("404 Not Found", "No such record"));
+    }
+    Ok(Response::ok(SDF_CONTENT_TYPE, state.archive.read_record(i)?))
+}
+
+fn records_by_name(state: &State, name: &str) -> io::Result<Response> {
+    let names = state.names()?;
+    let mut body: Vec<u8> = Vec::new();
+    for (i, _) in names.iter().enumerate().filter(|(_, n)| n.as_str() == name) {
+        state.archive.extract(i, &mut body)?;
+    }
+    if body.is_empty() {
+        return Ok(Response::error("404 Not Found", "No such record"));
+    }
+    Ok(Response::ok(SDF_CONTENT_TYPE, body))
+}
+
+// Input and output paths in the jobs directory and the level of a compression job
+fn job_files(state: &State, query: &str) -> Result<(PathBuf, PathBuf, u32), Response> {
+    let jobs = state.jobs.as_ref().ok_or_else(|| Response::error("403 Forbidden", "Compression jobs are disabled, start the server with --jobs-dir"))?;
+    // Plain file names only, so jobs can't reach outside the directory
+    let file = |key: &str| match query_param(query, key) {
+        Some(name) if !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\']) => Ok(jobs.join(name)),
+        Some(_) => Err(Response::error("400 Bad Request", &format!("Invalid {} file name", key))),
+        None => Err(Response::error("400 Bad Request", &format!("Missing {} parameter", key))),
+    };
+    let level = match query_param(query, "level") {
+        Some(level) => level.parse::<u32>().map_err(|_| Response::error("400 Bad Request", "Invalid level"))?,
+        None => 6,
+    };
+    Ok((file("input")?, file("output")?, level))
+}
+
+// Writes the records given by add to a new xz archive at output, removed again if a record fails
+fn create_archive<F>(output: &PathBuf, add: F) -> io::Result<Response>
+where
+    F: FnOnce(&File) -> io::Result<(usize, u64)>,
+{
+    let file = OpenOptions::new().write(true).create_new(true).open(output)?;
+    match add(&file) {
+        Ok((records, file_size)) => {
+            log::info!("Wrote {} records to {}", records, output.display());
+            let body = format!("{{\"records\":{},\"file_size\":{}}}", records, file_size);
+            Ok(Response::ok(JSON_CONTENT_TYPE, body.into_bytes()))
+        },
+        Err(e) => {
+            fs::remove_file(output).ok();
+            Err(e)
+        },
+    }
+}
+
+fn compress(input: &PathBuf, output: &PathBuf, level: u32) -> io::Result<Response> {
+    if level > cmzip::Codec::Xz.max_level() {
+        return Ok(Response::error("400 Bad Request", "Invalid level"));
+    }
+    let records = SdfRecords::new(BufReader::new(File::open(input)?));
+    create_archive(output, |file| {
+        let mut writer = Writer::new(io::BufWriter::new(file), level);
+        for record in records {
+            writer.add_record(record?.as_bytes())?;
+        }
+        let count = writer.len();
+        writer.finish()?.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+        Ok((count, file.metadata()?.len()))
+    })
+}
+
+// Keeps the codec, embedded dictionary and encryption of the input
+fn recompress(input: &PathBuf, output: &PathBuf, level: u32) -> io::Result<Response> {
+    let archive = Archive::open(input)?;
+    if level > archive.codec().max_level() {
+        return Ok(Response::error("400 Bad Request", "Invalid level"));
+    }
+    create_archive(output, |file| {
+        let dictionary = archive.embedded_dictionary();
+        let mut writer = Writer::with_codec(io::BufWriter::new(file), archive.codec(), level, dictionary.as_ref())?;
+        if let Some(key) = archive.key() {
+            writer.encrypt(key.clone())?;
+        }
+        if let Some(dictionary) = &dictionary {
+            writer.embed_dictionary(dictionary);
+        }
+        for record in archive.records() {
+            writer.add_record(record?.as_bytes())?;
+        }
+        let count = writer.len();
+        writer.finish()?.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+        Ok((count, file.metadata()?.len()))
+    })
+}
+
+// Returns the percent-decoded value of key from a query string
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query.split('&')
+        .filter_map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            Some((kv.next()?, kv.next().unwrap_or("")))
+        })
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| percent_decode(v))
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    },
+                    None => decoded.push(b'%'),
+                }
+            },
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
@@ -0,0 +1,84 @@
+// Archives split into shards, listed in a manifest (.cmzd) with one shard file per line. Relative
+// paths are resolved against the directory holding the manifest, blank lines and lines starting
+// with # are skipped.
+//
+// Records are selected with --where conditions on SD data tags: NAME=VALUE and NAME!=VALUE compare
+// text, NAME~PATTERN matches a regular expression and NAME<N, NAME<=N, NAME>N, NAME>=N compare
+// numbers, read as set by the number policy (see numbers.rs). Records without the tag never match.
+
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf}
+};
+use cmzip::Record;
+use regex::Regex;
+use crate::numbers::{self, Policy};
+
+// Shard files listed in the manifest, in manifest order
+pub fn read_manifest(path: &str) -> io::Result<Vec<PathBuf>> {
+    let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line))
+        .collect())
+}
+
+enum Operator {
+    Equal(String),
+    NotEqual(String),
+    Matches(Regex),
+    Less(f64),
+    LessEqual(f64),
+    Greater(f64),
+    GreaterEqual(f64),
+}
+
+pub struct Condition {
+    tag: String,
+    operator: Operator,
+    policy: Policy,
+}
+
+impl Condition {
+    pub fn parse(condition: &str, policy: Policy) -> Result<Condition, String> {
+        let invalid = || format!("Invalid condition {}, expected NAME=VALUE, NAME~PATTERN or NAME<NUMBER!", condition);
+        let start = condition.find(['=', '!', '~', '<', '>']).filter(|&start| start > 0).ok_or_else(invalid)?;
+        let (tag, rest) = condition.split_at(start);
+        let (symbol, value) = match rest.get(..2) {
+            Some("!=") | Some("<=") | Some(">=") => rest.split_at(2),
+            _ => rest.split_at(1),
+        };
+        let number = || numbers::parse(value, policy).ok_or_else(invalid);
+        let operator = match symbol {
+            "=" => Operator::Equal(value.to_string()),
+            "!=" => Operator::NotEqual(value.to_string()),
+            "~" => Operator::Matches(Regex::new(value).map_err(|e| format!("Invalid pattern in {}: {}", condition, e))?),
+            "<" => Operator::Less(number()?),
+            "<=" => Operator::LessEqual(number()?),
+            ">" => Operator::Greater(number()?),
+            ">=" => Operator::GreaterEqual(number()?),
+            _ => return Err(invalid()),
+        };
+        Ok(Condition { tag: tag.trim().to_string(), operator, policy })
+    }
+
+    pub fn matches(&self, record: &Record) -> bool {
+        let value = match record.tag(&self.tag) {
+            Some(value) => value.trim(),
+            None => return false,
+        };
+        let number = || numbers::parse(value, self.policy);
+        match &self.operator {
+            Operator::Equal(expected) => value == expected,
+            Operator::NotEqual(expected) => value != expected,
+            Operator::Matches(pattern) => pattern.is_match(value),
+            Operator::Less(limit) => number().is_some_and(|n| n < *limit),
+            Operator::LessEqual(limit) => number().is_some_and(|n| n <= *limit),
+            Operator::Greater(limit) => number().is_some_and(|n| n > *limit),
+            Operator::GreaterEqual(limit) => number().is_some_and(|n| n >= *limit),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+// Index sidecar files: the decoded index of an archive stored uncompressed next to it (a.cmz.idx
+// for a.cmz), so tools opening the same archive over and over skip decompressing the footer index.
+// Unlike the index cache they are written on request and travel with the archive.
+//
+// Layout: MAGIC, the archive size, the compressed index size and the SHA-256 of the compressed
+// index, all identifying the footer the sidecar was made from, followed by the number of index
+// entries and the entries, all little endian u64s. A sidecar that doesn't match the footer is
+// stale and ignored, readers then decode the index from the footer as usual.
+
+use std::{
+    convert::TryInto,
+    fs,
+    io,
+    path::{Path, PathBuf}
+};
+use sha2::{Digest, Sha256};
+
+pub const MAGIC: [u8; 8] = *b"CMZ\0SIDX";
+const HEADER_SIZE: usize = 8 + 8 + 8 + 32 + 8;
+
+// Sidecar location of the archive at path
+pub fn path(archive: &Path) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+// Index stored in the sidecar at path, None if there is none or it belongs to another footer
+pub fn load(path: &Path, file_size: u64, index_size: u64, compressed_index: &[u8]) -> Option<Vec<u64>> {
+    let data = fs::read(path).ok()?;
+    if data.len() < HEADER_SIZE || data[..8] != MAGIC {
+        return None;
+    }
+    let number = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().unwrap());
+    if number(8) != file_size || number(16) != index_size || data[24..56] != Sha256::digest(compressed_index)[..] {
+        return None;
+    }
+    let entries = &data[HEADER_SIZE..];
+    if entries.len() as u64 != number(56).checked_mul(8)? {
+        return None;
+    }
+    Some(entries.chunks(8).map(|value| u64::from_le_bytes(value.try_into().unwrap())).collect())
+}
+
+// Writes the sidecar to a temporary file first, so readers never see half of it
+pub fn store(path: &Path, file_size: u64, index_size: u64, compressed_index: &[u8], index: &[u64]) -> io::Result<()> {
+    let mut data: Vec<u8> = Vec::with_capacity(HEADER_SIZE + index.len() * 8);
+    data.extend_from_slice(&MAGIC);
+    data.extend_from_slice(&file_size.to_le_bytes());
+    data.extend_from_slice(&index_size.to_le_bytes());
+    data.extend_from_slice(&Sha256::digest(compressed_index));
+    data.extend_from_slice(&(index.len() as u64).to_le_bytes());
+    for value in index {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    fs::write(&tmp, &data)?;
+    fs::rename(&tmp, path)
+}
@@ -0,0 +1,65 @@
+// Detached ed25519 signatures of archives for cmzip sign and verification in unzip and verify, in
+// the minisign format: a.cmz is signed in a.cmz.minisig, which `minisign -Vm a.cmz` verifies as
+// well, and keys made with `minisign -G` work here. The signature covers the whole archive file,
+// so any change after signing, including ones made in place, fails verification. The trusted
+// comment names the signed file and when it was signed.
+
+use std::{
+    fs::File,
+    io::BufReader
+};
+use minisign::{PublicKey, SecretKey, SignatureBox};
+use crate::{diagnostic::Diagnostic, unix_time};
+
+pub const SUFFIX: &str = ".minisig";
+// Passphrase of encrypted secret keys, prompted for otherwise
+pub const PASSWORD_VAR: &str = "CMZIP_KEY_PASSWORD";
+
+pub fn signature_path(archive: &str) -> String {
+    format!("{}{}", archive, SUFFIX)
+}
+
+// Signature of archive made with the secret key in key_path, as the contents of a .minisig file
+pub fn sign(archive: &str, key_path: &str, comment: Option<&str>) -> Result<String, String> {
+    let key = secret_key(key_path)?;
+    let file = File::open(archive).map_err(|e| format!("Unable to read {}: {}", archive, e))?;
+    let name = std::path::Path::new(archive).file_name().unwrap_or_default().to_string_lossy();
+    let trusted = match comment {
+        Some(comment) => comment.to_string(),
+        None => format!("timestamp:{}\tfile:{}\thashed", unix_time(), name),
+    };
+    let signature = minisign::sign(None, &key, BufReader::new(file), Some(&trusted), Some("signature from cmzip secret key"))
+        .map_err(|e| format!("Unable to sign {}: {}", archive, e))?;
+    Ok(signature.into_string())
+}
+
+// Checks the signature of archive in signature_path against the public key in key_path, returns
+// its trusted comment. Signatures that don't match are reported as checksum_mismatch.
+pub fn verify(archive: &str, signature_path: &str, key_path: &str) -> Result<String, Box<Diagnostic>> {
+    let unreadable = |path: &str, e: minisign::PError| Box::new(match std::path::Path::new(path).exists() {
+        true => Diagnostic::error(&format!("Unable to read {}!", path)).cause(e),
+        false => Diagnostic::error(&format!("Unable to read {}!", path)).code("not_found").cause(e),
+    });
+    let key = PublicKey::from_file(key_path).map_err(|e| unreadable(key_path, e))?;
+    let signature = SignatureBox::from_file(signature_path).map_err(|e| unreadable(signature_path, e))?;
+    let file = File::open(archive).map_err(|e| Box::new(Diagnostic::io(&format!("Unable to read {}!", archive), &e)))?;
+    let invalid = |e: minisign::PError| Box::new(Diagnostic::error(&format!("Signature of {} is invalid!", archive)).code("checksum_mismatch").cause(e));
+    minisign::verify(&key, &signature, BufReader::new(file), true, false, false).map_err(invalid)?;
+    signature.trusted_comment().map_err(invalid)
+}
+
+// Secret key in key_path. Keys without a password (minisign -W) are used as they are, otherwise
+// the password comes from PASSWORD_VAR or a prompt.
+fn secret_key(key_path: &str) -> Result<SecretKey, String> {
+    let unreadable = |e: minisign::PError| format!("Unable to read secret key {}: {}", key_path, e);
+    let text = std::fs::read_to_string(key_path).map_err(|e| format!("Unable to read secret key {}: {}", key_path, e))?;
+    if let Ok(key) = SecretKey::from_box(text.clone().into(), Some(String::new())) {
+        return Ok(key);
+    }
+    let password = match std::env::var(PASSWORD_VAR) {
+        Ok(password) => password,
+        Err(_) => rpassword::prompt_password(format!("Password of {}: ", key_path))
+            .map_err(|_| format!("Secret key {} is encrypted, set {} to its password!", key_path, PASSWORD_VAR))?,
+    };
+    SecretKey::from_box(text.into(), Some(password)).map_err(unreadable)
+}
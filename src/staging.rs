@@ -0,0 +1,117 @@
+// Staging of commands writing several outputs, e.g. the shards of unzip --split or the archives of
+// zip --dir. Outputs are written under a temporary name next to their final one and only renamed
+// once all of them are complete, so an interrupted command can't leave a partial set of files that
+// downstream jobs mistake for a complete one. A failed rename undoes the renames before it and puts
+// back the files they replaced. Commands writing one archive use AtomicOutput instead.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex
+};
+
+const PREFIX: &str = ".partial-"; // Of staged files, which keeps their extension
+
+#[derive(Default)]
+pub struct Staging {
+    staged: Mutex<Vec<(PathBuf, PathBuf)>>, // Temporary and final path
+}
+
+impl Staging {
+    pub fn new() -> Staging {
+        Staging::default()
+    }
+
+    // Temporary path to write the output at path to, a file left there by an interrupted command is
+    // removed
+    pub fn stage(&self, path: &Path) -> PathBuf {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let staged = path.with_file_name(format!("{}{}", PREFIX, name));
+        let _ = fs::remove_file(&staged);
+        self.staged.lock().unwrap().push((staged.clone(), path.to_path_buf()));
+        staged
+    }
+
+    // Renames every staged output to its final path, all or none
+    pub fn commit(self) -> Result<(), String> {
+        let staged = self.staged.lock().unwrap().split_off(0);
+        let mut done: Vec<(&Path, &Path, Option<PathBuf>)> = Vec::new(); // Renamed outputs and the files they replaced
+        for (temporary, path) in &staged {
+            let replaced = path.with_file_name(format!("{}replaced-{}", PREFIX, path.file_name().unwrap_or_default().to_string_lossy()));
+            let replaced = fs::rename(path, &replaced).ok().map(|_| replaced);
+            if let Err(e) = fs::rename(temporary, path) {
+                if let Some(replaced) = &replaced {
+                    let _ = fs::rename(replaced, path);
+                }
+                for (temporary, path, replaced) in done.into_iter().rev() {
+                    let _ = fs::rename(path, temporary);
+                    if let Some(replaced) = replaced {
+                        let _ = fs::rename(replaced, path);
+                    }
+                }
+                discard(&staged);
+                return Err(format!("Couldn't rename {} to {}, none of the outputs were written: {}", temporary.display(), path.display(), e));
+            }
+            done.push((temporary, path, replaced));
+        }
+        for (_, _, replaced) in done {
+            if let Some(replaced) = replaced {
+                let _ = fs::remove_file(replaced);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Outputs that were never committed are removed
+impl Drop for Staging {
+    fn drop(&mut self) {
+        discard(&self.staged.lock().unwrap());
+    }
+}
+
+fn discard(staged: &[(PathBuf, PathBuf)]) {
+    for (temporary, _) in staged {
+        let _ = fs::remove_file(temporary);
+    }
+}
+
+// Single output written to "<name>.tmp" and renamed over its final name once complete, which
+// replaces an existing file in one step, so an interrupted command never leaves a truncated file
+// under the name of a valid archive. Without atomic the output is written in place, for
+// filesystems where renaming is costly.
+pub struct AtomicOutput {
+    filename: String, // Final name
+    temporary: Option<String>, // None unless atomic or once renamed
+}
+
+impl AtomicOutput {
+    pub fn new(filename: &str, atomic: bool) -> AtomicOutput {
+        let temporary = atomic.then(|| format!("{}.tmp", filename));
+        AtomicOutput { filename: filename.to_string(), temporary }
+    }
+
+    // Name to write the output to
+    pub fn filename(&self) -> &str {
+        self.temporary.as_deref().unwrap_or(&self.filename)
+    }
+
+    pub fn commit(mut self) -> Result<(), String> {
+        if let Some(temporary) = self.temporary.take() {
+            if let Err(e) = fs::rename(&temporary, &self.filename) {
+                let _ = fs::remove_file(&temporary);
+                return Err(format!("Couldn't rename {} to {}: {}", temporary, self.filename, e));
+            }
+        }
+        Ok(())
+    }
+}
+
+// An output that was never committed is removed
+impl Drop for AtomicOutput {
+    fn drop(&mut self) {
+        if let Some(temporary) = &self.temporary {
+            let _ = fs::remove_file(temporary);
+        }
+    }
+}
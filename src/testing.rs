@@ -0,0 +1,103 @@
+// Helpers for tests of crates embedding CmZIP: synthetic SD records, archives written to memory and
+// controlled damage to them, so reading code can be tested against real archives without fixture
+// files or running the cmzip binary.
+
+use std::{
+    convert::{TryFrom, TryInto},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering}
+};
+use crate::{Archive, Codec, Writer, archive};
+
+static OPENED: AtomicUsize = AtomicUsize::new(0); // Archives opened from memory, for unique file names
+
+// Well-formed V2000 SD record named name with a chain of atoms carbon atoms and the given data
+// items, which passes sdf::validate(). Panics if atoms is 0 or above 999.
+pub fn sd_record(name: &str, atoms: usize, tags: &[(&str, &str)]) -> Vec<u8> {
+    assert!((1..=999).contains(&atoms), "Records hold 1 to 999 atoms!");
+    let mut record = format!("{}\n  cmzip\n\n{:3}{:3}  0  0  0  0  0  0  0  0999 V2000\n", name, atoms, atoms - 1);
+    for i in 0..atoms {
+        record.push_str(&format!("{:10.4}{:10.4}{:10.4} C   0  0  0  0  0  0  0  0  0  0  0  0\n", i as f64 * 1.54, 0.0, 0.0));
+    }
+    for i in 1..atoms {
+        record.push_str(&format!("{:3}{:3}  1  0\n", i, i + 1));
+    }
+    record.push_str("M  END\n");
+    for (tag, value) in tags {
+        record.push_str(&format!("> <{}>\n{}\n\n", tag, value));
+    }
+    record.push_str("$$$$\n");
+    record.into_bytes()
+}
+
+// count records named MOL0, MOL1, ... of growing size, each with its position in an INDEX data item
+pub fn sd_records(count: usize) -> Vec<Vec<u8>> {
+    (0..count).map(|i| sd_record(&format!("MOL{}", i), 1 + i % 32, &[("INDEX", &i.to_string())])).collect()
+}
+
+// Bytes of an xz archive of records, at the fastest level
+pub fn archive_bytes<R: AsRef<[u8]>>(records: &[R]) -> io::Result<Vec<u8>> {
+    let mut writer = Writer::with_codec(Vec::new(), Codec::Xz, 0, None)?;
+    for record in records {
+        writer.add_record(record.as_ref())?;
+    }
+    writer.finish()
+}
+
+// Opens archive bytes. Archives are read from files, so the bytes go to a file in the temporary
+// directory that is removed again right away where the platform allows it.
+pub fn open(bytes: &[u8]) -> io::Result<Archive> {
+    let n = OPENED.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("cmzip-testing-{}-{}.cmz", std::process::id(), n));
+    OpenOptions::new().write(true).create_new(true).open(&path)?.write_all(bytes)?;
+    let file = File::open(&path);
+    let _ = fs::remove_file(&path); // Fails on Windows while the file is open
+    Archive::new(file?)
+}
+
+// Damage done by corrupt()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corruption {
+    FlipRecordByte(usize), // Flips a byte in the middle of compressed record i, failing its decompression
+    FlipIndexByte, // Flips a byte in the middle of the compressed index, failing to open the archive
+    IndexSize(u64), // Overwrites the compressed index size at the end of the file
+    Truncate(u64), // Cuts off the last n bytes, e.g. an interrupted copy, open_backup() may still succeed
+}
+
+// Damages archive bytes, fails with InvalidInput if the damage doesn't fit them, e.g. for a record
+// the archive doesn't hold
+pub fn corrupt(bytes: &mut Vec<u8>, corruption: Corruption) -> io::Result<()> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let len = bytes.len();
+    match corruption {
+        Corruption::FlipRecordByte(i) => {
+            let (_, index) = footer(bytes)?;
+            let start: u64 = index.iter().take(i + 1).sum();
+            let size = *index.get(i + 1).ok_or_else(|| invalid(format!("Record {} does not exist!", i)))?;
+            bytes[(start + size / 2) as usize] ^= 0xff;
+        },
+        Corruption::FlipIndexByte => {
+            let (index_start, _) = footer(bytes)?;
+            bytes[(index_start + len - 8) / 2] ^= 0xff;
+        },
+        Corruption::IndexSize(size) => {
+            let start = len.checked_sub(8).ok_or_else(|| invalid("Archive is too short to hold a file footer!".to_string()))?;
+            bytes[start..].copy_from_slice(&size.to_le_bytes());
+        },
+        Corruption::Truncate(n) => {
+            let n = usize::try_from(n).ok().filter(|&n| n <= len).ok_or_else(|| invalid(format!("Archive is shorter than {} bytes!", n)))?;
+            bytes.truncate(len - n);
+        },
+    }
+    Ok(())
+}
+
+// Start of the compressed index and the decoded index of archive bytes
+fn footer(bytes: &[u8]) -> io::Result<(usize, Vec<u64>)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Archive index is corrupt!");
+    let end = bytes.len().checked_sub(8).ok_or_else(invalid)?;
+    let index_size = u64::from_le_bytes(bytes[end..].try_into().unwrap());
+    let start = usize::try_from(index_size).ok().and_then(|size| end.checked_sub(size)).ok_or_else(invalid)?;
+    Ok((start, archive::decode_index(&bytes[start..end], bytes.len() as u64 + 1)?))
+}
@@ -0,0 +1,132 @@
+// Hooks for changing record contents on their way into and out of an archive, e.g. normalizing
+// line endings, watermarking or redacting fields, without touching the archive logic. Transforms
+// are attached to a Writer, Archive or AsyncArchive with with_transform() and run in the order
+// they were attached. Compressed records copied with Writer::add_compressed() or read with
+// Archive::read_raw() are passed through unchanged.
+
+use std::{
+    convert::TryInto,
+    io
+};
+use sha2::{Digest, Sha256};
+use crate::record::Record;
+
+pub const WATERMARK_TAG: &str = "CMZIP_WATERMARK";
+
+pub trait RecordTransform: Send + Sync {
+    // Called with every record given to Writer::add_record(), before it is compressed
+    fn pre_compress(&self, record: Vec<u8>) -> io::Result<Vec<u8>> {
+        Ok(record)
+    }
+
+    // Called with every record decompressed by an archive reader, before it is returned
+    fn post_decompress(&self, record: Vec<u8>) -> io::Result<Vec<u8>> {
+        Ok(record)
+    }
+}
+
+// Removes the given SD data tags from every record before it is compressed, see Record::without_tags()
+pub struct DropTags {
+    names: Vec<String>,
+}
+
+impl DropTags {
+    pub fn new<S: Into<String>, I: IntoIterator<Item = S>>(names: I) -> DropTags {
+        DropTags { names: names.into_iter().map(Into::into).collect() }
+    }
+}
+
+impl RecordTransform for DropTags {
+    fn pre_compress(&self, record: Vec<u8>) -> io::Result<Vec<u8>> {
+        Ok(Record::new(record).without_tags(&self.names).into_bytes())
+    }
+}
+
+// Marks a pseudo-random share of records with the WATERMARK_TAG data item, so a leaked copy of an
+// archive can be traced to its recipient. Whether a record is marked only depends on the recipient
+// and the record contents, and the tag value is a digest of the recipient, so the same records of
+// every copy for one recipient carry the same mark.
+pub struct Watermark {
+    mark: String,
+    threshold: u64, // Records whose selection hash is below it are marked
+}
+
+impl Watermark {
+    // rate is the share of records to mark, between 0 and 1
+    pub fn new(recipient: &str, rate: f64) -> Watermark {
+        let threshold = (rate.clamp(0.0, 1.0) * u64::MAX as f64) as u64;
+        Watermark { mark: Watermark::mark(recipient), threshold }
+    }
+
+    // Tag value of the records marked for recipient
+    pub fn mark(recipient: &str) -> String {
+        let digest = Sha256::digest(format!("cmzip watermark\n{}", recipient).as_bytes());
+        digest.iter().take(16).map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn is_selected(&self, record: &[u8]) -> bool {
+        let mut hasher = Sha256::new();
+        hasher.update(self.mark.as_bytes());
+        hasher.update(record);
+        u64::from_le_bytes(hasher.finalize()[..8].try_into().unwrap()) < self.threshold
+    }
+}
+
+impl RecordTransform for Watermark {
+    fn pre_compress(&self, record: Vec<u8>) -> io::Result<Vec<u8>> {
+        if !self.is_selected(&record) {
+            return Ok(record);
+        }
+        Ok(Record::new(record).with_tag(WATERMARK_TAG, &self.mark).into_bytes())
+    }
+}
+
+// Converts CRLF line endings to LF before compression, so archives of SD files written on Windows
+// hold the same bytes as those of their Unix copies
+pub struct NormalizeNewlines;
+
+impl NormalizeNewlines {
+    pub fn to_lf(record: &[u8]) -> Vec<u8> {
+        let mut converted: Vec<u8> = Vec::with_capacity(record.len());
+        for (i, &b) in record.iter().enumerate() {
+            if b != b'\r' || record.get(i + 1) != Some(&b'\n') {
+                converted.push(b);
+            }
+        }
+        converted
+    }
+}
+
+impl RecordTransform for NormalizeNewlines {
+    fn pre_compress(&self, record: Vec<u8>) -> io::Result<Vec<u8>> {
+        if !record.contains(&b'\r') {
+            return Ok(record);
+        }
+        Ok(NormalizeNewlines::to_lf(&record))
+    }
+}
+
+// Converts LF line endings to CRLF after decompression, for tools that expect Windows line
+// endings. Lines already ending with CRLF are left alone.
+pub struct CrlfNewlines;
+
+impl RecordTransform for CrlfNewlines {
+    fn post_decompress(&self, record: Vec<u8>) -> io::Result<Vec<u8>> {
+        let mut converted: Vec<u8> = Vec::with_capacity(record.len() + record.len() / 32);
+        for (i, &b) in record.iter().enumerate() {
+            if b == b'\n' && (i == 0 || record[i - 1] != b'\r') {
+                converted.push(b'\r');
+            }
+            converted.push(b);
+        }
+        Ok(converted)
+    }
+}
+
+pub(crate) fn pre_compress(transforms: &[Box<dyn RecordTransform>], record: &[u8]) -> io::Result<Vec<u8>> {
+    transforms.iter().try_fold(record.to_vec(), |record, transform| transform.pre_compress(record))
+}
+
+pub(crate) fn post_decompress(transforms: &[Box<dyn RecordTransform>], record: Vec<u8>) -> io::Result<Vec<u8>> {
+    transforms.iter().try_fold(record, |record, transform| transform.post_decompress(record))
+}
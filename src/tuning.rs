@@ -0,0 +1,85 @@
+// Measurements of codecs and levels on a sample of records, for cmzip bench and zip --level auto.
+// Records are compressed one at a time with one encoder, as zip does. The auto level is the one
+// with the best ratio that still compresses the target number of MB (10^6 bytes) of records per
+// second on the threads compressing: levels are tried from the fastest up until one misses the
+// target, since higher levels only get slower.
+
+use std::{
+    io,
+    time::{Duration, Instant}
+};
+use cmzip::{Codec, Dictionary, codec::{Decoder, Encoder}};
+
+pub struct Measurement {
+    pub level: u32,
+    pub size: u64, // Bytes of the records
+    pub compressed_size: u64,
+    pub compress_time: Duration,
+    pub decompress_time: Duration,
+    pub memory: u64, // Bytes used by the encoder
+}
+
+impl Measurement {
+    pub fn ratio(&self) -> f64 {
+        self.size as f64 / self.compressed_size.max(1) as f64
+    }
+
+    // Bytes of records compressed per second on one thread
+    pub fn compress_speed(&self) -> f64 {
+        self.size as f64 / self.compress_time.as_secs_f64().max(1e-9)
+    }
+
+    pub fn decompress_speed(&self) -> f64 {
+        self.size as f64 / self.decompress_time.as_secs_f64().max(1e-9)
+    }
+}
+
+pub fn measure(mut encoder: Encoder, dictionary: Option<&Dictionary>, records: &[Vec<u8>]) -> io::Result<Measurement> {
+    let started = Instant::now();
+    let mut compressed: Vec<Vec<u8>> = Vec::with_capacity(records.len());
+    for record in records {
+        let mut compressed_data: Vec<u8> = Vec::new();
+        encoder.compress(record, &mut compressed_data)?;
+        compressed.push(compressed_data);
+    }
+    let compress_time = started.elapsed();
+    let decoder = Decoder::new(encoder.codec(), dictionary);
+    let started = Instant::now();
+    for compressed_data in &compressed {
+        decoder.decompress(compressed_data, &mut io::sink())?;
+    }
+    Ok(Measurement {
+        level: encoder.level(),
+        size: records.iter().map(|record| record.len() as u64).sum(),
+        compressed_size: compressed.iter().map(|compressed_data| compressed_data.len() as u64).sum(),
+        compress_time,
+        decompress_time: started.elapsed(),
+        memory: encoder.memory_usage(),
+    })
+}
+
+// Level of codec with the best ratio compressing at least target_mbps MB/s on threads threads,
+// the fastest level if none does, measured with the encoders new_encoder makes for each level
+pub fn auto_level(codec: Codec, new_encoder: &dyn Fn(u32) -> io::Result<Encoder>, dictionary: Option<&Dictionary>, records: &[Vec<u8>], target_mbps: f64, threads: usize) -> io::Result<Measurement> {
+    let meets_target = |measurement: &Measurement| measurement.compress_speed() * threads as f64 >= target_mbps * 1e6;
+    let mut best: Option<Measurement> = None;
+    for level in min_level(codec)..=codec.max_level() {
+        let measurement = measure(new_encoder(level)?, dictionary, records)?;
+        log::debug!("Level {}: ratio {:.2}, {:.1} MB/s", level, measurement.ratio(), measurement.compress_speed() * threads as f64 / 1e6);
+        if !meets_target(&measurement) {
+            return Ok(best.unwrap_or(measurement));
+        }
+        if best.as_ref().is_none_or(|best| measurement.ratio() > best.ratio()) {
+            best = Some(measurement);
+        }
+    }
+    Ok(best.unwrap())
+}
+
+// Fastest level worth trying, zstd level 0 means its default level 3
+fn min_level(codec: Codec) -> u32 {
+    match codec {
+        Codec::Xz => 0,
+        Codec::Zstd => 1,
+    }
+}
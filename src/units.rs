@@ -0,0 +1,99 @@
+// Parsing of human friendly command line values, shared by every flag that takes a size, a share
+// of the input or a duration. Sizes accept decimal (kB, MB, GB, TB) and binary (KiB, MiB, GiB, TiB) suffixes, a
+// bare K, M, G or T is binary as well, so "4G" and "4GiB" are the same size.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Amount {
+    Count(u64),
+    Fraction(f64), // Share of the whole, between 0 and 1
+}
+
+// Parses sizes like "112640", "4MiB", "1.5G" or "500kB" into bytes
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid size {}, expected a number of bytes like 4MiB or 500kB!", value);
+    let (number, suffix) = split_number(value.trim());
+    let multiplier: u64 = match suffix.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => return Err(invalid()),
+    };
+    // Whole numbers are kept exact, fractional ones are rounded to the nearest byte
+    if let Ok(number) = number.parse::<u64>() {
+        return number.checked_mul(multiplier).ok_or_else(invalid);
+    }
+    match number.parse::<f64>() {
+        Ok(number) if number.is_finite() && number >= 0.0 && number * (multiplier as f64) < u64::MAX as f64 => {
+            Ok((number * multiplier as f64).round() as u64)
+        },
+        _ => Err(invalid()),
+    }
+}
+
+// Parses percentages like "5%" or "0.5%" into a fraction between 0 and 1
+pub fn parse_percent(value: &str) -> Result<f64, String> {
+    let invalid = || format!("Invalid percentage {}, expected a value like 5%!", value);
+    let number = value.trim().strip_suffix('%').ok_or_else(invalid)?;
+    match number.trim().parse::<f64>() {
+        Ok(number) if (0.0..=100.0).contains(&number) => Ok(number / 100.0),
+        _ => Err(invalid()),
+    }
+}
+
+// Parses durations like "365d", "12h" or "90s" into seconds
+pub fn parse_duration(value: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid duration {}, expected a number of days like 365d, or of s, m, h, w or y!", value);
+    let (number, suffix) = split_number(value.trim());
+    let multiplier: u64 = match suffix {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    number.parse::<u64>().ok().and_then(|number| number.checked_mul(multiplier)).ok_or_else(invalid)
+}
+
+// Parses either a count like "10000" or a percentage like "1%" of some total
+pub fn parse_amount(value: &str) -> Result<Amount, String> {
+    if value.trim().ends_with('%') {
+        parse_percent(value).map(Amount::Fraction)
+    } else {
+        value.trim().parse::<u64>()
+            .map(Amount::Count)
+            .map_err(|_| format!("Invalid amount {}, expected a count like 10000 or a percentage like 1%!", value))
+    }
+}
+
+// Formats bytes with the largest binary suffix that keeps the number at least 1, e.g. "1.5 GiB"
+pub fn format_size(bytes: u64) -> String {
+    const SUFFIXES: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1 << 10 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut suffix = "B";
+    for next in SUFFIXES {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        suffix = next;
+    }
+    format!("{:.1} {}", value, suffix)
+}
+
+// Splits "4.5MiB" into "4.5" and "MiB"
+fn split_number(value: &str) -> (&str, &str) {
+    let end = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (number, suffix) = value.split_at(end);
+    (number, suffix.trim())
+}
@@ -0,0 +1,492 @@
+// Write access to CmZ archives: record compression and the file footer
+
+use std::{
+    fs::File,
+    io::{
+        self,
+        SeekFrom,
+        prelude::*
+    },
+    ops::Range,
+    path::Path
+};
+use xz2::read::XzEncoder;
+use crate::{
+    archive::{self, Archive, IndexFormat},
+    backup,
+    codec::{Codec, Encoder},
+    crypto::{self, Block, Key},
+    dictionary::Dictionary,
+    health::{self, Health},
+    intent::{self, Intent},
+    metadata::{self, Metadata},
+    transform::{self, RecordTransform},
+    xz
+};
+
+pub struct Writer<W: Write> {
+    output: W,
+    encoder: Encoder,
+    metadata: Metadata, // Written to the file footer by finish()
+    index: Vec<u64>, // Holds the sizes of each created record, used for calculating offsets at decompression
+    compressed_data: Vec<u8>, // Cleared each record, used for compression
+    transforms: Vec<Box<dyn RecordTransform>>, // Applied to every record before compression
+    size_limit: Option<u64>, // Largest allowed archive size, footer included
+    records_end: u64, // Sum of the index, where the footer will start
+    uncompressed_size: Option<u64>, // Total size of the records decompressed, None once unknown
+    index_format: IndexFormat,
+    intent: Option<Intent>, // Of an archive reopened in place, finished with the footer
+    original: Vec<u64>, // Index of an archive reopened in place as it was before, empty otherwise
+    key: Option<Key>, // Records and footer are sealed with, see crypto.rs
+}
+
+impl<W: Write> Writer<W> {
+    // Starts a new xz archive with records compressed at level (0 - 9)
+    pub fn new(output: W, level: u32) -> Writer<W> {
+        Writer::from_encoder(output, Encoder::xz(level), None)
+    }
+
+    // Starts a new archive with the given codec. Archives compressed with a dictionary only
+    // reference it by id and digest, use embed_dictionary() to store the dictionary itself.
+    pub fn with_codec(output: W, codec: Codec, level: u32, dictionary: Option<&Dictionary>) -> io::Result<Writer<W>> {
+        Ok(Writer::from_encoder(output, Encoder::new(codec, level, dictionary)?, dictionary))
+    }
+
+    // Continues an archive whose footer was never written, e.g. by an interrupted compression,
+    // holding records of the given compressed sizes. output has to be positioned after them.
+    pub fn resume(output: W, codec: Codec, level: u32, dictionary: Option<&Dictionary>, sizes: &[u64]) -> io::Result<Writer<W>> {
+        let mut writer = Writer::with_codec(output, codec, level, dictionary)?;
+        writer.index.extend_from_slice(sizes);
+        writer.records_end = sizes.iter().sum();
+        writer.uncompressed_size = None;
+        Ok(writer)
+    }
+
+    fn from_encoder(output: W, encoder: Encoder, dictionary: Option<&Dictionary>) -> Writer<W> {
+        let mut metadata = Metadata::new();
+        metadata.set("codec", encoder.codec().name());
+        metadata.set("level", encoder.level().to_string());
+        metadata.set("encoder", format!("cmzip {}", env!("CARGO_PKG_VERSION")));
+        metadata.set("codec_version", encoder.codec().library_version());
+        metadata.set("parameters", encoder.parameters());
+        if let Some(preset) = encoder.preset() {
+            metadata.set("preset", preset);
+        }
+        if let Some(dictionary) = dictionary {
+            metadata.set("dictionary_id", dictionary.id().to_string());
+            metadata.set("dictionary_sha256", dictionary.digest());
+        }
+        Writer {
+            output, encoder, metadata, index: vec![0], compressed_data: Vec::new(), transforms: Vec::new(),
+            size_limit: None, records_end: 0, uncompressed_size: Some(0), index_format: IndexFormat::Varint, intent: None,
+            original: Vec::new(), key: None,
+        }
+    }
+
+    // Starts a new archive with the codec, level and metadata of an existing one, so its compressed
+    // records can be copied over with add_compressed(). Archives referencing a dictionary they
+    // don't embed need it supplied.
+    // Encrypted archives give an archive encrypted with the same key.
+    pub fn with_settings_of(output: W, archive: &Archive, dictionary: Option<&Dictionary>) -> io::Result<Writer<W>> {
+        let mut writer = Writer::settings_of(output, archive, dictionary)?;
+        if let Some(key) = archive.key() {
+            writer.encrypt(key.clone())?;
+        }
+        Ok(writer)
+    }
+
+    fn settings_of(output: W, archive: &Archive, dictionary: Option<&Dictionary>) -> io::Result<Writer<W>> {
+        let dictionary = archive.embedded_dictionary().or_else(|| dictionary.cloned());
+        if archive.metadata().get("dictionary_sha256").is_some() && dictionary.is_none() {
+            return Err(archive::missing_dictionary(archive.metadata()));
+        }
+        let level = archive.metadata().get_str("level").and_then(|level| level.parse::<u32>().ok()).unwrap_or(6);
+        let mut encoder = Encoder::new(archive.codec(), level, dictionary.as_ref())?;
+        encoder.set_extreme(archive.metadata().get_str("preset").is_some_and(|preset| preset.ends_with('e')))?;
+        let options = archive.metadata().get_str("xz_options").map(str::parse::<xz::Options>).transpose()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        encoder.set_xz_options(options)?;
+        let mut writer = Writer::from_encoder(output, encoder, None);
+        writer.metadata = archive.metadata().clone();
+        Ok(writer)
+    }
+
+    // Runs transform on every record added with add_record() from now on, after the transforms
+    // attached before
+    pub fn with_transform<T: RecordTransform + 'static>(mut self, transform: T) -> Writer<W> {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    // Encrypts the records and the footer with key, which has to be set before the first record is
+    // added. Writes the encryption header.
+    pub fn encrypt(&mut self, key: Key) -> io::Result<()> {
+        if self.records_end > 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Archives can only be encrypted before their first record!"));
+        }
+        self.output.write_all(key.header())?;
+        self.index[0] = crypto::HEADER_SIZE;
+        self.records_end = crypto::HEADER_SIZE;
+        self.metadata.set("encryption", crypto::ALGORITHM);
+        self.key = Some(key);
+        Ok(())
+    }
+
+    // Stores the dictionary in the archive, so it can be read without supplying it
+    pub fn embed_dictionary(&mut self, dictionary: &Dictionary) {
+        self.metadata.set("dictionary", dictionary.as_bytes());
+    }
+
+    // Key set by encrypt(), records are sealed with it
+    pub fn key(&self) -> Option<&Key> {
+        self.key.as_ref()
+    }
+
+    pub fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
+    }
+
+    // Caps the size of the finished archive. Records that would make the archive with its footer
+    // larger are rejected with ErrorKind::FileTooLarge before anything is written, so finish()
+    // still produces a valid archive of the records added so far.
+    pub fn set_size_limit(&mut self, limit: u64) {
+        self.size_limit = Some(limit);
+    }
+
+    // Sets the layout of the index written by finish(), IndexFormat::Varint unless changed. Readers
+    // older than the varint index need IndexFormat::Fixed.
+    pub fn set_index_format(&mut self, format: IndexFormat) {
+        self.index_format = format;
+    }
+
+    // Compresses the records added from now on with the extreme xz preset, see Encoder::set_extreme()
+    pub fn set_extreme(&mut self, extreme: bool) -> io::Result<()> {
+        self.encoder.set_extreme(extreme)?;
+        self.metadata.set("parameters", self.encoder.parameters());
+        if let Some(preset) = self.encoder.preset() {
+            self.metadata.set("preset", preset);
+        }
+        Ok(())
+    }
+
+    // Compresses the records added from now on with a custom xz filter chain, see xz.rs. The
+    // options are stored in the metadata as xz_options.
+    pub fn set_xz_options(&mut self, options: Option<xz::Options>) -> io::Result<()> {
+        self.encoder.set_xz_options(options)?;
+        match self.encoder.xz_options() {
+            Some(options) => self.metadata.set("xz_options", options.to_string()),
+            None => {
+                self.metadata.remove("xz_options");
+            },
+        }
+        self.metadata.set("parameters", self.encoder.parameters());
+        Ok(())
+    }
+
+    // Bytes of records written so far, without the file footer
+    pub fn records_size(&self) -> u64 {
+        self.records_end
+    }
+
+    // Upper bound of the footer size finish() writes
+    pub fn footer_size_bound(&self) -> u64 {
+        self.footer_bound(self.index.len())
+    }
+
+    // Upper bound of the footer size for an index of the given number of entries
+    fn footer_bound(&self, index_entries: usize) -> u64 {
+        // finish() sets the records, uncompressed_size and health entries
+        let entries = ["records", "uncompressed_size"].iter().map(|key| 16 + key.len() + u64::MAX.to_string().len()).sum::<usize>()
+            + 16 + health::KEY.len() + Health::encoded_bound();
+        let mut metadata_bound = (self.metadata.encoded_len() + entries) as u64;
+        let mut index_bound = unsafe { lzma_sys::lzma_stream_buffer_bound(self.index_format.max_size(index_entries)) } as u64;
+        if self.key.is_some() {
+            index_bound += crypto::OVERHEAD;
+            metadata_bound += crypto::OVERHEAD;
+        }
+        backup::size(metadata_bound, index_bound) + metadata_bound + 16 + index_bound + 8
+    }
+
+    fn check_size_limit(&self, compressed_size: usize) -> io::Result<()> {
+        if let Some(limit) = self.size_limit {
+            // The record adds an index entry to the footer
+            if self.records_size() + compressed_size as u64 + self.footer_bound(self.index.len() + 1) > limit {
+                return Err(io::Error::new(io::ErrorKind::FileTooLarge, format!("Archive would exceed its size limit of {} bytes!", limit)));
+            }
+        }
+        Ok(())
+    }
+
+    // Compresses a record and appends it to the archive
+    pub fn add_record(&mut self, record: &[u8]) -> io::Result<()> {
+        self.compressed_data.clear();
+        let size = if self.transforms.is_empty() {
+            self.encoder.compress(record, &mut self.compressed_data)?;
+            record.len()
+        } else {
+            let record = transform::pre_compress(&self.transforms, record)?;
+            self.encoder.compress(&record, &mut self.compressed_data)?;
+            record.len()
+        };
+        if let Some(key) = &self.key {
+            self.compressed_data = key.seal(&self.compressed_data, Block::Record(self.len()))?;
+        }
+        self.check_size_limit(self.compressed_data.len())?;
+        self.uncompressed_size = self.uncompressed_size.map(|total| total + size as u64);
+        self.output.write_all(&self.compressed_data)?;
+        self.index.push(self.compressed_data.len() as u64); // Update index
+        self.records_end += self.compressed_data.len() as u64;
+        Ok(())
+    }
+
+    // Appends a record that was already compressed by an Encoder with the same settings, e.g. as
+    // Archive::read_raw() returns it. Encrypted archives seal it here.
+    pub fn add_compressed(&mut self, compressed_data: &[u8]) -> io::Result<()> {
+        self.write_compressed(compressed_data, None)
+    }
+
+    // Same as add_compressed(), for records whose decompressed size is known
+    pub fn add_compressed_with_size(&mut self, compressed_data: &[u8], uncompressed_size: u64) -> io::Result<()> {
+        self.write_compressed(compressed_data, Some(uncompressed_size))
+    }
+
+    fn write_compressed(&mut self, compressed_data: &[u8], uncompressed_size: Option<u64>) -> io::Result<()> {
+        let sealed;
+        let compressed_data = match &self.key {
+            Some(key) => {
+                sealed = key.seal(compressed_data, Block::Record(self.len()))?;
+                &sealed[..]
+            },
+            None => compressed_data,
+        };
+        self.check_size_limit(compressed_data.len())?;
+        self.uncompressed_size = self.uncompressed_size.zip(uncompressed_size).map(|(total, size)| total + size);
+        self.output.write_all(compressed_data)?;
+        self.index.push(compressed_data.len() as u64); // Update index
+        self.records_end += compressed_data.len() as u64;
+        Ok(())
+    }
+
+    // Compressed size of every record written so far
+    pub fn record_sizes(&self) -> &[u64] {
+        &self.index[1..]
+    }
+
+    // Number of records written so far
+    pub fn len(&self) -> usize {
+        self.index.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    // Writes the file footer and returns the underlying output
+    pub fn finish(mut self) -> io::Result<W> {
+        self.metadata.set("records", self.len().to_string());
+        // Lets readers check for disk space before extracting, unknown once compressed records were copied in
+        match self.uncompressed_size {
+            Some(size) => self.metadata.set("uncompressed_size", size.to_string()),
+            None => {
+                self.metadata.remove("uncompressed_size");
+            },
+        }
+        self.metadata.set(health::KEY, Health::of(&self.index).encode());
+        let mut metadata = self.metadata.encode();
+
+        let data = archive::encode_index(&self.index, self.index_format);
+        self.compressed_data.clear();
+        compress(&data, &mut self.compressed_data, 9)?;
+        if let Some(key) = &self.key {
+            metadata = key.seal(&metadata, Block::Metadata)?;
+            self.compressed_data = key.seal(&self.compressed_data, Block::Index)?;
+        }
+
+        // The backup block follows the last record, see backup.rs
+        self.output.write_all(&backup::encode(&metadata, &self.compressed_data))?;
+
+        // Metadata goes first, its size and magic directly precede the index
+        self.output.write_all(&metadata)?;
+        self.output.write_all(&(metadata.len() as u64).to_le_bytes())?;
+        self.output.write_all(&metadata::MAGIC)?;
+
+        // Calculate size of compressed index for easier extraction
+        let size = (self.compressed_data.len() as u64).to_le_bytes();
+
+        // Write file footer
+        self.output.write_all(&self.compressed_data)?;
+        self.output.write_all(&size)?;
+        self.output.flush()?;
+        if let Some(intent) = self.intent.take() {
+            intent.finish()?;
+        }
+        Ok(self.output)
+    }
+}
+
+impl Writer<File> {
+    // Reopens the archive at path to add more records with its codec, level and metadata. The
+    // footer is cut off here and written again by finish(), the bytes overwritten are kept in an
+    // intent record until then (see intent.rs). Archives referencing a dictionary they don't embed
+    // need it supplied.
+    pub fn append<P: AsRef<Path>>(path: P, dictionary: Option<&Dictionary>) -> io::Result<Writer<File>> {
+        Writer::reopen(path.as_ref(), None, dictionary)
+    }
+
+    // Like append(), but keeps only the first records records, new ones are added after them
+    pub fn truncate<P: AsRef<Path>>(path: P, records: usize, dictionary: Option<&Dictionary>) -> io::Result<Writer<File>> {
+        Writer::reopen(path.as_ref(), Some(records), dictionary)
+    }
+
+    // Appends every record of archive as it is stored, which needs an archive of the same codec and
+    // dictionary. The records are copied in one run, with copy_file_range() on Linux so they don't
+    // pass through user space, and only their index entries are kept in memory. Records of
+    // encrypted archives are bound to their index, unless they keep it they are sealed again one
+    // at a time.
+    pub fn copy_records(&mut self, archive: &Archive) -> io::Result<()> {
+        let records = archive.len();
+        if records == 0 {
+            return Ok(());
+        }
+        if archive.key().map(Key::header) != self.key.as_ref().map(Key::header) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Records can only be copied between archives encrypted with the same key!"));
+        }
+        let start = archive.record_range(0).unwrap().start;
+        archive.check_present(records - 1, &archive.record_range(records - 1).unwrap())?;
+        let length = archive.records_end() - start;
+        if let Some(limit) = self.size_limit {
+            if self.records_size() + length + self.footer_bound(self.index.len() + records) > limit {
+                return Err(io::Error::new(io::ErrorKind::FileTooLarge, format!("Archive would exceed its size limit of {} bytes!", limit)));
+            }
+        }
+        let size = archive.metadata().get_str("uncompressed_size").and_then(|size| size.parse::<u64>().ok());
+        let uncompressed_size = self.uncompressed_size.zip(size).map(|(total, size)| total + size);
+        if self.key.is_some() && !self.is_empty() {
+            for i in 0..records {
+                self.add_compressed(&archive.read_raw(i)?)?;
+            }
+        } else {
+            copy_range(archive.file(), start, length, &mut self.output)?;
+            self.index.extend_from_slice(&archive.index()[1..]);
+            self.records_end += length;
+        }
+        self.uncompressed_size = uncompressed_size;
+        Ok(())
+    }
+
+    // Appends records of the archive as it was before truncate() cut them off, read back from its
+    // intent record, so the records after the ones kept don't have to be held in memory. Runs of
+    // records are copied as stored, records of encrypted archives are sealed again one at a time
+    // if they move to another index.
+    pub fn copy_original(&mut self, records: Range<usize>) -> io::Result<()> {
+        if records.end >= self.original.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Archive only held {} records!", self.original.len().saturating_sub(1))));
+        }
+        let start: u64 = self.original[..=records.start].iter().sum();
+        let sizes = self.original[records.start + 1..=records.end].to_vec();
+        let length: u64 = sizes.iter().sum();
+        if let Some(limit) = self.size_limit {
+            if self.records_size() + length + self.footer_bound(self.index.len() + records.len()) > limit {
+                return Err(io::Error::new(io::ErrorKind::FileTooLarge, format!("Archive would exceed its size limit of {} bytes!", limit)));
+            }
+        }
+        // Put back below, add_compressed() needs the writer while the intent record is read
+        let intent = self.intent.take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Only archives reopened with truncate() hold original records!"))?;
+        let result = intent.saved(start).and_then(|(file, position)| match self.key.clone() {
+            Some(key) if records.start != self.len() => {
+                let mut offset = position;
+                for (i, &size) in records.zip(&sizes) {
+                    let mut sealed = vec![0u8; size as usize];
+                    archive::read_exact_at(file, &mut sealed, offset)?;
+                    self.add_compressed(&key.open(&sealed, Block::Record(i))?)?;
+                    offset += size;
+                }
+                Ok(())
+            },
+            _ => {
+                copy_range(file, position, length, &mut self.output)?;
+                self.index.extend_from_slice(&sizes);
+                self.records_end += length;
+                self.uncompressed_size = None;
+                Ok(())
+            },
+        });
+        self.intent = Some(intent);
+        result
+    }
+
+    fn reopen(path: &Path, records: Option<usize>, dictionary: Option<&Dictionary>) -> io::Result<Writer<File>> {
+        let file = intent::open(path)?;
+        let mut archive = Archive::open(path)?;
+        if let (Some(dictionary), None) = (dictionary, archive.embedded_dictionary()) {
+            archive.set_dictionary(dictionary)?;
+        }
+        archive.check_readable()?;
+        let records = records.unwrap_or(archive.len());
+        if records > archive.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Archive only holds {} records!", archive.len())));
+        }
+
+        // The encryption header stays where it is
+        let mut writer = Writer::settings_of(file, &archive, dictionary)?;
+        writer.key = archive.key().cloned();
+        writer.index = archive.index()[..=records].to_vec();
+        writer.original = archive.index().to_vec();
+        writer.records_end = writer.index.iter().sum();
+        writer.uncompressed_size = None;
+        writer.intent = Some(Intent::begin(path, &writer.output, writer.records_end)?);
+        writer.output.set_len(writer.records_end)?;
+        writer.output.seek(SeekFrom::Start(writer.records_end))?;
+        Ok(writer)
+    }
+}
+
+// Copies length bytes of input starting at offset to the file position of output
+fn copy_range(input: &File, offset: u64, length: u64, output: &mut File) -> io::Result<()> {
+    let mut copied = 0;
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        while copied < length {
+            let mut from = (offset + copied) as libc::loff_t;
+            let chunk = (length - copied).min(1 << 30) as usize;
+            let n = unsafe { libc::copy_file_range(input.as_raw_fd(), &mut from, output.as_raw_fd(), std::ptr::null_mut(), chunk, 0) };
+            if n > 0 {
+                copied += n as u64;
+                continue;
+            }
+            let e = match n {
+                0 => io::Error::new(io::ErrorKind::UnexpectedEof, "Archive ends before its last record!"),
+                _ => io::Error::last_os_error(),
+            };
+            match e.raw_os_error() {
+                Some(libc::EINTR) => {},
+                // Filesystems that can't copy between each other, the rest is copied below
+                Some(libc::EXDEV) | Some(libc::ENOSYS) | Some(libc::EINVAL) | Some(libc::EOPNOTSUPP) => break,
+                _ => return Err(e),
+            }
+        }
+    }
+    // Elsewhere in large sequential chunks
+    let mut buffer: Vec<u8> = vec![0u8; (length - copied).min(COPY_CHUNK) as usize];
+    while copied < length {
+        let n = (length - copied).min(COPY_CHUNK) as usize;
+        archive::read_exact_at(input, &mut buffer[..n], offset + copied)?;
+        output.write_all(&buffer[..n])?;
+        copied += n as u64;
+    }
+    Ok(())
+}
+
+const COPY_CHUNK: u64 = 8 << 20;
+
+// Compresses input_buffer with xz at the given level, appending to output_buffer
+pub fn compress(input_buffer: &[u8], output_buffer: &mut Vec<u8>, level: u32) -> io::Result<usize> {
+    let mut compressor = XzEncoder::new(input_buffer, level);
+    compressor.read_to_end(output_buffer)
+}
@@ -0,0 +1,161 @@
+// Custom xz filter chains for advanced users, e.g. "dict=64MiB,lc=4" or "delta=4,pb=0": options
+// of the LZMA2 filter, applied on top of the preset of the level, and an optional delta filter in
+// front of it. The keys follow xz --lzma2 and --delta: dict (4KiB - 1536MiB), lc, lp and pb
+// (0 - 4, lc + lp at most 4), mode (fast or normal), nice (2 - 273), mf (hc3, hc4, bt2, bt3 or
+// bt4), depth and delta (byte distance 1 - 256). Records are still standard xz streams whose block
+// headers describe their filter chain, so any xz decoder reads them without knowing the options,
+// but decompression needs about as much memory as the dictionary.
+
+use std::{
+    convert::TryFrom,
+    fmt,
+    io,
+    os::raw::c_void,
+    ptr,
+    str::FromStr
+};
+use lzma_sys::{lzma_filter, lzma_match_finder, lzma_mode, lzma_options_lzma};
+use crate::units;
+
+const LZMA_FILTER_DELTA: lzma_sys::lzma_vli = 0x03;
+const LZMA_DELTA_TYPE_BYTE: u32 = 0;
+const MATCH_FINDERS: [(&str, lzma_match_finder); 5] = [
+    ("hc3", lzma_sys::LZMA_MF_HC3), ("hc4", lzma_sys::LZMA_MF_HC4),
+    ("bt2", lzma_sys::LZMA_MF_BT2), ("bt3", lzma_sys::LZMA_MF_BT3), ("bt4", lzma_sys::LZMA_MF_BT4),
+];
+const MODES: [(&str, lzma_mode); 2] = [("fast", lzma_sys::LZMA_MODE_FAST), ("normal", lzma_sys::LZMA_MODE_NORMAL)];
+
+// lzma_options_delta of liblzma, which lzma-sys doesn't declare
+#[repr(C)]
+struct DeltaOptions {
+    kind: u32, // lzma_delta_type, an enum of C int size
+    dist: u32,
+    reserved_int: [u32; 8],
+    reserved_ptr: [*mut c_void; 2],
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    pub delta: Option<u32>,
+    pub dict_size: Option<u32>,
+    pub lc: Option<u32>,
+    pub lp: Option<u32>,
+    pub pb: Option<u32>,
+    pub mode: Option<&'static str>,
+    pub nice_len: Option<u32>,
+    pub match_finder: Option<&'static str>,
+    pub depth: Option<u32>,
+}
+
+impl FromStr for Options {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Options, String> {
+        let mut options = Options::default();
+        for option in s.split(',').map(str::trim).filter(|option| !option.is_empty()) {
+            let (key, value) = option.split_once('=').ok_or(format!("xz option {} has no value, expected KEY=VALUE!", option))?;
+            let number = |min: u32, max: u32| value.parse::<u32>().ok().filter(|n| (min..=max).contains(n))
+                .ok_or(format!("xz option {} is invalid, expected {} - {}!", option, min, max));
+            match key {
+                "delta" => options.delta = Some(number(1, 256)?),
+                "dict" => options.dict_size = Some(units::parse_size(value).ok().and_then(|size| u32::try_from(size).ok()).filter(|size| (4 << 10..=1536 << 20).contains(size))
+                    .ok_or(format!("xz option {} is invalid, expected 4KiB - 1536MiB!", option))?),
+                "lc" => options.lc = Some(number(0, 4)?),
+                "lp" => options.lp = Some(number(0, 4)?),
+                "pb" => options.pb = Some(number(0, 4)?),
+                "mode" => options.mode = Some(named(&MODES, option, value)?),
+                "nice" => options.nice_len = Some(number(2, 273)?),
+                "mf" => options.match_finder = Some(named(&MATCH_FINDERS, option, value)?),
+                "depth" => options.depth = Some(number(0, u32::MAX)?),
+                _ => return Err(format!("Unknown xz option {}, expected dict, lc, lp, pb, mode, nice, mf, depth or delta!", key)),
+            }
+        }
+        if options.lc.unwrap_or(3) + options.lp.unwrap_or(0) > 4 {
+            return Err("xz options lc and lp add up to more than 4!".to_string());
+        }
+        Ok(options)
+    }
+}
+
+// Canonical form stored in the archive metadata, e.g. "dict=64MiB,lc=4"
+impl fmt::Display for Options {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let numbers = [("delta", self.delta), ("lc", self.lc), ("lp", self.lp), ("pb", self.pb), ("nice", self.nice_len), ("depth", self.depth)];
+        let mut options: Vec<String> = Vec::new();
+        options.extend(self.dict_size.map(|size| format!("dict={}", format_size(size))));
+        options.extend(numbers.iter().filter_map(|(key, value)| value.map(|value| format!("{}={}", key, value))));
+        options.extend(self.mode.map(|mode| format!("mode={}", mode)));
+        options.extend(self.match_finder.map(|match_finder| format!("mf={}", match_finder)));
+        f.write_str(&options.join(","))
+    }
+}
+
+impl Options {
+    // Compresses input into an xz stream with the filter chain of preset changed by the options,
+    // appending to output
+    pub fn compress(&self, preset: u32, input: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        self.with_filters(preset, |filters| {
+            let start = output.len();
+            output.resize(start + unsafe { lzma_sys::lzma_stream_buffer_bound(input.len()) }, 0);
+            let mut end = start;
+            let ret = unsafe {
+                lzma_sys::lzma_stream_buffer_encode(filters.as_mut_ptr(), lzma_sys::LZMA_CHECK_CRC64, ptr::null(),
+                    input.as_ptr(), input.len(), output.as_mut_ptr(), &mut end, output.len())
+            };
+            output.truncate(end);
+            match ret {
+                lzma_sys::LZMA_OK => Ok(()),
+                lzma_sys::LZMA_MEM_ERROR => Err(io::Error::new(io::ErrorKind::OutOfMemory, "Not enough memory for the xz options!")),
+                lzma_sys::LZMA_OPTIONS_ERROR => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported xz options {}!", self))),
+                ret => Err(io::Error::other(format!("xz compression failed with error {}!", ret))),
+            }
+        })
+    }
+
+    // Bytes of memory the filter chain needs for compressing
+    pub fn memory_usage(&self, preset: u32) -> u64 {
+        self.with_filters(preset, |filters| Ok(unsafe { lzma_sys::lzma_raw_encoder_memusage(filters.as_ptr()) })).unwrap_or(0)
+    }
+
+    fn with_filters<T>(&self, preset: u32, f: impl FnOnce(&mut [lzma_filter]) -> io::Result<T>) -> io::Result<T> {
+        let mut lzma: lzma_options_lzma = unsafe { std::mem::zeroed() };
+        if unsafe { lzma_sys::lzma_lzma_preset(&mut lzma, preset) } != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported xz preset {}!", preset)));
+        }
+        lzma.dict_size = self.dict_size.unwrap_or(lzma.dict_size);
+        lzma.lc = self.lc.unwrap_or(lzma.lc);
+        lzma.lp = self.lp.unwrap_or(lzma.lp);
+        lzma.pb = self.pb.unwrap_or(lzma.pb);
+        lzma.mode = self.mode.map_or(lzma.mode, |mode| value_of(&MODES, mode));
+        lzma.nice_len = self.nice_len.unwrap_or(lzma.nice_len);
+        lzma.mf = self.match_finder.map_or(lzma.mf, |match_finder| value_of(&MATCH_FINDERS, match_finder));
+        lzma.depth = self.depth.unwrap_or(lzma.depth);
+        let mut delta = DeltaOptions { kind: LZMA_DELTA_TYPE_BYTE, dist: self.delta.unwrap_or(1), reserved_int: [0; 8], reserved_ptr: [ptr::null_mut(); 2] };
+        let mut filters: Vec<lzma_filter> = Vec::new();
+        if self.delta.is_some() {
+            filters.push(lzma_filter { id: LZMA_FILTER_DELTA, options: &mut delta as *mut DeltaOptions as *mut c_void });
+        }
+        filters.push(lzma_filter { id: lzma_sys::LZMA_FILTER_LZMA2, options: &mut lzma as *mut lzma_options_lzma as *mut c_void });
+        filters.push(lzma_filter { id: lzma_sys::LZMA_VLI_UNKNOWN, options: ptr::null_mut() });
+        f(&mut filters)
+    }
+}
+
+// Name of names given as value of option
+fn named<T>(names: &[(&'static str, T)], option: &str, value: &str) -> Result<&'static str, String> {
+    let known: Vec<&str> = names.iter().map(|(name, _)| *name).collect();
+    known.iter().copied().find(|name| *name == value).ok_or(format!("xz option {} is invalid, expected one of {}!", option, known.join(", ")))
+}
+
+fn value_of<T: Copy>(names: &[(&str, T)], name: &str) -> T {
+    names.iter().find(|(known, _)| *known == name).unwrap().1
+}
+
+// Exact size in the largest binary unit dividing it, parsed back by units::parse_size
+fn format_size(size: u32) -> String {
+    match size {
+        _ if size.is_multiple_of(1 << 20) => format!("{}MiB", size >> 20),
+        _ if size.is_multiple_of(1 << 10) => format!("{}KiB", size >> 10),
+        _ => size.to_string(),
+    }
+}